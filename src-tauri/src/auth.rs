@@ -1,24 +1,82 @@
 use anyhow::{anyhow, Ok, Result};
-use argon2::password_hash::{rand_core::OsRng, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::password_hash::PasswordVerifier;
+#[cfg(test)]
+use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
 use argon2::{Argon2, PasswordHash};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+#[cfg(test)]
+use crate::encryption::KdfParams;
+
+const VERIFIER_PREFIX: &str = "$lockkey-hkdf$"; // marks verifiers stored in place of PHC strings
+
+/// Encodes a password verifier, split from the same Argon2 output as the key-encryption key,
+/// for storage in place of a password hash.
+///
+/// # Arguments
+/// * `verifier` - The password verifier.
+///
+/// # Returns
+/// * `String` - The encoded verifier.
+pub fn encode_verifier(verifier: &[u8; 32]) -> String {
+    format!("{VERIFIER_PREFIX}{}", BASE64.encode(verifier))
+}
+
+/// Checks whether a stored password hash is a PHC string from before the verifier and the
+/// key-encryption key were derived together, which needs a second Argon2 run to unlock.
+///
+/// # Arguments
+/// * `hash` - The stored password hash.
+///
+/// # Returns
+/// * `bool` - `true` if the hash is a legacy PHC string.
+pub fn is_legacy_hash(hash: &str) -> bool {
+    !hash.starts_with(VERIFIER_PREFIX)
+}
+
+/// Compares a derived password verifier with a stored one in constant time.
+///
+/// # Arguments
+/// * `verifier` - The verifier derived from the password that is being checked.
+/// * `hash` - The stored verifier, as encoded by `encode_verifier`.
+///
+/// # Returns
+/// * `Result<bool>` - `true` if the verifiers match, or an error if the stored one is malformed.
+pub fn check_verifier(verifier: &[u8; 32], hash: &str) -> Result<bool> {
+    let stored = hash
+        .strip_prefix(VERIFIER_PREFIX)
+        .and_then(|encoded| BASE64.decode(encoded).ok())
+        .filter(|stored| stored.len() == verifier.len())
+        .ok_or_else(|| anyhow!("the stored password verifier is malformed"))?;
+    let diff = verifier
+        .iter()
+        .zip(&stored)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    Ok(diff == 0)
+}
 
 /// Hashes the provided password using the Argon2 hashing algorithm.
+/// Accounts no longer store such hashes; this creates legacy accounts in tests.
 ///
 /// # Arguments
 /// * `passwd` - A string slice representing the password to be hashed.
+/// * `params` - The Argon2 cost parameters to hash with.
 ///
 /// # Returns
 /// * `Result<String>` - A result containing the hashed password as a PHC string if successful, or an error if the hashing process fails.
-pub fn hash_password(passwd: &str) -> Result<String> {
+#[cfg(test)]
+pub fn hash_password(passwd: &str, params: &KdfParams) -> Result<String> {
     let salt = SaltString::generate(&mut OsRng);
-    let hash = Argon2::default()
+    let hash = params
+        .argon2()?
         .hash_password(passwd.as_bytes(), &salt)
         .map_err(|_| anyhow!("an error occured while trying to generate a hash for the password"))?
         .to_string();
     Ok(hash)
 }
 
-/// Verifies whether the provided password matches the hashed password.
+/// Verifies whether the provided password matches the hashed password of a legacy account.
+/// The Argon2 parameters are read from the PHC string itself.
 ///
 /// # Arguments
 /// * `passwd` - A string slice representing the plain-text password to verify.
@@ -42,7 +100,40 @@ mod test {
     #[test]
     fn can_hash_and_verify_passwd() {
         let passwd = "test_pass".to_string();
-        let hash = hash_password(&passwd).unwrap();
+        let hash = hash_password(&passwd, &KdfParams::default()).unwrap();
         assert!(verify_passwd(&passwd, &hash).unwrap());
     }
+
+    /// Test case for ensuring that hashes made with custom parameters verify and record them.
+    #[test]
+    fn can_verify_passwd_hashed_with_custom_params() {
+        let passwd = "test_pass".to_string();
+        let params = KdfParams {
+            m_cost: 8 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        };
+        let hash = hash_password(&passwd, &params).unwrap();
+
+        assert!(hash.contains("m=8192,t=3,p=1"));
+        assert!(verify_passwd(&passwd, &hash).unwrap());
+        assert!(!verify_passwd("wrong_pass", &hash).unwrap());
+    }
+
+    /// Test case for ensuring that verifiers round trip, only match themselves, and are told
+    /// apart from legacy PHC strings.
+    #[test]
+    fn can_encode_and_check_verifier() {
+        let verifier = [7u8; 32];
+        let hash = encode_verifier(&verifier);
+
+        assert!(!is_legacy_hash(&hash));
+        assert!(check_verifier(&verifier, &hash).unwrap());
+        assert!(!check_verifier(&[8u8; 32], &hash).unwrap());
+
+        let legacy = hash_password("test_pass", &KdfParams::default()).unwrap();
+        assert!(is_legacy_hash(&legacy));
+        assert!(check_verifier(&verifier, &legacy).is_err());
+        assert!(check_verifier(&verifier, &format!("{VERIFIER_PREFIX}AAAA")).is_err());
+    }
 }
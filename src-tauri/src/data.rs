@@ -1,16 +1,124 @@
+use std::cmp::Ordering;
+
 use anyhow::{anyhow, Result};
+use rand::Rng;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use sha2::{Digest, Sha256};
 
-use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use crate::encryption::{EncryptionPolicy, KdfParams};
+use crate::envelope::{CipherId, PaddingId};
 
 /// Struct representing a connection to the SQLite database.
 pub struct DbConn {
     conn: Option<Connection>,
 }
 
-/// Struct used for retrieving labels from the database.
-pub struct RetrieveLabelsQueryResult {
+/// Struct representing a secret as stored in the database. The label and kind are only
+/// stored encrypted in `meta`, and `label_index` is a keyed hash of the label used to look
+/// the secret up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecretRow {
+    pub id: i64,
+    pub label_index: Vec<u8>,
+    pub meta: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Struct representing when a secret was stored, last edited and last read, in seconds since
+/// the Unix epoch. Secrets stored before timestamps were recorded have none.
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize)]
+pub struct SecretTimestamps {
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+/// Struct representing the encrypted metadata of a secret, its folder and its timestamps, as
+/// listed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelRow {
+    pub id: i64,
+    pub meta: Vec<u8>,
+    pub folder_id: Option<i64>,
+    pub timestamps: SecretTimestamps,
+}
+
+/// Struct representing a secret in the trash, with its encrypted metadata and when it was
+/// deleted, in seconds since the Unix epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrashRow {
+    pub id: i64,
+    pub meta: Vec<u8>,
+    pub deleted_at: u64,
+}
+
+/// Struct representing a folder, whose name is stored encrypted and bound to its row. Folders
+/// without a parent are at the root of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderRow {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub name: Vec<u8>,
+}
+
+/// Struct representing a tag, whose name is stored encrypted and bound to its row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagRow {
+    pub id: i64,
+    pub name: Vec<u8>,
+}
+
+/// What happens to the subfolders and secrets of a folder when it is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderDeletion {
+    /// The subfolders are deleted along with the folder and the secrets moved to the trash.
+    Cascade,
+    /// The subfolders and secrets are moved to the parent of the folder.
+    MoveToParent,
+}
+
+/// Struct representing an earlier version of a secret, kept when the secret was edited. The
+/// metadata and data are re-encrypted bound to the version's row, except for versions kept
+/// before that, which are still bound to the secret's row and have `legacy_context` set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecretVersionRow {
+    pub id: i64,
+    pub secret_id: i64,
+    pub meta: Vec<u8>,
+    pub data: Vec<u8>,
+    pub replaced_at: u64,
+    pub legacy_context: bool,
+}
+
+/// Struct representing a secret stored before labels and kinds were encrypted, which still
+/// has to be migrated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacySecretRow {
+    pub id: i64,
     pub kind: String,
     pub label: String,
+    pub data: Vec<u8>,
+}
+
+/// Struct representing what a user's master password and key file unlock: the password hash,
+/// the salt and Argon2 parameters of the key-encryption key, and the vault key wrapped by it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub passwd_hash: String,
+    pub enc_salt: Vec<u8>,
+    pub kdf_params: KdfParams,
+    pub wrapped_key: Vec<u8>,
+    pub key_file: bool,
+}
+
+/// Struct representing the failed login attempts of a user since their last successful login,
+/// and the number of failures after which they are locked out, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LoginAttempts {
+    pub failed: u32,
+    pub last_failed_at: u64,
+    pub lockout_after: Option<u32>,
 }
 
 /// Enum representing the kind of secret (either password or text).
@@ -51,6 +159,47 @@ impl Kind {
     }
 }
 
+/// Schema of the `secrets` table. The legacy columns are only set for secrets stored before
+/// labels and kinds were encrypted, in which case `label_index` and `meta` are NULL.
+const SECRETS_TABLE: &str = "CREATE TABLE IF NOT EXISTS secrets (
+    id           INTEGER PRIMARY KEY,
+    user_id      INTEGER,
+    label_index  BLOB,
+    meta         BLOB,
+    data         BLOB NOT NULL CHECK(length(data) > 3),
+    legacy_kind  TEXT,
+    legacy_label TEXT,
+    FOREIGN KEY(user_id) REFERENCES users(user_id),
+    UNIQUE(user_id, label_index)
+);";
+
+/// A schema migration, run inside the transaction that also records it as applied.
+type Migration = fn(&Connection) -> Result<()>;
+
+/// Schema migrations in the order they are applied. The `user_version` of a database is the
+/// number of migrations it has run, so migrations must only ever be appended to this list.
+const MIGRATIONS: &[Migration] = &[
+    create_base_schema,
+    create_meta_table,
+    create_secret_versions_table,
+    add_secret_timestamps,
+    create_folders_and_tags,
+    add_trash,
+    create_unknown_logins_table,
+    add_version_legacy_context,
+    make_ids_autoincrement,
+];
+
+/// Selects as `subtree` the folder `?2` of user `?1` and all the folders below it.
+const FOLDER_SUBTREE: &str = "WITH RECURSIVE subtree(id) AS (
+    SELECT id FROM folders WHERE user_id = ?1 AND id = ?2
+    UNION SELECT f.id FROM folders f JOIN subtree s ON f.parent_id = s.id
+)";
+
+/// Deletes the tags of user `?1` that no secret has anymore.
+const DELETE_UNUSED_TAGS: &str = "DELETE FROM tags WHERE user_id = ?1
+    AND id NOT IN (SELECT tag_id FROM secret_tags);";
+
 impl DbConn {
     /// Creates a new database connection and initializes the required tables.
     ///
@@ -60,12 +209,13 @@ impl DbConn {
     ///
     /// # Errors
     ///
-    /// Returns an error if the connection cannot be established or table creation fails.
+    /// Returns an error if the connection cannot be established, the schema cannot be brought
+    /// up to date, or the database was created by a newer version of Lockkey.
     pub fn new(path: &str) -> Result<DbConn> {
         let mut conn = DbConn {
             conn: Some(Connection::open(path)?),
         };
-        conn.create_table()?;
+        conn.migrate()?;
         Ok(conn)
     }
 
@@ -93,41 +243,36 @@ impl DbConn {
         }
     }
 
-    /// Creates the required tables (`users` and `secrets`) in the database if they do not already exist.
+    /// Brings the schema of the database up to date by running the migrations it has not run
+    /// yet, each in its own transaction.
     ///
     /// # Errors
     ///
-    /// Returns an error if the table creation queries fail.
-    fn create_table(&mut self) -> Result<()> {
-        let conn = self.start_transaction()?;
+    /// Returns an error if a migration fails, or if the database was created by a newer
+    /// version of Lockkey, in which case it is left untouched.
+    fn migrate(&mut self) -> Result<()> {
+        match &mut self.conn {
+            Some(conn) => run_migrations(conn, MIGRATIONS),
+            None => Err(anyhow!("does not have connection to the dabase")),
+        }
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                user_id     INTEGER PRIMARY KEY,
-                username    TEXT UNIQUE NOT NULL CHECK(length(username) <= 24),
-                passwd_hash TEXT NOT NULL,
-                enc_salt    BLOB NOT NULL
-            );
-            ",
-            (),
-        )?;
+    /// Retrieves a value from the `meta` table.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails. Returns `None` if the value is not set.
+    pub fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.get_conn()?;
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS secrets (
-                id          INTEGER PRIMARY KEY,
-                user_id     INTEGER,
-                kind        TEXT NOT NULL CHECK(kind IN ('text', 'password')),
-                label       TEXT NOT NULL CHECK(length(label) >= 3 AND length(label) <= 32),
-                data        BLOB NOT NULL CHECK(length(data) > 3),
-                FOREIGN KEY(user_id) REFERENCES users(user_id)
-            );
-            CREATE INDEX idx_secrets_user_id ON secrets (user_id);
-            ",
-            (),
-        )?;
+        let mut stmt = conn.prepare("SELECT value FROM meta WHERE key = ?1;")?;
+        let value: Option<String> = stmt.query_row([key], |row| row.get(0)).optional()?;
 
-        conn.commit()?;
-        Ok(())
+        Ok(value)
     }
 
     /// Checks if a user with the given username already exists in the database.
@@ -148,27 +293,52 @@ impl DbConn {
         Ok(count > 0)
     }
 
-    /// Creates a new user in the database with the provided username, password hash, and encryption salt.
+    /// Creates a new user in the database with the provided username, credentials and
+    /// encryption policy.
     ///
     /// # Arguments
     ///
     /// * `username` - A string slice representing the username.
-    /// * `passwd_hash` - A string slice representing the password hash.
-    /// * `enc_salt` - A byte slice representing the encryption salt.
+    /// * `credentials` - The password hash, encryption salt, key derivation parameters and
+    ///   wrapped vault key of the user, and whether a key file is required.
+    /// * `policy` - The cipher and padding the user's secrets are encrypted with.
     ///
     /// # Errors
     ///
     /// Returns an error if the user already exists or if the query fails.
-    pub fn create_user(&self, username: &str, passwd_hash: &str, enc_salt: &[u8]) -> Result<()> {
+    pub fn create_user(
+        &self,
+        username: &str,
+        credentials: &Credentials,
+        policy: EncryptionPolicy,
+    ) -> Result<()> {
         let conn = self.get_conn()?;
 
         if self.check_user_already_exists(username)? {
             return Err(anyhow!("The username, {username:?}, is already taken"));
         }
+        conn.execute(
+            "DELETE FROM unknown_logins WHERE username_hash = ?1;",
+            [username_hash(username)],
+        )?;
 
         conn.execute(
-            "INSERT INTO users (username, passwd_hash, enc_salt) VALUES (?1, ?2, ?3)",
-            (username, passwd_hash, enc_salt),
+            "INSERT INTO users
+            (username, passwd_hash, enc_salt, kdf_m_cost, kdf_t_cost, kdf_p_cost, wrapped_key,
+            key_file, cipher, padding)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                username,
+                credentials.passwd_hash,
+                credentials.enc_salt,
+                credentials.kdf_params.m_cost,
+                credentials.kdf_params.t_cost,
+                credentials.kdf_params.p_cost,
+                credentials.wrapped_key,
+                credentials.key_file,
+                policy.cipher.to_byte(),
+                policy.padding.to_byte()
+            ],
         )?;
 
         Ok(())
@@ -247,14 +417,60 @@ impl DbConn {
         Ok(enc_salt)
     }
 
-    pub fn delete_user(&self, username: &str) -> Result<()> {
+    /// Retrieves the key derivation parameters associated with the given username.
+    /// Users created before the parameters were stored get the `Argon2::default()` ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - A string slice representing the username.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_user_kdf_params(&self, username: &str) -> Result<Option<KdfParams>> {
         let conn = self.get_conn()?;
-        let mut stmt = conn.prepare("DELETE FROM users WHERE username = ?1")?;
-        stmt.execute([username])?;
-        Ok(())
+
+        let mut stmt = conn
+            .prepare("SELECT kdf_m_cost, kdf_t_cost, kdf_p_cost FROM users WHERE username = ?1;")?;
+        let params = stmt
+            .query_row([username], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()?;
+
+        Ok(params.map(kdf_params))
     }
 
-    /// Deletes a user from the database based on the given username.
+    /// Picks the key derivation parameters of one user, chosen from a hash of the given
+    /// username so that the same username always gets the parameters of the same user.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - A string slice representing the username, usually one with no account.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails. Returns `None` if there are no users yet.
+    pub fn pick_kdf_params(&self, username: &str) -> Result<Option<KdfParams>> {
+        let conn = self.get_conn()?;
+
+        let count: u64 = conn.query_row("SELECT COUNT(*) FROM users;", [], |row| row.get(0))?;
+        if count == 0 {
+            return Ok(None);
+        }
+        let hash = username_hash(username);
+        let offset = u64::from_le_bytes(hash[..8].try_into()?) % count;
+
+        let mut stmt = conn.prepare(
+            "SELECT kdf_m_cost, kdf_t_cost, kdf_p_cost FROM users
+            ORDER BY user_id LIMIT 1 OFFSET ?1;",
+        )?;
+        let params = stmt.query_row([offset], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+        Ok(Some(kdf_params(params)))
+    }
+
+    /// Retrieves the wrapped vault key associated with the given username.
     ///
     /// # Arguments
     ///
@@ -262,232 +478,2514 @@ impl DbConn {
     ///
     /// # Errors
     ///
-    /// Returns an error if the deletion fails.
-    fn check_if_secret_label_exists(&self, user_id: i64, label: &str) -> Result<bool> {
+    /// Returns an error if the query fails. Returns `None` if the user does not exist or was
+    /// created before vault keys were introduced.
+    pub fn get_user_wrapped_key(&self, username: &str) -> Result<Option<Vec<u8>>> {
         let conn = self.get_conn()?;
 
-        let mut stmt =
-            conn.prepare("SELECT COUNT(*) FROM secrets WHERE user_id = ?1 AND label = ?2;")?;
-        let count: i64 = stmt.query_row(params![user_id, label], |row| row.get(0))?;
+        let mut stmt = conn.prepare("SELECT wrapped_key FROM users WHERE username = ?1;")?;
+        let wrapped_key: Option<Option<Vec<u8>>> =
+            stmt.query_row([username], |row| row.get(0)).optional()?;
 
-        Ok(count > 0)
+        Ok(wrapped_key.flatten())
     }
 
-    /// Checks if a secret with the given label already exists for the specified user.
+    /// Retrieves the cipher and padding a user's secrets are encrypted with.
+    /// Users created before the cipher was selectable get AES-256-GCM, the only cipher then.
+    /// Users created before secrets were padded get Padmé padding, so that their secrets are
+    /// padded when they are next re-encrypted.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The user ID.
-    /// * `label` - A string slice representing the label of the secret.
     ///
     /// # Errors
     ///
-    /// Returns an error if the query fails.
-    pub fn store_secret(&self, user_id: i64, kind: Kind, label: &str, data: Vec<u8>) -> Result<()> {
+    /// Returns an error if the query fails, the user does not exist or the cipher or padding
+    /// is unknown.
+    pub fn get_user_policy(&self, user_id: i64) -> Result<EncryptionPolicy> {
         let conn = self.get_conn()?;
 
-        if self.check_if_secret_label_exists(user_id, label)? {
-            return Err(anyhow!("A secret with the same label already exists"));
-        }
+        let mut stmt = conn.prepare("SELECT cipher, padding FROM users WHERE user_id = ?1;")?;
+        let (cipher, padding): (Option<u8>, Option<u8>) =
+            stmt.query_row([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(EncryptionPolicy {
+            cipher: match cipher {
+                Some(cipher) => CipherId::from_byte(cipher)?,
+                None => CipherId::Aes256Gcm,
+            },
+            padding: match padding {
+                Some(padding) => PaddingId::from_byte(padding)?,
+                None => PaddingId::Padme,
+            },
+        })
+    }
 
-        let mut stmt = conn
-            .prepare("INSERT INTO secrets (user_id, kind, label, data) VALUES (?1, ?2, ?3, ?4);")?;
-        stmt.execute(params![user_id, kind.to_str(), label, data])?;
+    /// Changes the cipher and padding of a user together with all their secrets re-encrypted
+    /// with them, in a single transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `policy` - The new cipher and padding.
+    /// * `secrets` - The user's secrets re-encrypted with the new policy.
+    /// * `versions` - The versions of the user's secrets re-encrypted with the new policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any query fails, in which case nothing is changed.
+    pub fn set_user_policy(
+        &mut self,
+        user_id: i64,
+        policy: EncryptionPolicy,
+        secrets: Vec<SecretRow>,
+        versions: Vec<SecretVersionRow>,
+    ) -> Result<()> {
+        let tx = self.start_transaction()?;
+
+        tx.execute(
+            "UPDATE users SET cipher = ?1, padding = ?2 WHERE user_id = ?3;",
+            params![policy.cipher.to_byte(), policy.padding.to_byte(), user_id],
+        )?;
+        update_secrets(&tx, user_id, secrets)?;
+        update_secret_versions(&tx, user_id, versions)?;
 
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn edit_secret(
+    /// Retrieves the recovery salt and the vault key wrapped by the recovery key of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - A string slice representing the username.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails. Returns `None` if the user does not exist or has
+    /// no recovery key.
+    pub fn get_user_recovery_key(&self, username: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT recovery_salt, recovery_wrapped_key FROM users WHERE username = ?1;",
+        )?;
+        let recovery = stmt
+            .query_row([username], |row| {
+                let salt: Option<Vec<u8>> = row.get(0)?;
+                let wrapped_key: Option<Vec<u8>> = row.get(1)?;
+                Ok(salt.zip(wrapped_key))
+            })
+            .optional()?;
+
+        Ok(recovery.flatten())
+    }
+
+    /// Stores the recovery salt and the vault key wrapped by the recovery key of a user,
+    /// replacing any previous recovery key.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `salt` - The recovery salt.
+    /// * `wrapped_key` - The vault key wrapped by the recovery key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn set_user_recovery_key(
         &self,
         user_id: i64,
-        label: &str,
-        new_label: &str,
-        new_data: Vec<u8>,
+        salt: &[u8],
+        wrapped_key: &[u8],
     ) -> Result<()> {
         let conn = self.get_conn()?;
 
-        let mut stmt = conn.prepare(
-            "UPDATE OR ABORT secrets SET label = ?1, data = ?2 WHERE user_id = ?3 AND label = ?4;",
+        conn.execute(
+            "UPDATE users SET recovery_salt = ?1, recovery_wrapped_key = ?2 WHERE user_id = ?3;",
+            params![salt, wrapped_key, user_id],
         )?;
-        stmt.execute(params![new_label, new_data, user_id, label])?;
 
         Ok(())
     }
 
-    pub fn get_labels(&self, user_id: i64) -> Result<Vec<RetrieveLabelsQueryResult>> {
+    /// Removes the recovery key of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn remove_user_recovery_key(&self, user_id: i64) -> Result<()> {
         let conn = self.get_conn()?;
 
-        let mut stmt = conn.prepare("SELECT kind, label FROM secrets WHERE user_id = ?1")?;
-        let rows = stmt.query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        conn.execute(
+            "UPDATE users SET recovery_salt = NULL, recovery_wrapped_key = NULL WHERE user_id = ?1;",
+            [user_id],
+        )?;
 
-        let mut labels: Vec<RetrieveLabelsQueryResult> = Vec::new();
-        for row in rows {
-            match row {
-                Ok((kind, label)) => labels.push(RetrieveLabelsQueryResult { kind, label }),
-                _ => (),
-            }
-        }
-        Ok(labels)
+        Ok(())
     }
 
-    pub fn get_secret(&self, user_id: i64, label: &str) -> Result<Option<(String, Vec<u8>)>> {
+    /// Retrieves the failed login attempts of a user and their lockout threshold.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the user does not exist.
+    pub fn get_login_attempts(&self, user_id: i64) -> Result<LoginAttempts> {
         let conn = self.get_conn()?;
 
-        let mut stmt =
-            conn.prepare("SELECT kind, data FROM secrets WHERE user_id = ?1 AND label = ?2")?;
-
-        let data = stmt
-            .query_row([user_id.to_string(), label.to_string()], |row| {
-                Ok((row.get(0)?, row.get(1)?))
-            })
-            .optional()?;
-        Ok(data)
+        let mut stmt = conn.prepare(
+            "SELECT failed_logins, last_failed_login, lockout_after FROM users WHERE user_id = ?1;",
+        )?;
+        let (failed, last_failed_at, lockout_after): (Option<u32>, Option<u64>, Option<u32>) =
+            stmt.query_row([user_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+        Ok(LoginAttempts {
+            failed: failed.unwrap_or(0),
+            last_failed_at: last_failed_at.unwrap_or(0),
+            lockout_after,
+        })
     }
 
-    pub fn delete_secret(&self, user_id: i64, label: &str) -> Result<()> {
+    /// Records a failed login attempt of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `at` - When the attempt failed, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn record_failed_login(&self, user_id: i64, at: u64) -> Result<()> {
         let conn = self.get_conn()?;
 
-        let mut stmt = conn.prepare("DELETE FROM secrets WHERE user_id == ?1 AND label = ?2;")?;
-        stmt.execute([user_id.to_string(), label.to_string()])?;
-        Ok(())
-    }
+        conn.execute(
+            "UPDATE users SET failed_logins = COALESCE(failed_logins, 0) + 1,
+            last_failed_login = ?1 WHERE user_id = ?2;",
+            params![at, user_id],
+        )?;
 
-    pub fn close(&mut self) -> Result<()> {
-        if let Some(conn) = self.conn.take() {
-            conn.close().expect("should close db connection");
-        }
         Ok(())
     }
-}
 
-impl Drop for DbConn {
-    fn drop(&mut self) {
-        if let Some(conn) = self.conn.take() {
-            conn.close().expect("should close db connection");
-        }
-    }
-}
+    /// Retrieves the failed login attempts made with a username that has no account. The
+    /// username is only stored hashed.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The unknown username.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_unknown_login_attempts(&self, username: &str) -> Result<LoginAttempts> {
+        let conn = self.get_conn()?;
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::{
-        collections::HashMap,
-        fs,
-        path::PathBuf,
-        sync::atomic::{AtomicUsize, Ordering},
-        thread,
-        time::Duration,
-    };
+        let attempts = conn
+            .query_row(
+                "SELECT failed_logins, last_failed_login FROM unknown_logins
+                WHERE username_hash = ?1;",
+                [username_hash(username)],
+                |row| {
+                    Ok(LoginAttempts {
+                        failed: row.get(0)?,
+                        last_failed_at: row.get(1)?,
+                        lockout_after: None,
+                    })
+                },
+            )
+            .optional()?;
 
-    struct TestDb {
-        path: PathBuf,
+        Ok(attempts.unwrap_or_default())
     }
 
-    impl TestDb {
-        fn new() -> Self {
-            static TEST_ID: AtomicUsize = AtomicUsize::new(0);
-            let test_id = TEST_ID.fetch_add(1, Ordering::SeqCst);
-            let mut path = std::env::temp_dir();
-            path.push(format!("lockkey_data_test_db_{test_id:?}.sqlite"));
-            TestDb { path }
-        }
-
-        fn get_path(&self) -> &str {
-            &self.path.to_str().unwrap()
-        }
-    }
+    /// Records a failed login attempt made with a username that has no account.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The unknown username.
+    /// * `at` - When the attempt failed, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn record_unknown_failed_login(&self, username: &str, at: u64) -> Result<()> {
+        let conn = self.get_conn()?;
 
-    impl Drop for TestDb {
-        fn drop(&mut self) {
-            let mut attempts = 0;
-            while attempts < 5 {
-                if fs::remove_file(&self.path).is_ok() {
-                    return;
-                }
+        conn.execute(
+            "INSERT INTO unknown_logins (username_hash, failed_logins, last_failed_login)
+            VALUES (?1, 1, ?2)
+            ON CONFLICT(username_hash) DO UPDATE
+            SET failed_logins = failed_logins + 1, last_failed_login = ?2;",
+            params![username_hash(username), at],
+        )?;
 
-                attempts += 1;
-                thread::sleep(Duration::from_millis(100));
-            }
-            fs::remove_file(&self.path).expect("should delete the test database file");
-        }
+        Ok(())
     }
 
-    #[test]
-    fn can_create_db_connection() {
-        let test_db = TestDb::new();
+    /// Clears the failed login attempts of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn reset_failed_logins(&self, user_id: i64) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE users SET failed_logins = NULL, last_failed_login = NULL WHERE user_id = ?1;",
+            [user_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Sets the number of failed login attempts after which a user is locked out, or disables
+    /// the lockout.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `lockout_after` - The lockout threshold, or `None` to never lock the user out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn set_lockout_threshold(&self, user_id: i64, lockout_after: Option<u32>) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE users SET lockout_after = ?1 WHERE user_id = ?2;",
+            params![lockout_after, user_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Retrieves the encrypted two-factor state of a user, if they enrolled.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the user does not exist.
+    pub fn get_user_totp(&self, user_id: i64) -> Result<Option<Vec<u8>>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare("SELECT totp FROM users WHERE user_id = ?1;")?;
+        let totp: Option<Vec<u8>> = stmt.query_row([user_id], |row| row.get(0))?;
+
+        Ok(totp)
+    }
+
+    /// Stores the encrypted two-factor state of a user, or disables two-factor
+    /// authentication.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `totp` - The encrypted two-factor state, or `None` to disable it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn set_user_totp(&self, user_id: i64, totp: Option<&[u8]>) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE users SET totp = ?1 WHERE user_id = ?2;",
+            params![totp, user_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Retrieves the minimum strength score a user's new master passwords must reach, if they
+    /// set one.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the user does not exist.
+    pub fn get_min_password_score(&self, user_id: i64) -> Result<Option<u8>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare("SELECT min_password_score FROM users WHERE user_id = ?1;")?;
+        let min_score: Option<u8> = stmt.query_row([user_id], |row| row.get(0))?;
+
+        Ok(min_score)
+    }
+
+    /// Sets the minimum strength score a user's new master passwords must reach.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `min_score` - The minimum score.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn set_min_password_score(&self, user_id: i64, min_score: u8) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE users SET min_password_score = ?1 WHERE user_id = ?2;",
+            params![min_score, user_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Replaces the password hash, encryption salt, key derivation parameters, wrapped vault
+    /// key and key file requirement of a user in a single update.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `credentials` - The new credentials.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn update_user_credentials(&self, user_id: i64, credentials: &Credentials) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE users SET passwd_hash = ?1, enc_salt = ?2, kdf_m_cost = ?3, kdf_t_cost = ?4,
+            kdf_p_cost = ?5, wrapped_key = ?6, key_file = ?7 WHERE user_id = ?8;",
+            params![
+                credentials.passwd_hash,
+                credentials.enc_salt,
+                credentials.kdf_params.m_cost,
+                credentials.kdf_params.t_cost,
+                credentials.kdf_params.p_cost,
+                credentials.wrapped_key,
+                credentials.key_file,
+                user_id
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Stores a newly generated wrapped vault key for a user together with all their secrets
+    /// re-encrypted under it, in a single transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `wrapped_key` - The vault key wrapped by the password-derived key.
+    /// * `secrets` - All the user's secrets re-encrypted under the vault key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any query fails, in which case nothing is changed.
+    pub fn set_user_vault_key(
+        &mut self,
+        user_id: i64,
+        wrapped_key: &[u8],
+        secrets: Vec<SecretRow>,
+    ) -> Result<()> {
+        let tx = self.start_transaction()?;
+
+        tx.execute(
+            "UPDATE users SET wrapped_key = ?1 WHERE user_id = ?2;",
+            params![wrapped_key, user_id],
+        )?;
+        update_secrets(&tx, user_id, secrets)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Deletes a user from the database based on the given username.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - A string slice representing the username.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the deletion fails.
+    pub fn delete_user(&self, username: &str) -> Result<()> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare("DELETE FROM users WHERE username = ?1")?;
+        stmt.execute([username])?;
+        Ok(())
+    }
+
+    /// Checks if a secret with the given label index already exists for the specified user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `label_index` - The keyed hash of the secret's label.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    fn check_if_secret_label_exists(&self, user_id: i64, label_index: &[u8]) -> Result<bool> {
+        let conn = self.get_conn()?;
+
+        let mut stmt =
+            conn.prepare("SELECT COUNT(*) FROM secrets WHERE user_id = ?1 AND label_index = ?2;")?;
+        let count: i64 = stmt.query_row(params![user_id, label_index], |row| row.get(0))?;
+
+        Ok(count > 0)
+    }
+
+    /// Stores a new secret. The ID of the new row is allocated first and passed to `encrypt`,
+    /// so that the encrypted metadata and data can be bound to the row they are stored in.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `label_index` - The keyed hash of the secret's label.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    /// * `encrypt` - Produces the encrypted metadata and data given the ID of the new row.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the label already exists, `encrypt` fails or a query fails.
+    pub fn store_secret(
+        &self,
+        user_id: i64,
+        label_index: &[u8],
+        now: u64,
+        encrypt: impl FnOnce(i64) -> Result<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<i64> {
+        let conn = self.get_conn()?;
+
+        if self.check_if_secret_label_exists(user_id, label_index)? {
+            return Err(anyhow!("A secret with the same label already exists"));
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        let id = next_id(&tx, "secrets")?;
+        let (meta, data) = encrypt(id)?;
+        tx.execute(
+            "INSERT INTO secrets (id, user_id, label_index, meta, data, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6);",
+            params![id, user_id, label_index, meta, data, now],
+        )?;
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    /// Replaces the label index, encrypted metadata and data of an existing secret, and
+    /// records when it was edited. The replaced secret is passed to `archive` together with
+    /// the ID of the new version row, and what it returns is kept as the newest version of the
+    /// secret, so that the version can be bound to its own row. Only the `history_limit`
+    /// newest versions are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret` - The secret with its ID and new values.
+    /// * `history_limit` - The number of earlier versions to keep, 0 to keep none.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    /// * `archive` - Produces the encrypted metadata and data of the version given its ID and
+    ///   the replaced secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the new label index is already used by another secret, `archive`
+    /// fails or a query fails, in which case nothing is changed.
+    pub fn edit_secret(
+        &self,
+        user_id: i64,
+        secret: SecretRow,
+        history_limit: u32,
+        now: u64,
+        archive: impl FnOnce(i64, SecretRow) -> Result<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        let replaced = tx
+            .query_row(
+                "SELECT id, label_index, meta, data FROM secrets
+                WHERE user_id = ?1 AND id = ?2 AND meta IS NOT NULL AND deleted_at IS NULL;",
+                params![user_id, secret.id],
+                secret_row,
+            )
+            .optional()?;
+        if let Some(replaced) = replaced {
+            let version_id = next_id(&tx, "secret_versions")?;
+            let (meta, data) = archive(version_id, replaced)?;
+            tx.execute(
+                "INSERT INTO secret_versions (id, secret_id, meta, data, replaced_at)
+                VALUES (?1, ?2, ?3, ?4, ?5);",
+                params![version_id, secret.id, meta, data, now],
+            )?;
+        }
+        tx.execute(
+            "UPDATE OR ABORT secrets SET label_index = ?1, meta = ?2, data = ?3, updated_at = ?4
+            WHERE user_id = ?5 AND id = ?6;",
+            params![
+                secret.label_index,
+                secret.meta,
+                secret.data,
+                now,
+                user_id,
+                secret.id
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM secret_versions WHERE secret_id = ?1 AND id NOT IN (
+                SELECT id FROM secret_versions WHERE secret_id = ?1 ORDER BY id DESC LIMIT ?2
+            );",
+            params![secret.id, history_limit],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the earlier versions of a secret, newest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_secret_versions(
+        &self,
+        user_id: i64,
+        secret_id: i64,
+    ) -> Result<Vec<SecretVersionRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT v.id, v.secret_id, v.meta, v.data, v.replaced_at, v.legacy_context
+            FROM secret_versions v JOIN secrets s ON s.id = v.secret_id
+            WHERE s.user_id = ?1 AND v.secret_id = ?2
+            ORDER BY v.id DESC;",
+        )?;
+        let rows = stmt.query_map(params![user_id, secret_id], secret_version_row)?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves an earlier version of a secret by its ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    /// * `version_id` - The ID of the version.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails. Returns `None` if the secret has no such version.
+    pub fn get_secret_version(
+        &self,
+        user_id: i64,
+        secret_id: i64,
+        version_id: i64,
+    ) -> Result<Option<SecretVersionRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT v.id, v.secret_id, v.meta, v.data, v.replaced_at, v.legacy_context
+            FROM secret_versions v JOIN secrets s ON s.id = v.secret_id
+            WHERE s.user_id = ?1 AND v.secret_id = ?2 AND v.id = ?3;",
+        )?;
+        let version = stmt
+            .query_row(params![user_id, secret_id, version_id], secret_version_row)
+            .optional()?;
+
+        Ok(version)
+    }
+
+    /// Retrieves the number of earlier versions kept for each secret of a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the user does not exist. Returns `None` if
+    /// the user never set it.
+    pub fn get_history_limit(&self, user_id: i64) -> Result<Option<u32>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare("SELECT history_limit FROM users WHERE user_id = ?1;")?;
+        let history_limit: Option<u32> = stmt.query_row([user_id], |row| row.get(0))?;
+
+        Ok(history_limit)
+    }
+
+    /// Sets the number of earlier versions kept for each secret of a user, and deletes the
+    /// versions past it.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `history_limit` - The number of versions to keep, 0 to keep none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a query fails, in which case nothing is changed.
+    pub fn set_history_limit(&self, user_id: i64, history_limit: u32) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE users SET history_limit = ?1 WHERE user_id = ?2;",
+            params![history_limit, user_id],
+        )?;
+        tx.execute(
+            "DELETE FROM secret_versions WHERE id IN (
+                SELECT v.id FROM secret_versions v JOIN secrets s ON s.id = v.secret_id
+                WHERE s.user_id = ?1 AND (
+                    SELECT COUNT(*) FROM secret_versions w
+                    WHERE w.secret_id = v.secret_id AND w.id > v.id
+                ) >= ?2
+            );",
+            params![user_id, history_limit],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Replaces several secrets and versions in a single transaction, including secrets that
+    /// are still stored with plaintext legacy labels and versions bound to their secret's row.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secrets` - The secrets with their IDs and new values.
+    /// * `versions` - The versions with their IDs and new values.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any query fails, in which case nothing is changed.
+    pub fn update_secrets(
+        &mut self,
+        user_id: i64,
+        secrets: Vec<SecretRow>,
+        versions: Vec<SecretVersionRow>,
+    ) -> Result<()> {
+        let tx = self.start_transaction()?;
+        update_secrets(&tx, user_id, secrets)?;
+        update_secret_versions(&tx, user_id, versions)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Retrieves the IDs, encrypted metadata, folders and timestamps of all secrets belonging
+    /// to a user, except those in the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_labels(&self, user_id: i64) -> Result<Vec<LabelRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, meta, folder_id, created_at, updated_at, last_used_at FROM secrets
+            WHERE user_id = ?1 AND meta IS NOT NULL AND deleted_at IS NULL",
+        )?;
+        let rows = stmt.query_map([user_id], |row| {
+            Ok(LabelRow {
+                id: row.get(0)?,
+                meta: row.get(1)?,
+                folder_id: row.get(2)?,
+                timestamps: SecretTimestamps {
+                    created_at: row.get(3)?,
+                    updated_at: row.get(4)?,
+                    last_used_at: row.get(5)?,
+                },
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Records that a secret was read, for instance to be shown or copied.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn record_secret_use(&self, user_id: i64, secret_id: i64, now: u64) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE secrets SET last_used_at = ?1 WHERE user_id = ?2 AND id = ?3;",
+            params![now, user_id, secret_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Creates a folder. The ID of the new row is allocated first and passed to `encrypt`, so
+    /// that the encrypted name can be bound to the row it is stored in.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `parent_id` - The folder to create it in, `None` for the root.
+    /// * `encrypt` - Produces the encrypted name given the ID of the new row.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parent folder does not exist, `encrypt` fails or a query fails.
+    pub fn create_folder(
+        &self,
+        user_id: i64,
+        parent_id: Option<i64>,
+        encrypt: impl FnOnce(i64) -> Result<Vec<u8>>,
+    ) -> Result<i64> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        if let Some(parent_id) = parent_id {
+            check_folder_exists(&tx, user_id, parent_id)?;
+        }
+        let id = next_id(&tx, "folders")?;
+        let name = encrypt(id)?;
+        tx.execute(
+            "INSERT INTO folders (id, user_id, parent_id, name) VALUES (?1, ?2, ?3, ?4);",
+            params![id, user_id, parent_id, name],
+        )?;
+        tx.commit()?;
+
+        Ok(id)
+    }
+
+    /// Replaces the encrypted name of a folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `folder_id` - The ID of the folder.
+    /// * `name` - The new encrypted name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the folder does not exist or the query fails.
+    pub fn rename_folder(&self, user_id: i64, folder_id: i64, name: &[u8]) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let updated = conn.execute(
+            "UPDATE folders SET name = ?1 WHERE user_id = ?2 AND id = ?3;",
+            params![name, user_id, folder_id],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("folder not found"));
+        }
+
+        Ok(())
+    }
+
+    /// Moves a folder, along with everything in it, into another folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `folder_id` - The ID of the folder.
+    /// * `parent_id` - The folder to move it into, `None` for the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either folder does not exist, the folder would be moved into
+    /// itself or one of its subfolders, or a query fails.
+    pub fn move_folder(&self, user_id: i64, folder_id: i64, parent_id: Option<i64>) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        check_folder_exists(&tx, user_id, folder_id)?;
+        if let Some(parent_id) = parent_id {
+            check_folder_exists(&tx, user_id, parent_id)?;
+            let in_subtree: i64 = tx.query_row(
+                &format!("{FOLDER_SUBTREE} SELECT COUNT(*) FROM subtree WHERE id = ?3;"),
+                params![user_id, folder_id, parent_id],
+                |row| row.get(0),
+            )?;
+            if in_subtree > 0 {
+                return Err(anyhow!(
+                    "a folder cannot be moved into itself or one of its subfolders"
+                ));
+            }
+        }
+        tx.execute(
+            "UPDATE folders SET parent_id = ?1 WHERE user_id = ?2 AND id = ?3;",
+            params![parent_id, user_id, folder_id],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Deletes a folder. Its subfolders are either deleted with it, with their secrets and its
+    /// own moved to the trash, or moved to its parent together with its secrets. Trashed
+    /// secrets are taken out of the deleted folders so that they are restored to the root.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `folder_id` - The ID of the folder.
+    /// * `deletion` - What happens to the contents of the folder.
+    /// * `now` - The time of the deletion, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the folder does not exist or a query fails, in which case nothing
+    /// is changed.
+    pub fn delete_folder(
+        &self,
+        user_id: i64,
+        folder_id: i64,
+        deletion: FolderDeletion,
+        now: u64,
+    ) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        check_folder_exists(&tx, user_id, folder_id)?;
+        match deletion {
+            FolderDeletion::Cascade => {
+                tx.execute(
+                    &format!(
+                        "{FOLDER_SUBTREE} UPDATE secrets
+                        SET folder_id = NULL, label_index = NULL,
+                            deleted_at = COALESCE(deleted_at, ?3)
+                        WHERE user_id = ?1 AND folder_id IN subtree;"
+                    ),
+                    params![user_id, folder_id, now],
+                )?;
+                tx.execute(
+                    &format!("{FOLDER_SUBTREE} DELETE FROM folders WHERE id IN subtree;"),
+                    params![user_id, folder_id],
+                )?;
+            }
+            FolderDeletion::MoveToParent => {
+                let parent_id: Option<i64> = tx.query_row(
+                    "SELECT parent_id FROM folders WHERE id = ?1;",
+                    [folder_id],
+                    |row| row.get(0),
+                )?;
+                tx.execute(
+                    "UPDATE folders SET parent_id = ?1 WHERE user_id = ?2 AND parent_id = ?3;",
+                    params![parent_id, user_id, folder_id],
+                )?;
+                tx.execute(
+                    "UPDATE secrets SET folder_id = ?1 WHERE user_id = ?2 AND folder_id = ?3;",
+                    params![parent_id, user_id, folder_id],
+                )?;
+                tx.execute("DELETE FROM folders WHERE id = ?1;", [folder_id])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves all folders belonging to a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_folders(&self, user_id: i64) -> Result<Vec<FolderRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn
+            .prepare("SELECT id, parent_id, name FROM folders WHERE user_id = ?1 ORDER BY id;")?;
+        let rows = stmt.query_map([user_id], |row| {
+            Ok(FolderRow {
+                id: row.get(0)?,
+                parent_id: row.get(1)?,
+                name: row.get(2)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Moves a secret into a folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    /// * `folder_id` - The folder to move it into, `None` for the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the folder does not exist or a query fails.
+    pub fn set_secret_folder(
+        &self,
+        user_id: i64,
+        secret_id: i64,
+        folder_id: Option<i64>,
+    ) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        if let Some(folder_id) = folder_id {
+            check_folder_exists(conn, user_id, folder_id)?;
+        }
+        conn.execute(
+            "UPDATE secrets SET folder_id = ?1 WHERE user_id = ?2 AND id = ?3;",
+            params![folder_id, user_id, secret_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Tags a secret, creating the tag if the user has none with this name yet. The ID of a
+    /// new tag is allocated first and passed to `encrypt`, so that the encrypted name can be
+    /// bound to the row it is stored in.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    /// * `name_index` - The keyed hash of the tag's name.
+    /// * `encrypt` - Produces the encrypted name given the ID of a new tag.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret does not exist, `encrypt` fails or a query fails, in
+    /// which case nothing is changed.
+    pub fn tag_secret(
+        &self,
+        user_id: i64,
+        secret_id: i64,
+        name_index: &[u8],
+        encrypt: impl FnOnce(i64) -> Result<Vec<u8>>,
+    ) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        let count: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM secrets WHERE user_id = ?1 AND id = ?2;",
+            params![user_id, secret_id],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Err(anyhow!("secret not found"));
+        }
+        let tag_id = match get_tag_id(&tx, user_id, name_index)? {
+            Some(tag_id) => tag_id,
+            None => {
+                let id = next_id(&tx, "tags")?;
+                let name = encrypt(id)?;
+                tx.execute(
+                    "INSERT INTO tags (id, user_id, name_index, name) VALUES (?1, ?2, ?3, ?4);",
+                    params![id, user_id, name_index, name],
+                )?;
+                id
+            }
+        };
+        tx.execute(
+            "INSERT OR IGNORE INTO secret_tags (secret_id, tag_id) VALUES (?1, ?2);",
+            params![secret_id, tag_id],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Removes a tag from a secret, and deletes the tag once no secret has it.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    /// * `name_index` - The keyed hash of the tag's name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a query fails.
+    pub fn untag_secret(&self, user_id: i64, secret_id: i64, name_index: &[u8]) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        if let Some(tag_id) = get_tag_id(&tx, user_id, name_index)? {
+            tx.execute(
+                "DELETE FROM secret_tags WHERE secret_id = ?1 AND tag_id = ?2;",
+                params![secret_id, tag_id],
+            )?;
+            tx.execute(DELETE_UNUSED_TAGS, [user_id])?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the ID of a tag from the keyed hash of its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `name_index` - The keyed hash of the tag's name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails. Returns `None` if the user has no such tag.
+    pub fn get_tag_id(&self, user_id: i64, name_index: &[u8]) -> Result<Option<i64>> {
+        get_tag_id(self.get_conn()?, user_id, name_index)
+    }
+
+    /// Retrieves all tags belonging to a user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_tags(&self, user_id: i64) -> Result<Vec<TagRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare("SELECT id, name FROM tags WHERE user_id = ?1 ORDER BY id;")?;
+        let rows = stmt.query_map([user_id], |row| {
+            Ok(TagRow {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves which secrets of a user have which tags, as pairs of secret and tag IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_secret_tags(&self, user_id: i64) -> Result<Vec<(i64, i64)>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT st.secret_id, st.tag_id FROM secret_tags st JOIN tags t ON t.id = st.tag_id
+            WHERE t.user_id = ?1 ORDER BY st.secret_id, st.tag_id;",
+        )?;
+        let rows = stmt.query_map([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves all secrets belonging to a user, except those stored with legacy labels and
+    /// those in the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_all_secrets(&self, user_id: i64) -> Result<Vec<SecretRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, label_index, meta, data FROM secrets
+            WHERE user_id = ?1 AND meta IS NOT NULL AND deleted_at IS NULL",
+        )?;
+        let rows = stmt.query_map([user_id], secret_row)?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves the secrets of a user that are in the trash, with their encrypted data. Their
+    /// label index is left empty, since secrets in the trash are not indexed.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_trashed_secrets(&self, user_id: i64) -> Result<Vec<SecretRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, x'', meta, data FROM secrets
+            WHERE user_id = ?1 AND meta IS NOT NULL AND deleted_at IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([user_id], secret_row)?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves the earlier versions of all secrets of a user, those in the trash included.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_all_secret_versions(&self, user_id: i64) -> Result<Vec<SecretVersionRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT v.id, v.secret_id, v.meta, v.data, v.replaced_at, v.legacy_context
+            FROM secret_versions v JOIN secrets s ON s.id = v.secret_id
+            WHERE s.user_id = ?1;",
+        )?;
+        let rows = stmt.query_map([user_id], secret_version_row)?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves the secrets of a user that are still stored with plaintext labels and kinds.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_legacy_secrets(&self, user_id: i64) -> Result<Vec<LegacySecretRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, legacy_kind, legacy_label, data FROM secrets
+            WHERE user_id = ?1 AND meta IS NULL",
+        )?;
+        let rows = stmt.query_map([user_id], |row| {
+            Ok(LegacySecretRow {
+                id: row.get(0)?,
+                kind: row.get(1)?,
+                label: row.get(2)?,
+                data: row.get(3)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Retrieves a secret by the keyed hash of its label.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `label_index` - The keyed hash of the secret's label.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_secret(&self, user_id: i64, label_index: &[u8]) -> Result<Option<SecretRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, label_index, meta, data FROM secrets
+            WHERE user_id = ?1 AND label_index = ?2",
+        )?;
+
+        let data = stmt
+            .query_row(params![user_id, label_index], secret_row)
+            .optional()?;
+        Ok(data)
+    }
+
+    /// Moves a secret to the trash by the keyed hash of its label. Its ciphertext, versions,
+    /// folder and tags are kept, but its label index is cleared so that the label can be
+    /// used by another secret in the meantime.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `label_index` - The keyed hash of the secret's label.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn trash_secret(&self, user_id: i64, label_index: &[u8], now: u64) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE secrets SET label_index = NULL, deleted_at = ?1
+            WHERE user_id = ?2 AND label_index = ?3;",
+            params![now, user_id, label_index],
+        )?;
+
+        Ok(())
+    }
+
+    /// Retrieves the secrets of a user that are in the trash, most recently deleted first.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn get_trash(&self, user_id: i64) -> Result<Vec<TrashRow>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, meta, deleted_at FROM secrets
+            WHERE user_id = ?1 AND deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC, id DESC;",
+        )?;
+        let rows = stmt.query_map([user_id], |row| {
+            Ok(TrashRow {
+                id: row.get(0)?,
+                meta: row.get(1)?,
+                deleted_at: row.get(2)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Takes a secret out of the trash, indexing it again by the keyed hash of its label.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    /// * `label_index` - The keyed hash of the secret's label.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret is not in the trash, another secret now has the same
+    /// label, or the query fails.
+    pub fn restore_secret(&self, user_id: i64, secret_id: i64, label_index: &[u8]) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        if self.check_if_secret_label_exists(user_id, label_index)? {
+            return Err(anyhow!("A secret with the same label already exists"));
+        }
+        let updated = conn.execute(
+            "UPDATE secrets SET label_index = ?1, deleted_at = NULL
+            WHERE user_id = ?2 AND id = ?3 AND deleted_at IS NOT NULL;",
+            params![label_index, user_id, secret_id],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("secret not found in the trash"));
+        }
+
+        Ok(())
+    }
+
+    /// Permanently deletes a secret in the trash, together with its earlier versions and tags.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `secret_id` - The ID of the secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret is not in the trash or the deletion fails.
+    pub fn delete_trashed_secret(&self, user_id: i64, secret_id: i64) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        let deleted = delete_secrets(
+            &tx,
+            user_id,
+            "id = ?2 AND deleted_at IS NOT NULL",
+            secret_id,
+        )?;
+        if deleted == 0 {
+            return Err(anyhow!("secret not found in the trash"));
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Permanently deletes the secrets of a user that were moved to the trash at or before a
+    /// given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `deleted_before` - The time, in seconds since the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the deletion fails, in which case nothing is changed.
+    pub fn purge_trash(&self, user_id: i64, deleted_before: u64) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        let tx = conn.unchecked_transaction()?;
+        delete_secrets(&tx, user_id, "deleted_at <= ?2", deleted_before)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the number of days secrets of a user stay in the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails or the user does not exist. Returns `None` if
+    /// the user never set it.
+    pub fn get_trash_retention(&self, user_id: i64) -> Result<Option<u32>> {
+        let conn = self.get_conn()?;
+
+        let mut stmt =
+            conn.prepare("SELECT trash_retention_days FROM users WHERE user_id = ?1;")?;
+        let days: Option<u32> = stmt.query_row([user_id], |row| row.get(0))?;
+
+        Ok(days)
+    }
+
+    /// Sets the number of days secrets of a user stay in the trash.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID.
+    /// * `days` - The number of days.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query fails.
+    pub fn set_trash_retention(&self, user_id: i64, days: u32) -> Result<()> {
+        let conn = self.get_conn()?;
+
+        conn.execute(
+            "UPDATE users SET trash_retention_days = ?1 WHERE user_id = ?2;",
+            params![days, user_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Moves the connection into a new `DbConn`, leaving this one without a connection.
+    pub fn take(&mut self) -> DbConn {
+        DbConn {
+            conn: self.conn.take(),
+        }
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        if let Some(conn) = self.conn.take() {
+            conn.close().expect("should close db connection");
+        }
+        Ok(())
+    }
+}
+
+/// Hashes a username that has no account, so that the usernames tried are not stored.
+fn username_hash(username: &str) -> Vec<u8> {
+    Sha256::digest(username.as_bytes()).to_vec()
+}
+
+/// Builds key derivation parameters from their columns, which are all `NULL` for users created
+/// before parameters were stored, who use the defaults.
+fn kdf_params(columns: (Option<u32>, Option<u32>, Option<u32>)) -> KdfParams {
+    match columns {
+        (Some(m_cost), Some(t_cost), Some(p_cost)) => KdfParams {
+            m_cost,
+            t_cost,
+            p_cost,
+        },
+        _ => KdfParams::default(),
+    }
+}
+
+/// Maps a row of `id, label_index, meta, data` to a `SecretRow`.
+fn secret_row(row: &rusqlite::Row) -> rusqlite::Result<SecretRow> {
+    Ok(SecretRow {
+        id: row.get(0)?,
+        label_index: row.get(1)?,
+        meta: row.get(2)?,
+        data: row.get(3)?,
+    })
+}
+
+/// Maps a row of `id, secret_id, meta, data, replaced_at, legacy_context` to a
+/// `SecretVersionRow`.
+fn secret_version_row(row: &rusqlite::Row) -> rusqlite::Result<SecretVersionRow> {
+    Ok(SecretVersionRow {
+        id: row.get(0)?,
+        secret_id: row.get(1)?,
+        meta: row.get(2)?,
+        data: row.get(3)?,
+        replaced_at: row.get(4)?,
+        legacy_context: row.get(5)?,
+    })
+}
+
+/// Permanently deletes the secrets of a user matching a condition, together with their
+/// earlier versions and tags, then the tags no secret has anymore.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+/// * `user_id` - The user ID, bound to `?1`.
+/// * `condition` - An SQL condition on the `secrets` table.
+/// * `param` - The value bound to `?2` in the condition.
+///
+/// # Errors
+///
+/// Returns an error if a statement fails. Returns the number of secrets deleted.
+fn delete_secrets(
+    conn: &Connection,
+    user_id: i64,
+    condition: &str,
+    param: impl rusqlite::ToSql,
+) -> Result<usize> {
+    let selected = format!("SELECT id FROM secrets WHERE user_id = ?1 AND {condition}");
+    conn.execute(
+        &format!("DELETE FROM secret_versions WHERE secret_id IN ({selected});"),
+        params![user_id, param],
+    )?;
+    conn.execute(
+        &format!("DELETE FROM secret_tags WHERE secret_id IN ({selected});"),
+        params![user_id, param],
+    )?;
+    let deleted = conn.execute(
+        &format!("DELETE FROM secrets WHERE user_id = ?1 AND {condition};"),
+        params![user_id, param],
+    )?;
+    conn.execute(DELETE_UNUSED_TAGS, [user_id])?;
+    Ok(deleted)
+}
+
+/// Checks that a folder exists and belongs to a user.
+///
+/// # Arguments
+///
+/// * `conn` - The connection or transaction to run the query on.
+/// * `user_id` - The user ID.
+/// * `folder_id` - The ID of the folder.
+///
+/// # Errors
+///
+/// Returns an error if the folder does not exist or the query fails.
+fn check_folder_exists(conn: &Connection, user_id: i64, folder_id: i64) -> Result<()> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM folders WHERE user_id = ?1 AND id = ?2;",
+        params![user_id, folder_id],
+        |row| row.get(0),
+    )?;
+    if count == 0 {
+        return Err(anyhow!("folder not found"));
+    }
+    Ok(())
+}
+
+/// Retrieves the ID the next row of a table gets. The tables with IDs are `AUTOINCREMENT`,
+/// so the ID is never one that a deleted row had, and inserting a row with it explicitly
+/// advances the sequence.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction the row will be inserted in.
+/// * `table` - The name of the table.
+///
+/// # Errors
+///
+/// Returns an error if the query fails.
+fn next_id(conn: &Connection, table: &str) -> Result<i64> {
+    let id = conn.query_row(
+        "SELECT IFNULL((SELECT seq FROM sqlite_sequence WHERE name = ?1), 0) + 1;",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(id)
+}
+
+/// Retrieves the ID of a tag from the keyed hash of its name.
+///
+/// # Arguments
+///
+/// * `conn` - The connection or transaction to run the query on.
+/// * `user_id` - The user ID.
+/// * `name_index` - The keyed hash of the tag's name.
+///
+/// # Errors
+///
+/// Returns an error if the query fails. Returns `None` if the user has no such tag.
+fn get_tag_id(conn: &Connection, user_id: i64, name_index: &[u8]) -> Result<Option<i64>> {
+    let tag_id = conn
+        .query_row(
+            "SELECT id FROM tags WHERE user_id = ?1 AND name_index = ?2;",
+            params![user_id, name_index],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(tag_id)
+}
+
+/// Replaces the label index, encrypted metadata and data of secrets identified by their IDs,
+/// clearing any plaintext legacy label and kind. Secrets in the trash stay unindexed.
+///
+/// # Arguments
+///
+/// * `conn` - The connection or transaction to run the updates on.
+/// * `user_id` - The user ID the secrets belong to.
+/// * `secrets` - The secrets with their IDs and new values.
+///
+/// # Errors
+///
+/// Returns an error if any update fails.
+fn update_secrets(conn: &Connection, user_id: i64, secrets: Vec<SecretRow>) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "UPDATE secrets SET label_index = CASE WHEN deleted_at IS NULL THEN ?1 END,
+            meta = ?2, data = ?3, legacy_kind = NULL, legacy_label = NULL
+        WHERE user_id = ?4 AND id = ?5;",
+    )?;
+    for secret in secrets {
+        stmt.execute(params![
+            secret.label_index,
+            secret.meta,
+            secret.data,
+            user_id,
+            secret.id
+        ])?;
+    }
+    Ok(())
+}
+
+/// Replaces the encrypted metadata and data of versions identified by their IDs, which are
+/// then bound to their own row.
+///
+/// # Arguments
+///
+/// * `conn` - The connection or transaction to run the updates on.
+/// * `user_id` - The user ID the secrets of the versions belong to.
+/// * `versions` - The versions with their IDs and new values.
+///
+/// # Errors
+///
+/// Returns an error if any update fails.
+fn update_secret_versions(
+    conn: &Connection,
+    user_id: i64,
+    versions: Vec<SecretVersionRow>,
+) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "UPDATE secret_versions SET meta = ?1, data = ?2, legacy_context = 0
+        WHERE id = ?3 AND secret_id IN (SELECT id FROM secrets WHERE user_id = ?4);",
+    )?;
+    for version in versions {
+        stmt.execute(params![version.meta, version.data, version.id, user_id])?;
+    }
+    Ok(())
+}
+
+/// Checks whether a table has a column with the given name.
+///
+/// # Arguments
+///
+/// * `conn` - The connection or transaction to run the query on.
+/// * `table` - The name of the table.
+/// * `column` - The name of the column.
+///
+/// # Errors
+///
+/// Returns an error if the table cannot be inspected.
+fn column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table});"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+    Ok(exists)
+}
+
+/// Adds a column to a table unless it already exists.
+/// This is used to bring databases created by older versions up to date.
+///
+/// # Arguments
+///
+/// * `conn` - The connection or transaction to run the statements on.
+/// * `table` - The name of the table.
+/// * `column` - The name of the column.
+/// * `definition` - The column type and constraints.
+///
+/// # Errors
+///
+/// Returns an error if the table cannot be inspected or altered.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    if !column_exists(conn, table, column)? {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition};"),
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs the migrations a database has not run yet, in order. Each migration runs in its own
+/// transaction together with the update of `user_version`, so a failed migration leaves the
+/// database as the previous one left it.
+///
+/// # Arguments
+///
+/// * `conn` - The connection to the database.
+/// * `migrations` - The migrations, in the order they are applied.
+///
+/// # Errors
+///
+/// Returns an error if a migration fails or if the database has run more migrations than
+/// are known, meaning it was created by a newer version of Lockkey.
+fn run_migrations(conn: &mut Connection, migrations: &[Migration]) -> Result<()> {
+    loop {
+        // the version is read within the transaction, so that two instances opening the same
+        // database cannot run a migration twice
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version: usize = tx.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+
+        let migration = match version.cmp(&migrations.len()) {
+            Ordering::Less => migrations[version],
+            Ordering::Equal => return Ok(()),
+            Ordering::Greater => {
+                return Err(anyhow!(
+                    "the vault was created by a newer version of Lockkey (schema version \
+                    {version}, this version supports up to {}), update Lockkey to open it",
+                    migrations.len()
+                ))
+            }
+        };
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+    }
+}
+
+/// Migration 1: creates the `users` and `secrets` tables, or brings databases created before
+/// migrations were versioned up to the schema they had then.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn create_base_schema(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            user_id     INTEGER PRIMARY KEY,
+            username    TEXT UNIQUE NOT NULL CHECK(length(username) <= 24),
+            passwd_hash TEXT NOT NULL,
+            enc_salt    BLOB NOT NULL,
+            kdf_m_cost  INTEGER,
+            kdf_t_cost  INTEGER,
+            kdf_p_cost  INTEGER,
+            wrapped_key BLOB,
+            recovery_salt        BLOB,
+            recovery_wrapped_key BLOB,
+            cipher      INTEGER,
+            padding     INTEGER,
+            key_file    INTEGER,
+            failed_logins     INTEGER,
+            last_failed_login INTEGER,
+            lockout_after     INTEGER,
+            totp              BLOB,
+            min_password_score INTEGER
+        );
+        ",
+        (),
+    )?;
+
+    // databases created before the KDF parameters were stored per user
+    for column in ["kdf_m_cost", "kdf_t_cost", "kdf_p_cost"] {
+        add_column_if_missing(conn, "users", column, "INTEGER")?;
+    }
+    // databases created before secrets were encrypted with a wrapped vault key
+    add_column_if_missing(conn, "users", "wrapped_key", "BLOB")?;
+    // databases created before recovery keys were introduced
+    add_column_if_missing(conn, "users", "recovery_salt", "BLOB")?;
+    add_column_if_missing(conn, "users", "recovery_wrapped_key", "BLOB")?;
+    // databases created before the cipher was selectable per vault
+    add_column_if_missing(conn, "users", "cipher", "INTEGER")?;
+    // databases created before secrets were padded
+    add_column_if_missing(conn, "users", "padding", "INTEGER")?;
+    // databases created before key files were introduced
+    add_column_if_missing(conn, "users", "key_file", "INTEGER")?;
+    // databases created before failed logins were throttled
+    for column in ["failed_logins", "last_failed_login", "lockout_after"] {
+        add_column_if_missing(conn, "users", column, "INTEGER")?;
+    }
+    // databases created before two-factor authentication was introduced
+    add_column_if_missing(conn, "users", "totp", "BLOB")?;
+    // databases created before password strength was enforced
+    add_column_if_missing(conn, "users", "min_password_score", "INTEGER")?;
+
+    conn.execute(SECRETS_TABLE, ())?;
+
+    // databases created before labels and kinds were encrypted store them in plaintext
+    // columns with CHECK constraints, so the table is rebuilt and the plaintext values are
+    // kept in legacy columns until each user logs in and they can be encrypted
+    if column_exists(conn, "secrets", "label")? {
+        conn.execute("ALTER TABLE secrets RENAME TO plaintext_secrets;", ())?;
+        conn.execute(SECRETS_TABLE, ())?;
+        conn.execute(
+            "INSERT INTO secrets (id, user_id, data, legacy_kind, legacy_label)
+            SELECT id, user_id, data, kind, label FROM plaintext_secrets;",
+            (),
+        )?;
+        conn.execute("DROP TABLE plaintext_secrets;", ())?;
+    }
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_secrets_user_id ON secrets (user_id);",
+        (),
+    )?;
+
+    Ok(())
+}
+
+/// Migration 2: creates the `meta` table, which holds a random ID identifying the vault and
+/// the version of Lockkey that created it. For databases older than the table, that is the
+/// version that added it.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn create_meta_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE meta (
+            key   TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+        (),
+    )?;
+
+    let vault_id: String = rand::thread_rng()
+        .gen::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    conn.execute(
+        "INSERT INTO meta (key, value) VALUES ('vault_id', ?1), ('created_by', ?2);",
+        params![vault_id, env!("CARGO_PKG_VERSION")],
+    )?;
+
+    Ok(())
+}
+
+/// Migration 3: creates the `secret_versions` table, which keeps the earlier versions of
+/// edited secrets, and the per-user number of versions to keep.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn create_secret_versions_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE secret_versions (
+            id          INTEGER PRIMARY KEY,
+            secret_id   INTEGER NOT NULL,
+            meta        BLOB NOT NULL,
+            data        BLOB NOT NULL,
+            replaced_at INTEGER NOT NULL,
+            FOREIGN KEY(secret_id) REFERENCES secrets(id)
+        );
+        CREATE INDEX idx_secret_versions_secret_id ON secret_versions (secret_id);
+        ALTER TABLE users ADD COLUMN history_limit INTEGER;",
+    )?;
+    Ok(())
+}
+
+/// Migration 4: adds the times a secret was stored, last edited and last read.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn add_secret_timestamps(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE secrets ADD COLUMN created_at INTEGER;
+        ALTER TABLE secrets ADD COLUMN updated_at INTEGER;
+        ALTER TABLE secrets ADD COLUMN last_used_at INTEGER;",
+    )?;
+    Ok(())
+}
+
+/// Migration 5: adds folders, which form a tree, and tags, which secrets can have any number
+/// of. Folder and tag names are only stored encrypted, along with a keyed hash of tag names.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn create_folders_and_tags(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE folders (
+            id        INTEGER PRIMARY KEY,
+            user_id   INTEGER NOT NULL,
+            parent_id INTEGER,
+            name      BLOB NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(user_id),
+            FOREIGN KEY(parent_id) REFERENCES folders(id)
+        );
+        CREATE INDEX idx_folders_user_id ON folders (user_id);
+        CREATE TABLE tags (
+            id         INTEGER PRIMARY KEY,
+            user_id    INTEGER NOT NULL,
+            name_index BLOB NOT NULL,
+            name       BLOB NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(user_id),
+            UNIQUE(user_id, name_index)
+        );
+        CREATE TABLE secret_tags (
+            secret_id INTEGER NOT NULL,
+            tag_id    INTEGER NOT NULL,
+            FOREIGN KEY(secret_id) REFERENCES secrets(id),
+            FOREIGN KEY(tag_id) REFERENCES tags(id),
+            PRIMARY KEY(secret_id, tag_id)
+        );
+        CREATE INDEX idx_secret_tags_tag_id ON secret_tags (tag_id);
+        ALTER TABLE secrets ADD COLUMN folder_id INTEGER REFERENCES folders(id);",
+    )?;
+    Ok(())
+}
+
+/// Migration 6: adds the time a secret was moved to the trash, and the number of days it
+/// stays there before being deleted.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn add_trash(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE secrets ADD COLUMN deleted_at INTEGER;
+        ALTER TABLE users ADD COLUMN trash_retention_days INTEGER;",
+    )?;
+    Ok(())
+}
+
+/// Migration 7: adds the failed login attempts made with usernames that have no account, so
+/// that they are throttled like those of existing users.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn create_unknown_logins_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE unknown_logins (
+            username_hash     BLOB PRIMARY KEY,
+            failed_logins     INTEGER NOT NULL,
+            last_failed_login INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+/// Migration 8: flags the versions kept so far, whose metadata and data are still bound to
+/// the secret's row rather than the version's, so that they are re-encrypted on next login.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn add_version_legacy_context(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE secret_versions ADD COLUMN legacy_context INTEGER NOT NULL DEFAULT 0;
+        UPDATE secret_versions SET legacy_context = 1;",
+    )?;
+    Ok(())
+}
+
+/// Migration 9: rebuilds the tables whose rows are referred to by ID as `AUTOINCREMENT`, so
+/// that the IDs of deleted secrets, versions, folders and tags are never given to new ones,
+/// which would bind their ciphertexts to rows they were not created for. Each table is
+/// rebuilt under a new name which then replaces the old one, so that the references of the
+/// other tables are kept.
+///
+/// # Arguments
+///
+/// * `conn` - The transaction to run the statements in.
+///
+/// # Errors
+///
+/// Returns an error if the statements fail.
+fn make_ids_autoincrement(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE new_secrets (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id      INTEGER,
+            label_index  BLOB,
+            meta         BLOB,
+            data         BLOB NOT NULL CHECK(length(data) > 3),
+            legacy_kind  TEXT,
+            legacy_label TEXT,
+            created_at   INTEGER,
+            updated_at   INTEGER,
+            last_used_at INTEGER,
+            folder_id    INTEGER REFERENCES folders(id),
+            deleted_at   INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(user_id),
+            UNIQUE(user_id, label_index)
+        );
+        INSERT INTO new_secrets (id, user_id, label_index, meta, data, legacy_kind,
+            legacy_label, created_at, updated_at, last_used_at, folder_id, deleted_at)
+        SELECT id, user_id, label_index, meta, data, legacy_kind, legacy_label, created_at,
+            updated_at, last_used_at, folder_id, deleted_at FROM secrets;
+        DROP TABLE secrets;
+        ALTER TABLE new_secrets RENAME TO secrets;
+        CREATE INDEX idx_secrets_user_id ON secrets (user_id);
+
+        CREATE TABLE new_secret_versions (
+            id             INTEGER PRIMARY KEY AUTOINCREMENT,
+            secret_id      INTEGER NOT NULL,
+            meta           BLOB NOT NULL,
+            data           BLOB NOT NULL,
+            replaced_at    INTEGER NOT NULL,
+            legacy_context INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY(secret_id) REFERENCES secrets(id)
+        );
+        INSERT INTO new_secret_versions (id, secret_id, meta, data, replaced_at, legacy_context)
+        SELECT id, secret_id, meta, data, replaced_at, legacy_context FROM secret_versions;
+        DROP TABLE secret_versions;
+        ALTER TABLE new_secret_versions RENAME TO secret_versions;
+        CREATE INDEX idx_secret_versions_secret_id ON secret_versions (secret_id);
+
+        CREATE TABLE new_folders (
+            id        INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id   INTEGER NOT NULL,
+            parent_id INTEGER,
+            name      BLOB NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(user_id),
+            FOREIGN KEY(parent_id) REFERENCES folders(id)
+        );
+        INSERT INTO new_folders (id, user_id, parent_id, name)
+        SELECT id, user_id, parent_id, name FROM folders;
+        DROP TABLE folders;
+        ALTER TABLE new_folders RENAME TO folders;
+        CREATE INDEX idx_folders_user_id ON folders (user_id);
+
+        CREATE TABLE new_tags (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            name_index BLOB NOT NULL,
+            name       BLOB NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(user_id),
+            UNIQUE(user_id, name_index)
+        );
+        INSERT INTO new_tags (id, user_id, name_index, name)
+        SELECT id, user_id, name_index, name FROM tags;
+        DROP TABLE tags;
+        ALTER TABLE new_tags RENAME TO tags;",
+    )?;
+    Ok(())
+}
+
+impl Drop for DbConn {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            conn.close().expect("should close db connection");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        fs,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    const POLICY: EncryptionPolicy = EncryptionPolicy {
+        cipher: CipherId::Aes256Gcm,
+        padding: PaddingId::Padme,
+    };
+
+    fn credentials(passwd_hash: &str, enc_salt: &[u8]) -> Credentials {
+        Credentials {
+            passwd_hash: passwd_hash.to_string(),
+            enc_salt: enc_salt.to_vec(),
+            kdf_params: KdfParams::default(),
+            wrapped_key: b"key".to_vec(),
+            key_file: false,
+        }
+    }
+
+    struct TestDb {
+        path: PathBuf,
+    }
+
+    impl TestDb {
+        fn new() -> Self {
+            static TEST_ID: AtomicUsize = AtomicUsize::new(0);
+            let test_id = TEST_ID.fetch_add(1, Ordering::SeqCst);
+            let mut path = std::env::temp_dir();
+            path.push(format!("lockkey_data_test_db_{test_id:?}.sqlite"));
+            TestDb { path }
+        }
+
+        fn get_path(&self) -> &str {
+            &self.path.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let mut attempts = 0;
+            while attempts < 5 {
+                if fs::remove_file(&self.path).is_ok() {
+                    return;
+                }
+
+                attempts += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+            fs::remove_file(&self.path).expect("should delete the test database file");
+        }
+    }
+
+    #[test]
+    fn can_create_db_connection() {
+        let test_db = TestDb::new();
         let db_path = test_db.get_path();
 
         DbConn::new(db_path).unwrap();
     }
 
     #[test]
-    fn can_insert_and_retrieve_data_from_table() {
+    fn can_insert_and_retrieve_data_from_table() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        // setup credentials
+        let username = "test_user";
+        let passwd = "test_pass";
+        let enc_salt = b"test_salt";
+
+        conn.create_user(username, &credentials(passwd, enc_salt), POLICY)
+            .unwrap();
+
+        let passwd: Vec<u8> = "passwd".into();
+        let index1 = b"index1";
+        conn.store_secret(1, index1, 1000, |_| Ok((b"meta1".to_vec(), passwd.clone())))
+            .unwrap();
+
+        let index2 = b"index2";
+        conn.store_secret(1, index2, 1000, |_| Ok((b"meta2".to_vec(), passwd.clone())))
+            .unwrap();
+
+        // getting back the data
+        let secret1 = conn.get_secret(1, index1).unwrap().unwrap();
+        assert_eq!(&secret1.meta, b"meta1");
+        assert_eq!(&secret1.data, &passwd);
+
+        let secret2 = conn.get_secret(1, index2).unwrap().unwrap();
+        assert_eq!(&secret2.meta, b"meta2");
+        assert_eq!(&secret2.data, &passwd);
+    }
+
+    #[test]
+    fn can_retrieve_labels() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        // setup credentials
+        let username = "test_user";
+        let passwd = "test_pass";
+        let enc_salt = b"test_salt";
+        conn.create_user(username, &credentials(passwd, enc_salt), POLICY)
+            .unwrap();
+
+        let passwd: Vec<u8> = "passwd".into();
+        let meta1 = b"meta1".to_vec();
+        let id1 = conn
+            .store_secret(1, b"index1", 1000, |_| Ok((meta1.clone(), passwd.clone())))
+            .expect("should insert into table");
+
+        let meta2 = b"meta2".to_vec();
+        let id2 = conn
+            .store_secret(1, b"index2", 1000, |_| Ok((meta2.clone(), passwd.clone())))
+            .expect("should insert into table");
+
+        let query = conn.get_labels(1).expect("should retrieve labels");
+
+        assert_eq!(query.len(), 2);
+
+        let mut inputs = HashMap::new();
+        inputs.insert(id1, meta1);
+        inputs.insert(id2, meta2);
+
+        for row in query {
+            assert!(inputs.contains_key(&row.id));
+            assert_eq!(inputs.get(&row.id), Some(&row.meta));
+        }
+    }
+
+    #[test]
+    fn can_record_secret_timestamps() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        let id = conn
+            .store_secret(1, b"index", 1000, |_| {
+                Ok((b"meta".to_vec(), b"data".to_vec()))
+            })
+            .unwrap();
+        let timestamps = || conn.get_labels(1).unwrap()[0].timestamps;
+        assert_eq!(
+            timestamps(),
+            SecretTimestamps {
+                created_at: Some(1000),
+                updated_at: Some(1000),
+                last_used_at: None,
+            }
+        );
+
+        conn.record_secret_use(1, id, 1500).unwrap();
+        conn.record_secret_use(2, id, 1600).unwrap();
+        let secret = conn.get_secret(1, b"index").unwrap().unwrap();
+        conn.edit_secret(1, secret, 0, 2000, |_, replaced| {
+            Ok((replaced.meta, replaced.data))
+        })
+        .unwrap();
+        assert_eq!(
+            timestamps(),
+            SecretTimestamps {
+                created_at: Some(1000),
+                updated_at: Some(2000),
+                last_used_at: Some(1500),
+            }
+        );
+    }
+
+    #[test]
+    fn can_organize_folders() {
         let test_db = TestDb::new();
         let db_path = test_db.get_path();
         let conn = DbConn::new(db_path).unwrap();
 
-        // setup credentials
-        let username = "test_user";
-        let passwd = "test_pass";
-        let enc_salt = b"test_salt";
-
-        conn.create_user(username, passwd, enc_salt).unwrap();
-
-        let passwd: Vec<u8> = "passwd".into();
-        let label1 = "pass1".to_string();
-        conn.store_secret(1, Kind::Password, &label1, passwd.clone())
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
             .unwrap();
+        let folder = |parent_id| {
+            conn.create_folder(1, parent_id, |id| Ok(format!("folder{id}").into_bytes()))
+                .unwrap()
+        };
+        let work = folder(None);
+        let mail = folder(Some(work));
+        let old = folder(Some(mail));
+        assert!(conn.create_folder(1, Some(42), |_| Ok(vec![])).is_err());
+        assert!(conn.create_folder(2, Some(work), |_| Ok(vec![])).is_err());
+
+        conn.rename_folder(1, mail, b"renamed").unwrap();
+        assert!(conn.rename_folder(2, mail, b"renamed").is_err());
+        assert!(conn.move_folder(1, work, Some(old)).is_err());
+        assert!(conn.move_folder(1, work, Some(work)).is_err());
+        conn.move_folder(1, old, Some(work)).unwrap();
+        assert_eq!(
+            conn.get_folders(1).unwrap(),
+            vec![
+                FolderRow {
+                    id: work,
+                    parent_id: None,
+                    name: b"folder1".to_vec(),
+                },
+                FolderRow {
+                    id: mail,
+                    parent_id: Some(work),
+                    name: b"renamed".to_vec(),
+                },
+                FolderRow {
+                    id: old,
+                    parent_id: Some(work),
+                    name: b"folder3".to_vec(),
+                },
+            ]
+        );
+
+        let secret = |index: &[u8], folder_id| {
+            let id = conn
+                .store_secret(1, index, 1000, |_| Ok((b"meta".to_vec(), b"data".to_vec())))
+                .unwrap();
+            conn.set_secret_folder(1, id, folder_id).unwrap();
+            id
+        };
+        let in_mail = secret(b"index1", Some(mail));
+        let in_old = secret(b"index2", Some(old));
+        assert!(conn.set_secret_folder(1, in_mail, Some(42)).is_err());
+        let folder_of = |id| {
+            conn.get_labels(1)
+                .unwrap()
+                .into_iter()
+                .find(|row| row.id == id)
+                .map(|row| row.folder_id)
+        };
 
-        let label2 = "pass2".to_string();
-        conn.store_secret(1, Kind::Text, &label2, passwd.clone())
+        conn.delete_folder(1, mail, FolderDeletion::MoveToParent, 2000)
             .unwrap();
+        assert_eq!(folder_of(in_mail), Some(Some(work)));
+        conn.delete_folder(1, work, FolderDeletion::MoveToParent, 2000)
+            .unwrap();
+        assert_eq!(folder_of(in_mail), Some(None));
+        assert_eq!(folder_of(in_old), Some(Some(old)));
+        assert_eq!(conn.get_folders(1).unwrap()[0].parent_id, None);
 
-        // getting back the data
-        let (kind1, data1) = conn.get_secret(1, &label1).unwrap().unwrap();
-        assert_eq!(&kind1, &Kind::Password.to_str());
-        assert_eq!(&data1, &passwd);
-
-        let (kind2, data2) = conn.get_secret(1, &label2).unwrap().unwrap();
-        assert_eq!(&kind2, &Kind::Text.to_str());
-        assert_eq!(&data2, &passwd);
+        let nested = folder(Some(old));
+        let in_nested = secret(b"index3", Some(nested));
+        conn.tag_secret(1, in_nested, b"tag", |_| Ok(b"tag".to_vec()))
+            .unwrap();
+        let trashed = secret(b"index4", Some(nested));
+        conn.trash_secret(1, b"index4", 1500).unwrap();
+        assert!(conn
+            .delete_folder(2, old, FolderDeletion::Cascade, 3000)
+            .is_err());
+        conn.delete_folder(1, old, FolderDeletion::Cascade, 3000)
+            .unwrap();
+        assert!(conn.get_folders(1).unwrap().is_empty());
+        assert_eq!(folder_of(in_old), None);
+        assert_eq!(folder_of(in_nested), None);
+        assert_eq!(folder_of(in_mail), Some(None));
+        let trash: Vec<_> = conn
+            .get_trash(1)
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.id, row.deleted_at))
+            .collect();
+        assert_eq!(trash, [(in_nested, 3000), (in_old, 3000), (trashed, 1500)]);
+        assert_eq!(conn.get_tags(1).unwrap().len(), 1);
+
+        conn.restore_secret(1, in_nested, b"index3").unwrap();
+        assert_eq!(folder_of(in_nested), Some(None));
     }
 
     #[test]
-    fn can_retrieve_labels() {
+    fn can_tag_secrets() {
         let test_db = TestDb::new();
         let db_path = test_db.get_path();
         let conn = DbConn::new(db_path).unwrap();
 
-        // setup credentials
-        let username = "test_user";
-        let passwd = "test_pass";
-        let enc_salt = b"test_salt";
-        conn.create_user(username, passwd, enc_salt).unwrap();
-
-        let passwd: Vec<u8> = "passwd".into();
-        let label1 = "pass1".to_string();
-        let kind1 = Kind::Password;
-        conn.store_secret(1, kind1.clone(), &label1, passwd.clone())
-            .expect("should insert into table");
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        let first = conn
+            .store_secret(1, b"index1", 1000, |_| {
+                Ok((b"meta".to_vec(), b"data".to_vec()))
+            })
+            .unwrap();
+        let second = conn
+            .store_secret(1, b"index2", 1000, |_| {
+                Ok((b"meta".to_vec(), b"data".to_vec()))
+            })
+            .unwrap();
 
-        let label2 = "pass2".to_string();
-        let kind2 = Kind::Text;
-        conn.store_secret(1, kind2.clone(), &label2, passwd.clone())
-            .expect("should insert into table");
+        conn.tag_secret(1, first, b"work", |id| Ok(format!("work{id}").into_bytes()))
+            .unwrap();
+        conn.tag_secret(1, second, b"work", |_| panic!("the tag already exists"))
+            .unwrap();
+        conn.tag_secret(1, first, b"work", |_| panic!("the tag already exists"))
+            .unwrap();
+        conn.tag_secret(1, first, b"mail", |id| Ok(format!("mail{id}").into_bytes()))
+            .unwrap();
+        assert!(conn
+            .tag_secret(2, first, b"other", |_| Ok(b"other".to_vec()))
+            .is_err());
+        assert_eq!(
+            conn.get_tags(1).unwrap(),
+            vec![
+                TagRow {
+                    id: 1,
+                    name: b"work1".to_vec(),
+                },
+                TagRow {
+                    id: 2,
+                    name: b"mail2".to_vec(),
+                },
+            ]
+        );
+        assert!(conn.get_tags(2).unwrap().is_empty());
+        assert_eq!(conn.get_tag_id(1, b"mail").unwrap(), Some(2));
+        assert_eq!(
+            conn.get_secret_tags(1).unwrap(),
+            vec![(first, 1), (first, 2), (second, 1)]
+        );
+
+        conn.untag_secret(1, first, b"mail").unwrap();
+        conn.untag_secret(1, first, b"missing").unwrap();
+        assert_eq!(conn.get_tag_id(1, b"mail").unwrap(), None);
+        conn.trash_secret(1, b"index1", 3000).unwrap();
+        conn.delete_trashed_secret(1, first).unwrap();
+        assert_eq!(conn.get_secret_tags(1).unwrap(), vec![(second, 1)]);
+        conn.trash_secret(1, b"index2", 3000).unwrap();
+        assert_eq!(conn.get_tags(1).unwrap().len(), 1);
+        conn.delete_trashed_secret(1, second).unwrap();
+        assert!(conn.get_tags(1).unwrap().is_empty());
+    }
 
-        let query = conn.get_labels(1).expect("should retrieve labels");
+    #[test]
+    fn should_never_reuse_ids() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
 
-        assert_eq!(query.len(), 2);
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        let mut ids = vec![];
+        for round in 0..2 {
+            let secret_id = conn
+                .store_secret(1, b"index", 1000, |_| {
+                    Ok((b"meta".to_vec(), b"data".to_vec()))
+                })
+                .unwrap();
+            let secret = conn.get_secret(1, b"index").unwrap().unwrap();
+            conn.edit_secret(1, secret, 5, 2000, |_, replaced| {
+                Ok((replaced.meta, replaced.data))
+            })
+            .unwrap();
+            let version_id = conn.get_secret_versions(1, secret_id).unwrap()[0].id;
+            let folder_id = conn.create_folder(1, None, |_| Ok(vec![])).unwrap();
+            conn.tag_secret(1, secret_id, b"tag", |_| Ok(vec![]))
+                .unwrap();
+            let tag_id = conn.get_tag_id(1, b"tag").unwrap().unwrap();
+            ids.push((secret_id, version_id, folder_id, tag_id));
+
+            // the newest rows are deleted, so the highest IDs are free again
+            conn.trash_secret(1, b"index", 3000 + round).unwrap();
+            conn.delete_trashed_secret(1, secret_id).unwrap();
+            conn.delete_folder(1, folder_id, FolderDeletion::Cascade, 3000)
+                .unwrap();
+            assert!(conn.get_tags(1).unwrap().is_empty());
+            assert!(conn.get_folders(1).unwrap().is_empty());
+        }
+        assert_eq!(ids, vec![(1, 1, 1, 1), (2, 2, 2, 2)]);
+    }
 
-        let mut inputs = HashMap::new();
-        inputs.insert(label1, kind1.to_str());
-        inputs.insert(label2, kind2.to_str());
+    #[test]
+    fn can_trash_and_restore_secrets() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
 
-        for result in query {
-            assert!(inputs.contains_key(&result.label));
-            assert_eq!(inputs.get(&result.label), Some(&result.kind));
-        }
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        let store = |index: &[u8]| {
+            conn.store_secret(1, index, 1000, |_| Ok((b"meta".to_vec(), b"data".to_vec())))
+                .unwrap()
+        };
+        let first = store(b"index1");
+        let second = store(b"index2");
+        conn.trash_secret(1, b"index1", 2000).unwrap();
+        conn.trash_secret(1, b"index2", 3000).unwrap();
+        conn.trash_secret(2, b"index2", 3000).unwrap();
+        assert!(conn.get_labels(1).unwrap().is_empty());
+        assert!(conn.get_all_secrets(1).unwrap().is_empty());
+        assert_eq!(conn.get_secret(1, b"index1").unwrap(), None);
+        assert_eq!(
+            conn.get_trash(1).unwrap(),
+            vec![
+                TrashRow {
+                    id: second,
+                    meta: b"meta".to_vec(),
+                    deleted_at: 3000,
+                },
+                TrashRow {
+                    id: first,
+                    meta: b"meta".to_vec(),
+                    deleted_at: 2000,
+                },
+            ]
+        );
+
+        let reused = store(b"index1");
+        assert!(conn.restore_secret(1, first, b"index1").is_err());
+        assert!(conn.restore_secret(1, reused, b"index3").is_err());
+        assert!(conn.restore_secret(2, first, b"index3").is_err());
+        conn.restore_secret(1, first, b"index3").unwrap();
+        assert_eq!(conn.get_secret(1, b"index3").unwrap().unwrap().id, first);
+
+        assert!(conn.delete_trashed_secret(1, reused).is_err());
+        assert!(conn.delete_trashed_secret(2, second).is_err());
+        conn.purge_trash(1, 2999).unwrap();
+        assert_eq!(conn.get_trash(1).unwrap().len(), 1);
+        conn.delete_trashed_secret(1, second).unwrap();
+        assert!(conn.get_trash(1).unwrap().is_empty());
+        assert_eq!(conn.get_labels(1).unwrap().len(), 2);
+
+        assert_eq!(conn.get_trash_retention(1).unwrap(), None);
+        conn.set_trash_retention(1, 7).unwrap();
+        assert_eq!(conn.get_trash_retention(1).unwrap(), Some(7));
     }
 
     #[test]
@@ -497,10 +2995,14 @@ mod test {
         let db_path = test_db.get_path();
         let conn = DbConn::new(db_path).unwrap();
 
-        conn.store_secret(1, Kind::Password, &String::from("pass1"), b"pass1".to_vec())
-            .unwrap();
-        conn.store_secret(1, Kind::Text, &String::from("pass1"), b"pass2".to_vec())
-            .unwrap();
+        conn.store_secret(1, b"index1", 1000, |_| {
+            Ok((b"meta1".to_vec(), b"pass1".to_vec()))
+        })
+        .unwrap();
+        conn.store_secret(1, b"index1", 1000, |_| {
+            Ok((b"meta2".to_vec(), b"pass2".to_vec()))
+        })
+        .unwrap();
     }
 
     #[test]
@@ -514,21 +3016,31 @@ mod test {
         let username = "test_user";
         let passwd = "test_pass";
         let enc_salt = b"test_salt";
-        conn.create_user(username, passwd, enc_salt).unwrap();
+        conn.create_user(username, &credentials(passwd, enc_salt), POLICY)
+            .unwrap();
 
         let passwd: Vec<u8> = "passwd".into();
-        let label = "pass1".to_string();
-        conn.store_secret(1, Kind::Password, &label, passwd.clone())
+        let index = b"index1";
+        conn.store_secret(1, index, 1000, |_| Ok((b"meta".to_vec(), passwd.clone())))
             .expect("should insert into table");
 
         // check if data is inserted
-        let data = conn.get_secret(1, &label).unwrap();
-        assert_eq!(data, Some((Kind::Password.to_str(), passwd)));
+        let data = conn.get_secret(1, index).unwrap();
+        assert_eq!(
+            data,
+            Some(SecretRow {
+                id: 1,
+                label_index: index.to_vec(),
+                meta: b"meta".to_vec(),
+                data: passwd
+            })
+        );
 
-        conn.delete_secret(1, &label).expect("should delete data");
+        conn.trash_secret(1, index, 2000)
+            .expect("should delete data");
 
         // try to get data again
-        let data = conn.get_secret(1, &label).unwrap();
+        let data = conn.get_secret(1, index).unwrap();
         assert_eq!(data, None);
     }
 
@@ -539,7 +3051,7 @@ mod test {
 
         let conn = DbConn::new(db_path).unwrap();
 
-        let data = conn.get_secret(1, &String::from("data_label")).unwrap();
+        let data = conn.get_secret(1, b"data_label").unwrap();
         assert_eq!(data, None);
     }
 
@@ -554,8 +3066,10 @@ mod test {
         let username2 = "test_user2";
         let passwd_hash = "test_pass";
         let salt = b"salt";
-        conn.create_user(username1, &passwd_hash, salt).unwrap();
-        conn.create_user(username2, &passwd_hash, salt).unwrap();
+        conn.create_user(username1, &credentials(passwd_hash, salt), POLICY)
+            .unwrap();
+        conn.create_user(username2, &credentials(passwd_hash, salt), POLICY)
+            .unwrap();
 
         // check if user is created
         let retrieved_hash = conn.get_user_passwd_hash(&username2).unwrap();
@@ -580,8 +3094,10 @@ mod test {
         let username = "test_user";
         let passwd_hash = "test_pass";
         let salt = b"salt";
-        conn.create_user(username, passwd_hash, salt).unwrap();
-        conn.create_user(username, passwd_hash, salt).unwrap();
+        conn.create_user(username, &credentials(passwd_hash, salt), POLICY)
+            .unwrap();
+        conn.create_user(username, &credentials(passwd_hash, salt), POLICY)
+            .unwrap();
     }
 
     #[test]
@@ -596,17 +3112,25 @@ mod test {
         let passwd_hash = "passwd";
         let salt = b"salt";
 
-        conn.create_user(username1, passwd_hash, salt).unwrap();
-        conn.create_user(username2, passwd_hash, salt).unwrap();
-
-        // check if user is created
-        conn.store_secret(1, Kind::Password, &"sec1".to_string(), b"sec1".to_vec())
-            .unwrap();
-        conn.store_secret(2, Kind::Password, &"sec2".to_string(), b"sec2".to_vec())
+        conn.create_user(username1, &credentials(passwd_hash, salt), POLICY)
             .unwrap();
-        conn.store_secret(2, Kind::Password, &"sec3".to_string(), b"sec3".to_vec())
+        conn.create_user(username2, &credentials(passwd_hash, salt), POLICY)
             .unwrap();
 
+        // check if user is created
+        conn.store_secret(1, b"sec1", 1000, |_| {
+            Ok((b"meta".to_vec(), b"sec1".to_vec()))
+        })
+        .unwrap();
+        conn.store_secret(2, b"sec2", 1000, |_| {
+            Ok((b"meta".to_vec(), b"sec2".to_vec()))
+        })
+        .unwrap();
+        conn.store_secret(2, b"sec3", 1000, |_| {
+            Ok((b"meta".to_vec(), b"sec3".to_vec()))
+        })
+        .unwrap();
+
         let labels1 = conn.get_labels(1).unwrap();
         assert_eq!(labels1.len(), 1);
 
@@ -617,6 +3141,362 @@ mod test {
         assert_eq!(labels3.len(), 0);
     }
 
+    #[test]
+    fn can_store_and_change_user_policy() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let mut conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        let id = conn
+            .store_secret(1, b"index", 1000, |_| {
+                Ok((b"meta".to_vec(), b"aes_data".to_vec()))
+            })
+            .unwrap();
+        assert_eq!(conn.get_user_policy(1).unwrap(), POLICY);
+
+        let secret = SecretRow {
+            id,
+            label_index: b"index".to_vec(),
+            meta: b"meta".to_vec(),
+            data: b"xchacha_data".to_vec(),
+        };
+        let policy = EncryptionPolicy {
+            cipher: CipherId::XChaCha20Poly1305,
+            padding: PaddingId::None,
+        };
+        conn.set_user_policy(1, policy, vec![secret.clone()], vec![])
+            .unwrap();
+
+        assert_eq!(conn.get_user_policy(1).unwrap(), policy);
+        assert_eq!(conn.get_secret(1, b"index").unwrap(), Some(secret));
+
+        // users created before the cipher and padding were stored use AES-256-GCM, and
+        // have their secrets padded from then on
+        conn.get_conn()
+            .unwrap()
+            .execute(
+                "UPDATE users SET cipher = NULL, padding = NULL WHERE user_id = 1;",
+                (),
+            )
+            .unwrap();
+        assert_eq!(conn.get_user_policy(1).unwrap(), POLICY);
+        assert!(conn.get_user_policy(2).is_err());
+    }
+
+    #[test]
+    fn can_store_and_retrieve_kdf_params() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+        assert_eq!(conn.pick_kdf_params("nobody").unwrap(), None);
+
+        let params = KdfParams {
+            m_cost: 65536,
+            t_cost: 4,
+            p_cost: 1,
+        };
+        conn.create_user("old_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        conn.create_user(
+            "test_user",
+            &Credentials {
+                kdf_params: params,
+                ..credentials("test_pass", b"salt")
+            },
+            POLICY,
+        )
+        .unwrap();
+
+        assert_eq!(conn.get_user_kdf_params("test_user").unwrap(), Some(params));
+        assert_eq!(conn.get_user_kdf_params("nobody").unwrap(), None);
+
+        conn.create_user(
+            "new_user",
+            &Credentials {
+                kdf_params: KdfParams {
+                    m_cost: 32768,
+                    t_cost: 6,
+                    p_cost: 1,
+                },
+                ..credentials("test_pass", b"salt")
+            },
+            POLICY,
+        )
+        .unwrap();
+        let all_params = [
+            KdfParams::default(),
+            params,
+            conn.get_user_kdf_params("new_user").unwrap().unwrap(),
+        ];
+        let picked: Vec<KdfParams> = (0..32)
+            .map(|i| {
+                conn.pick_kdf_params(&format!("nobody{i}"))
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+        assert!(all_params.iter().all(|params| picked.contains(params)));
+        assert!(picked.iter().all(|params| all_params.contains(params)));
+        assert_eq!(conn.pick_kdf_params("nobody0").unwrap().unwrap(), picked[0]);
+    }
+
+    #[test]
+    fn can_update_credentials_and_key_file_requirement() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        let key_file = || -> bool {
+            conn.get_conn()
+                .unwrap()
+                .query_row("SELECT key_file FROM users WHERE user_id = 1", [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        assert!(!key_file());
+
+        let updated = Credentials {
+            wrapped_key: b"new_key".to_vec(),
+            key_file: true,
+            ..credentials("new_hash", b"new_salt")
+        };
+        conn.update_user_credentials(1, &updated).unwrap();
+
+        assert!(key_file());
+        assert_eq!(
+            conn.get_user_passwd_hash("test_user").unwrap(),
+            Some("new_hash".to_string())
+        );
+        assert_eq!(
+            conn.get_user_enc_salt("test_user").unwrap(),
+            Some(b"new_salt".to_vec())
+        );
+        assert_eq!(
+            conn.get_user_wrapped_key("test_user").unwrap(),
+            Some(b"new_key".to_vec())
+        );
+    }
+
+    #[test]
+    fn can_record_and_reset_failed_logins() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        assert_eq!(
+            conn.get_login_attempts(1).unwrap(),
+            LoginAttempts::default()
+        );
+
+        conn.record_failed_login(1, 100).unwrap();
+        conn.record_failed_login(1, 200).unwrap();
+        conn.set_lockout_threshold(1, Some(10)).unwrap();
+        assert_eq!(
+            conn.get_login_attempts(1).unwrap(),
+            LoginAttempts {
+                failed: 2,
+                last_failed_at: 200,
+                lockout_after: Some(10),
+            }
+        );
+
+        conn.reset_failed_logins(1).unwrap();
+        conn.set_lockout_threshold(1, None).unwrap();
+        assert_eq!(
+            conn.get_login_attempts(1).unwrap(),
+            LoginAttempts::default()
+        );
+        assert!(conn.get_login_attempts(2).is_err());
+
+        conn.record_unknown_failed_login("nobody", 100).unwrap();
+        conn.record_unknown_failed_login("nobody", 300).unwrap();
+        assert_eq!(
+            conn.get_unknown_login_attempts("nobody").unwrap(),
+            LoginAttempts {
+                failed: 2,
+                last_failed_at: 300,
+                lockout_after: None,
+            }
+        );
+        assert_eq!(
+            conn.get_unknown_login_attempts("somebody").unwrap(),
+            LoginAttempts::default()
+        );
+        conn.create_user("nobody", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        assert_eq!(
+            conn.get_unknown_login_attempts("nobody").unwrap(),
+            LoginAttempts::default()
+        );
+    }
+
+    #[test]
+    fn can_store_and_clear_user_totp() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        assert_eq!(conn.get_user_totp(1).unwrap(), None);
+
+        conn.set_user_totp(1, Some(b"encrypted")).unwrap();
+        assert_eq!(conn.get_user_totp(1).unwrap(), Some(b"encrypted".to_vec()));
+
+        conn.set_user_totp(1, None).unwrap();
+        assert_eq!(conn.get_user_totp(1).unwrap(), None);
+        assert!(conn.get_user_totp(2).is_err());
+    }
+
+    #[test]
+    fn can_store_min_password_score() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        assert_eq!(conn.get_min_password_score(1).unwrap(), None);
+
+        conn.set_min_password_score(1, 4).unwrap();
+        assert_eq!(conn.get_min_password_score(1).unwrap(), Some(4));
+        assert!(conn.get_min_password_score(2).is_err());
+    }
+
+    #[test]
+    fn can_set_vault_key_and_reencrypt_secrets() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let mut conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user(
+            "test_user",
+            &Credentials {
+                wrapped_key: b"old_key".to_vec(),
+                ..credentials("hash", b"salt")
+            },
+            POLICY,
+        )
+        .unwrap();
+        let id = conn
+            .store_secret(1, b"old_index", 1000, |_| {
+                Ok((b"old_meta".to_vec(), b"old_data".to_vec()))
+            })
+            .unwrap();
+
+        let secret = SecretRow {
+            id,
+            label_index: b"new_index".to_vec(),
+            meta: b"new_meta".to_vec(),
+            data: b"new_data".to_vec(),
+        };
+        conn.set_user_vault_key(1, b"new_key", vec![secret.clone()])
+            .unwrap();
+
+        assert_eq!(
+            conn.get_user_wrapped_key("test_user").unwrap(),
+            Some(b"new_key".to_vec())
+        );
+        assert_eq!(conn.get_secret(1, b"old_index").unwrap(), None);
+        assert_eq!(conn.get_secret(1, b"new_index").unwrap(), Some(secret));
+    }
+
+    #[test]
+    fn should_pass_row_id_to_encrypt_when_storing_secret() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let mut conn = DbConn::new(db_path).unwrap();
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+
+        let encrypt = |id: i64| Ok((b"meta".to_vec(), format!("row {id}").into_bytes()));
+        let id1 = conn.store_secret(1, b"index1", 1000, encrypt).unwrap();
+        let id2 = conn.store_secret(1, b"index2", 1000, encrypt).unwrap();
+        assert_ne!(id1, id2);
+
+        // a failing encryption must not leave a row behind
+        assert!(conn
+            .store_secret(1, b"index3", 1000, |_| Err(anyhow!("failed")))
+            .is_err());
+        assert_eq!(conn.get_secret(1, b"index3").unwrap(), None);
+
+        let secrets = conn.get_all_secrets(1).unwrap();
+        assert_eq!(
+            secrets
+                .iter()
+                .map(|s| (s.id, s.data.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (id1, format!("row {id1}").into_bytes()),
+                (id2, format!("row {id2}").into_bytes()),
+            ]
+        );
+
+        let secret = SecretRow {
+            data: b"new_data".to_vec(),
+            ..secrets[1].clone()
+        };
+        conn.update_secrets(1, vec![secret.clone()], vec![])
+            .unwrap();
+        assert_eq!(conn.get_secret(1, b"index2").unwrap(), Some(secret));
+    }
+
+    #[test]
+    fn can_set_and_remove_recovery_key() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("hash", b"salt"), POLICY)
+            .unwrap();
+        assert_eq!(conn.get_user_recovery_key("test_user").unwrap(), None);
+
+        conn.set_user_recovery_key(1, b"recovery_salt", b"recovery_key")
+            .unwrap();
+        assert_eq!(
+            conn.get_user_recovery_key("test_user").unwrap(),
+            Some((b"recovery_salt".to_vec(), b"recovery_key".to_vec()))
+        );
+
+        conn.remove_user_recovery_key(1).unwrap();
+        assert_eq!(conn.get_user_recovery_key("test_user").unwrap(), None);
+    }
+
+    #[test]
+    fn should_add_kdf_columns_to_existing_users_table() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        // a users table as created by older versions
+        let conn = Connection::open(db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (
+                user_id     INTEGER PRIMARY KEY,
+                username    TEXT UNIQUE NOT NULL CHECK(length(username) <= 24),
+                passwd_hash TEXT NOT NULL,
+                enc_salt    BLOB NOT NULL
+            );
+            INSERT INTO users (username, passwd_hash, enc_salt) VALUES ('old_user', 'hash', 'salt');",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let conn = DbConn::new(db_path).unwrap();
+        assert_eq!(
+            conn.get_user_kdf_params("old_user").unwrap(),
+            Some(KdfParams::default())
+        );
+        assert_eq!(conn.get_user_wrapped_key("old_user").unwrap(), None);
+    }
+
     #[test]
     fn can_edit_secret() {
         let test_db = TestDb::new();
@@ -628,37 +3508,379 @@ mod test {
         let master_pass = "test_pass";
         let enc_salt = b"test_salt";
 
-        conn.create_user(username, master_pass, enc_salt).unwrap();
+        conn.create_user(username, &credentials(master_pass, enc_salt), POLICY)
+            .unwrap();
 
         // store data
         let passwd: Vec<u8> = "passwd".into();
-        let label = "pass1".to_string();
-        conn.store_secret(1, Kind::Password, &label, passwd.clone())
+        let index = b"index1";
+        conn.store_secret(1, index, 1000, |_| Ok((b"meta".to_vec(), passwd.clone())))
             .unwrap();
 
         // check if data is first in the db
-        let (kind, data) = conn.get_secret(1, &label).unwrap().unwrap();
-        assert_eq!(&kind, &Kind::Password.to_str());
-        assert_eq!(&data, &passwd);
+        let secret = conn.get_secret(1, index).unwrap().unwrap();
+        assert_eq!(&secret.data, &passwd);
 
         // edit the data
         let new_passwd: Vec<u8> = "new_passwd".into();
-        conn.edit_secret(1, &label, &label, new_passwd.clone())
-            .unwrap();
-        let (kind, data) = conn.get_secret(1, &label).unwrap().unwrap();
-        assert_eq!(&kind, &Kind::Password.to_str());
-        assert_ne!(&data, &passwd);
-        assert_eq!(&data, &new_passwd);
+        conn.edit_secret(
+            1,
+            SecretRow {
+                data: new_passwd.clone(),
+                ..secret.clone()
+            },
+            0,
+            0,
+            |_, replaced| Ok((replaced.meta, replaced.data)),
+        )
+        .unwrap();
+        let secret = conn.get_secret(1, index).unwrap().unwrap();
+        assert_eq!(&secret.meta, b"meta");
+        assert_ne!(&secret.data, &passwd);
+        assert_eq!(&secret.data, &new_passwd);
 
         // edit the label and the password
-        let new_label = "pass2".to_string();
-        conn.edit_secret(1, &label, &new_label, passwd.clone())
-            .unwrap();
-        let (kind, data) = conn.get_secret(1, &new_label).unwrap().unwrap();
-        let old_label = conn.get_secret(1, &label).unwrap();
-        assert_eq!(&kind, &Kind::Password.to_str());
-        assert_ne!(&data, &new_passwd);
-        assert_eq!(&data, &passwd);
+        let new_index = b"index2";
+        conn.edit_secret(
+            1,
+            SecretRow {
+                id: secret.id,
+                label_index: new_index.to_vec(),
+                meta: b"new_meta".to_vec(),
+                data: passwd.clone(),
+            },
+            0,
+            0,
+            |_, replaced| Ok((replaced.meta, replaced.data)),
+        )
+        .unwrap();
+        let secret = conn.get_secret(1, new_index).unwrap().unwrap();
+        let old_label = conn.get_secret(1, index).unwrap();
+        assert_eq!(&secret.meta, b"new_meta");
+        assert_ne!(&secret.data, &new_passwd);
+        assert_eq!(&secret.data, &passwd);
         assert_eq!(old_label, None);
+
+        // another secret cannot take the same label
+        conn.store_secret(1, index, 1000, |_| Ok((b"meta".to_vec(), passwd.clone())))
+            .unwrap();
+        let other = conn.get_secret(1, index).unwrap().unwrap();
+        assert!(conn
+            .edit_secret(
+                1,
+                SecretRow {
+                    label_index: new_index.to_vec(),
+                    ..other
+                },
+                0,
+                0,
+                |_, replaced| Ok((replaced.meta, replaced.data)),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn can_keep_secret_versions() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        conn.create_user("other_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        let index = b"index1";
+        let id = conn
+            .store_secret(1, index, 1000, |_| {
+                Ok((b"meta0".to_vec(), b"data0".to_vec()))
+            })
+            .unwrap();
+        let edit = |n: u64, history_limit: u32| {
+            conn.edit_secret(
+                1,
+                SecretRow {
+                    id,
+                    label_index: index.to_vec(),
+                    meta: format!("meta{n}").into_bytes(),
+                    data: format!("data{n}").into_bytes(),
+                },
+                history_limit,
+                1000 + n,
+                |version_id, replaced| {
+                    assert_eq!(replaced.id, id);
+                    Ok((
+                        replaced.meta,
+                        [replaced.data, version_id.to_be_bytes().to_vec()].concat(),
+                    ))
+                },
+            )
+        };
+
+        for n in 1..=3 {
+            edit(n, 2).unwrap();
+        }
+        let versions = conn.get_secret_versions(1, id).unwrap();
+        assert_eq!(
+            versions
+                .iter()
+                .map(|version| (version.data.clone(), version.replaced_at))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    [&b"data2"[..], &versions[0].id.to_be_bytes()].concat(),
+                    1003
+                ),
+                (
+                    [&b"data1"[..], &versions[1].id.to_be_bytes()].concat(),
+                    1002
+                )
+            ]
+        );
+        assert!(!versions[0].legacy_context);
+        assert_eq!(
+            conn.get_secret_version(1, id, versions[1].id).unwrap(),
+            Some(versions[1].clone())
+        );
+        assert_eq!(
+            conn.get_secret_version(2, id, versions[1].id).unwrap(),
+            None
+        );
+        assert!(conn.get_secret_versions(2, id).unwrap().is_empty());
+
+        // a failed edit keeps no version
+        let other_index = b"index2";
+        conn.store_secret(1, other_index, 1000, |_| {
+            Ok((b"meta".to_vec(), b"data".to_vec()))
+        })
+        .unwrap();
+        assert!(conn
+            .edit_secret(
+                1,
+                SecretRow {
+                    id,
+                    label_index: other_index.to_vec(),
+                    meta: b"meta".to_vec(),
+                    data: b"data".to_vec(),
+                },
+                2,
+                2000,
+                |_, replaced| Ok((replaced.meta, replaced.data)),
+            )
+            .is_err());
+        assert_eq!(conn.get_secret_versions(1, id).unwrap(), versions);
+
+        assert_eq!(conn.get_history_limit(1).unwrap(), None);
+        conn.set_history_limit(1, 1).unwrap();
+        assert_eq!(conn.get_history_limit(1).unwrap(), Some(1));
+        assert_eq!(conn.get_secret_versions(1, id).unwrap(), versions[..1]);
+
+        edit(4, 0).unwrap();
+        assert!(conn.get_secret_versions(1, id).unwrap().is_empty());
+        edit(5, 1).unwrap();
+        conn.trash_secret(1, index, 3000).unwrap();
+        assert_eq!(conn.get_secret_versions(1, id).unwrap().len(), 1);
+        conn.purge_trash(1, 3000).unwrap();
+        let count: i64 = conn
+            .get_conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM secret_versions;", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn can_reencrypt_trashed_secrets_and_versions() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let mut conn = DbConn::new(db_path).unwrap();
+
+        conn.create_user("test_user", &credentials("test_pass", b"salt"), POLICY)
+            .unwrap();
+        let secret = |index: &[u8]| {
+            conn.store_secret(1, index, 1000, |_| Ok((b"meta".to_vec(), b"data".to_vec())))
+                .unwrap()
+        };
+        let live = secret(b"index1");
+        let trashed = secret(b"index2");
+        let row = conn.get_secret(1, b"index1").unwrap().unwrap();
+        conn.edit_secret(1, row, 1, 2000, |_, replaced| {
+            Ok((replaced.meta, replaced.data))
+        })
+        .unwrap();
+        conn.trash_secret(1, b"index2", 3000).unwrap();
+        conn.get_conn()
+            .unwrap()
+            .execute("UPDATE secret_versions SET legacy_context = 1;", [])
+            .unwrap();
+
+        let trash = conn.get_trashed_secrets(1).unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].id, trashed);
+        assert!(trash[0].label_index.is_empty());
+        let versions = conn.get_all_secret_versions(1).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].secret_id, live);
+        assert!(versions[0].legacy_context);
+
+        conn.update_secrets(
+            1,
+            vec![SecretRow {
+                label_index: b"index3".to_vec(),
+                meta: b"new meta".to_vec(),
+                ..trash[0].clone()
+            }],
+            vec![SecretVersionRow {
+                data: b"new data".to_vec(),
+                ..versions[0].clone()
+            }],
+        )
+        .unwrap();
+        assert_eq!(conn.get_trashed_secrets(1).unwrap()[0].meta, b"new meta");
+        assert_eq!(conn.get_secret(1, b"index3").unwrap(), None);
+        let version = &conn.get_all_secret_versions(1).unwrap()[0];
+        assert_eq!(version.data, b"new data");
+        assert!(!version.legacy_context);
+    }
+
+    #[test]
+    fn should_move_plaintext_labels_to_legacy_columns() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        // a secrets table as created before labels and kinds were encrypted
+        let conn = Connection::open(db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (
+                user_id     INTEGER PRIMARY KEY,
+                username    TEXT UNIQUE NOT NULL CHECK(length(username) <= 24),
+                passwd_hash TEXT NOT NULL,
+                enc_salt    BLOB NOT NULL
+            );
+            INSERT INTO users (username, passwd_hash, enc_salt) VALUES ('old_user', 'hash', 'salt');
+            CREATE TABLE secrets (
+                id          INTEGER PRIMARY KEY,
+                user_id     INTEGER,
+                kind        TEXT NOT NULL CHECK(kind IN ('text', 'password')),
+                label       TEXT NOT NULL CHECK(length(label) >= 3 AND length(label) <= 32),
+                data        BLOB NOT NULL CHECK(length(data) > 3)
+            );
+            INSERT INTO secrets (user_id, kind, label, data) VALUES (1, 'text', 'notes', x'64617461');",
+        )
+        .unwrap();
+        conn.close().unwrap();
+
+        let mut conn = DbConn::new(db_path).unwrap();
+        assert_eq!(
+            conn.get_legacy_secrets(1).unwrap(),
+            vec![LegacySecretRow {
+                id: 1,
+                kind: Kind::Text.to_str(),
+                label: "notes".to_string(),
+                data: b"data".to_vec(),
+            }]
+        );
+        assert!(conn.get_all_secrets(1).unwrap().is_empty());
+        assert!(!column_exists(conn.get_conn().unwrap(), "secrets", "label").unwrap());
+        assert!(index_exists(
+            conn.get_conn().unwrap(),
+            "idx_secrets_user_id"
+        ));
+
+        let secret = SecretRow {
+            id: 1,
+            label_index: b"index".to_vec(),
+            meta: b"meta".to_vec(),
+            data: b"data".to_vec(),
+        };
+        conn.update_secrets(1, vec![secret.clone()], vec![])
+            .unwrap();
+        assert!(conn.get_legacy_secrets(1).unwrap().is_empty());
+        assert_eq!(conn.get_all_secrets(1).unwrap(), vec![secret]);
+    }
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version;", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn index_exists(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = ?1;",
+            [name],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    #[test]
+    fn can_migrate_new_database() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let conn = DbConn::new(db_path).unwrap();
+        assert_eq!(user_version(conn.get_conn().unwrap()), MIGRATIONS.len());
+        assert!(index_exists(
+            conn.get_conn().unwrap(),
+            "idx_secrets_user_id"
+        ));
+
+        let vault_id = conn.get_meta("vault_id").unwrap().unwrap();
+        assert_eq!(vault_id.len(), 32);
+        assert_eq!(
+            conn.get_meta("created_by").unwrap().as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(conn.get_meta("nothing").unwrap(), None);
+        drop(conn);
+
+        let conn = DbConn::new(db_path).unwrap();
+        assert_eq!(conn.get_meta("vault_id").unwrap(), Some(vault_id));
+        assert_ne!(
+            DbConn::new(TestDb::new().get_path())
+                .unwrap()
+                .get_meta("vault_id")
+                .unwrap(),
+            conn.get_meta("vault_id").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_roll_back_failed_migration() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let mut conn = Connection::open(db_path).unwrap();
+
+        let migrations: &[Migration] = &[create_base_schema, |conn| {
+            conn.execute("CREATE TABLE half_done (id INTEGER);", ())?;
+            Err(anyhow!("migration failed"))
+        }];
+        assert!(run_migrations(&mut conn, migrations).is_err());
+        assert_eq!(user_version(&conn), 1);
+        assert!(column_exists(&conn, "users", "username").unwrap());
+        assert!(!column_exists(&conn, "half_done", "id").unwrap());
+
+        run_migrations(&mut conn, &migrations[..1]).unwrap();
+        assert_eq!(user_version(&conn), 1);
+    }
+
+    #[test]
+    fn should_refuse_database_from_newer_version() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let conn = Connection::open(db_path).unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        conn.close().unwrap();
+
+        let err = DbConn::new(db_path).err().unwrap();
+        assert!(err.to_string().contains("newer version of Lockkey"));
+
+        let conn = Connection::open(db_path).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len() + 1);
+        assert!(!column_exists(&conn, "users", "username").unwrap());
     }
 }
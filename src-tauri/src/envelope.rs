@@ -0,0 +1,355 @@
+use anyhow::{anyhow, Result};
+
+const MAGIC: &[u8; 2] = b"LK"; // marks a versioned ciphertext envelope
+const UNBOUND_VERSION: u8 = 1; // envelopes whose ciphertext is not bound to a context
+const PADDED_VERSION: u8 = 3; // first version recording how the plaintext was padded
+pub const VERSION: u8 = 3; // current envelope format version, bound to a context and padded
+
+/// Identifies the AEAD cipher used to produce the ciphertext in an envelope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CipherId {
+    /// AES-256-GCM with a 96-bit nonce, fast on CPUs with AES instructions.
+    Aes256Gcm,
+    /// XChaCha20-Poly1305 with a 192-bit nonce, fast everywhere and safe with random nonces
+    /// for any number of messages.
+    XChaCha20Poly1305,
+}
+
+impl CipherId {
+    /// Converts a byte from an envelope header to a `CipherId`.
+    ///
+    /// # Arguments
+    /// * `id` - The cipher id byte.
+    ///
+    /// # Returns
+    /// * `Result<CipherId>` - The cipher id, or an error if it is unknown.
+    pub fn from_byte(id: u8) -> Result<CipherId> {
+        match id {
+            1 => Ok(CipherId::Aes256Gcm),
+            2 => Ok(CipherId::XChaCha20Poly1305),
+            _ => Err(anyhow!("unknown cipher id: {id}")),
+        }
+    }
+
+    /// Converts the `CipherId` to the byte written in an envelope header.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            CipherId::Aes256Gcm => 1,
+            CipherId::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    /// Converts a cipher name, as used by the settings of a vault, to a `CipherId`.
+    ///
+    /// # Arguments
+    /// * `name` - The cipher name, `aes-256-gcm` or `xchacha20-poly1305`.
+    ///
+    /// # Returns
+    /// * `Result<CipherId>` - The cipher id, or an error if the name is unknown.
+    pub fn from_name(name: &str) -> Result<CipherId> {
+        match name {
+            "aes-256-gcm" => Ok(CipherId::Aes256Gcm),
+            "xchacha20-poly1305" => Ok(CipherId::XChaCha20Poly1305),
+            _ => Err(anyhow!("unknown cipher: {name:?}")),
+        }
+    }
+
+    /// Converts the `CipherId` to its name.
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherId::Aes256Gcm => "aes-256-gcm",
+            CipherId::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+
+    /// Returns the nonce length in bytes used by the cipher.
+    pub fn nonce_len(self) -> usize {
+        match self {
+            CipherId::Aes256Gcm => 12,
+            CipherId::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+/// Identifies the KDF used to derive the cipher key of an envelope from the encryption key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KdfId {
+    HkdfSha256,
+}
+
+impl KdfId {
+    /// Converts a byte from an envelope header to a `KdfId`.
+    ///
+    /// # Arguments
+    /// * `id` - The KDF id byte.
+    ///
+    /// # Returns
+    /// * `Result<KdfId>` - The KDF id, or an error if it is unknown.
+    pub fn from_byte(id: u8) -> Result<KdfId> {
+        match id {
+            1 => Ok(KdfId::HkdfSha256),
+            _ => Err(anyhow!("unknown kdf id: {id}")),
+        }
+    }
+
+    /// Converts the `KdfId` to the byte written in an envelope header.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            KdfId::HkdfSha256 => 1,
+        }
+    }
+}
+
+/// Identifies how the plaintext of an envelope was padded before encryption.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaddingId {
+    /// The plaintext is encrypted as is, so the ciphertext reveals its exact length.
+    None,
+    /// The plaintext is padded to a Padmé length of at least 32 bytes.
+    Padme,
+}
+
+impl PaddingId {
+    /// Converts a byte from an envelope header to a `PaddingId`.
+    ///
+    /// # Arguments
+    /// * `id` - The padding id byte.
+    ///
+    /// # Returns
+    /// * `Result<PaddingId>` - The padding id, or an error if it is unknown.
+    pub fn from_byte(id: u8) -> Result<PaddingId> {
+        match id {
+            0 => Ok(PaddingId::None),
+            1 => Ok(PaddingId::Padme),
+            _ => Err(anyhow!("unknown padding id: {id}")),
+        }
+    }
+
+    /// Converts the `PaddingId` to the byte written in an envelope header.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            PaddingId::None => 0,
+            PaddingId::Padme => 1,
+        }
+    }
+
+    /// Converts a padding name, as used by the settings of a vault, to a `PaddingId`.
+    ///
+    /// # Arguments
+    /// * `name` - The padding name, `none` or `padme`.
+    ///
+    /// # Returns
+    /// * `Result<PaddingId>` - The padding id, or an error if the name is unknown.
+    pub fn from_name(name: &str) -> Result<PaddingId> {
+        match name {
+            "none" => Ok(PaddingId::None),
+            "padme" => Ok(PaddingId::Padme),
+            _ => Err(anyhow!("unknown padding: {name:?}")),
+        }
+    }
+
+    /// Converts the `PaddingId` to its name.
+    pub fn name(self) -> &'static str {
+        match self {
+            PaddingId::None => "none",
+            PaddingId::Padme => "padme",
+        }
+    }
+}
+
+/// A self-describing ciphertext as stored in the database.
+///
+/// The layout is `magic || version || cipher id || kdf id || padding id || salt length ||
+/// salt || nonce || ciphertext`, where the salt is the KDF parameter and the nonce length is
+/// defined by the cipher. Everything before the ciphertext is the header, which is
+/// authenticated as associated data so that it cannot be altered without failing decryption.
+///
+/// Since version 2 the associated data also includes a caller supplied context, such as the
+/// row a secret is stored in. Version 1 envelopes only authenticate their header. The padding
+/// id was added in version 3; older envelopes have no padding id and are never padded.
+#[derive(Debug, PartialEq)]
+pub struct Envelope {
+    pub version: u8,
+    pub cipher: CipherId,
+    pub kdf: KdfId,
+    pub padding: PaddingId,
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl Envelope {
+    /// Parses an envelope from its serialized bytes.
+    ///
+    /// # Arguments
+    /// * `bytes` - The serialized envelope.
+    ///
+    /// # Returns
+    /// * `Result<Envelope>` - The parsed envelope, or an error if the bytes are not a valid envelope.
+    pub fn parse(bytes: &[u8]) -> Result<Envelope> {
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| anyhow!("not a ciphertext envelope"))?;
+        let (&version, rest) = rest
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated envelope"))?;
+        if !(UNBOUND_VERSION..=VERSION).contains(&version) {
+            return Err(anyhow!("unsupported envelope version: {version}"));
+        }
+
+        let [cipher, kdf, rest @ ..] = rest else {
+            return Err(anyhow!("truncated envelope"));
+        };
+        let cipher = CipherId::from_byte(*cipher)?;
+        let kdf = KdfId::from_byte(*kdf)?;
+        let (padding, rest) = match version >= PADDED_VERSION {
+            true => match rest.split_first() {
+                Some((padding, rest)) => (PaddingId::from_byte(*padding)?, rest),
+                None => return Err(anyhow!("truncated envelope")),
+            },
+            false => (PaddingId::None, rest),
+        };
+        let (&salt_len, rest) = rest
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated envelope"))?;
+        let salt_len = salt_len as usize;
+
+        if rest.len() <= salt_len + cipher.nonce_len() {
+            return Err(anyhow!("truncated envelope"));
+        }
+        let (salt, rest) = rest.split_at(salt_len);
+        let (nonce, ciphertext) = rest.split_at(cipher.nonce_len());
+
+        Ok(Envelope {
+            version,
+            cipher,
+            kdf,
+            padding,
+            salt: salt.to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+
+    /// Returns the header bytes of the envelope, i.e. everything before the ciphertext.
+    pub fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(MAGIC.len() + 5 + self.salt.len() + self.nonce.len());
+        header.extend_from_slice(MAGIC);
+        header.push(self.version);
+        header.push(self.cipher.to_byte());
+        header.push(self.kdf.to_byte());
+        if self.version >= PADDED_VERSION {
+            header.push(self.padding.to_byte());
+        }
+        header.push(self.salt.len() as u8);
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(&self.nonce);
+        header
+    }
+
+    /// Returns whether the ciphertext is bound to a context in addition to its header.
+    pub fn is_bound(&self) -> bool {
+        self.version > UNBOUND_VERSION
+    }
+
+    /// Serializes the envelope into the bytes stored in the database.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.header(), self.ciphertext.clone()].concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that an envelope survives a serialization round trip.
+    #[test]
+    fn can_serialize_and_parse_envelope() {
+        let envelope = Envelope {
+            version: VERSION,
+            cipher: CipherId::Aes256Gcm,
+            kdf: KdfId::HkdfSha256,
+            padding: PaddingId::Padme,
+            salt: vec![1; 16],
+            nonce: vec![2; 12],
+            ciphertext: vec![3; 20],
+        };
+
+        let bytes = envelope.to_bytes();
+
+        assert_eq!(&bytes[..6], b"LK\x03\x01\x01\x01");
+        assert_eq!(Envelope::parse(&bytes).unwrap(), envelope);
+        assert!(envelope.is_bound());
+    }
+
+    /// Tests that envelopes written before the padding id was added still parse, as unpadded.
+    #[test]
+    fn can_parse_envelopes_without_padding_id() {
+        for version in [UNBOUND_VERSION, 2] {
+            let envelope = Envelope {
+                version,
+                cipher: CipherId::Aes256Gcm,
+                kdf: KdfId::HkdfSha256,
+                padding: PaddingId::None,
+                salt: vec![1; 16],
+                nonce: vec![2; 12],
+                ciphertext: vec![3; 20],
+            };
+
+            let bytes = envelope.to_bytes();
+
+            assert_eq!(&bytes[..6], [b'L', b'K', version, 1, 1, 16]);
+            assert_eq!(Envelope::parse(&bytes).unwrap(), envelope);
+            assert_eq!(envelope.is_bound(), version > UNBOUND_VERSION);
+        }
+    }
+
+    /// Tests that the nonce length read from an envelope depends on its cipher.
+    #[test]
+    fn can_parse_envelope_with_extended_nonce() {
+        let envelope = Envelope {
+            version: VERSION,
+            cipher: CipherId::XChaCha20Poly1305,
+            kdf: KdfId::HkdfSha256,
+            padding: PaddingId::None,
+            salt: vec![1; 16],
+            nonce: vec![2; 24],
+            ciphertext: vec![3; 20],
+        };
+
+        let bytes = envelope.to_bytes();
+
+        assert_eq!(bytes[3], 2);
+        assert_eq!(Envelope::parse(&bytes).unwrap(), envelope);
+        // the same bytes read as AES-256-GCM would split the nonce differently
+        let as_aes = [&bytes[..3], &[1], &bytes[4..]].concat();
+        assert_eq!(Envelope::parse(&as_aes).unwrap().nonce, vec![2; 12]);
+    }
+
+    /// Tests that cipher and padding names round trip and unknown ones are rejected.
+    #[test]
+    fn can_convert_cipher_and_padding_names() {
+        for cipher in [CipherId::Aes256Gcm, CipherId::XChaCha20Poly1305] {
+            assert_eq!(CipherId::from_name(cipher.name()).unwrap(), cipher);
+            assert_eq!(CipherId::from_byte(cipher.to_byte()).unwrap(), cipher);
+        }
+        assert!(CipherId::from_name("rot13").is_err());
+
+        for padding in [PaddingId::None, PaddingId::Padme] {
+            assert_eq!(PaddingId::from_name(padding.name()).unwrap(), padding);
+            assert_eq!(PaddingId::from_byte(padding.to_byte()).unwrap(), padding);
+        }
+        assert!(PaddingId::from_name("zeros").is_err());
+    }
+
+    /// Tests that malformed or unknown envelopes are rejected.
+    #[test]
+    fn should_reject_invalid_envelopes() {
+        assert!(Envelope::parse(b"not an envelope").is_err());
+        assert!(Envelope::parse(b"LK\x04\x01\x01\x00aaaaaaaaaaaaaaaa").is_err());
+        assert!(Envelope::parse(b"LK\x01\x09\x01\x00aaaaaaaaaaaaaaaa").is_err());
+        assert!(Envelope::parse(b"LK\x01\x01\x01\x10aaaa").is_err());
+        assert!(Envelope::parse(b"LK\x03\x01\x01\x09\x00aaaaaaaaaaaaaaaa").is_err());
+        assert!(Envelope::parse(b"LK\x03\x01\x01").is_err());
+    }
+}
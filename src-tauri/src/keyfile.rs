@@ -0,0 +1,102 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::Rng;
+use sha2::{digest::generic_array::GenericArray, Digest, Sha256};
+use zeroize::Zeroizing;
+
+const KEY_FILE_KEY_LEN: usize = 32; // bytes of randomness in a generated key file
+const KEY_FILE_HEADER: &str = "lockkey key file v1"; // first line of a generated key file
+
+/// Generates a new key file: a header line followed by 32 random bytes encoded in base64.
+/// Any other file, such as a photo, can be used as a key file as well.
+///
+/// # Returns
+/// * `Zeroizing<Vec<u8>>` - The contents of the key file, wiped from memory when dropped.
+pub fn create_key_file() -> Zeroizing<Vec<u8>> {
+    let key = Zeroizing::new(rand::thread_rng().gen::<[u8; KEY_FILE_KEY_LEN]>());
+    let encoded = Zeroizing::new(BASE64.encode(*key));
+    Zeroizing::new(format!("{KEY_FILE_HEADER}\n{}\n", *encoded).into_bytes())
+}
+
+/// Reduces a key file to the 32-byte value that is mixed into the key derivation.
+/// Key files generated by `create_key_file` yield the key they contain, so that
+/// re-encoding their line endings does not change them; any other file yields its SHA-256
+/// hash.
+///
+/// # Arguments
+/// * `key_file` - The contents of the key file.
+///
+/// # Returns
+/// * `Result<Zeroizing<[u8; 32]>>` - The key file hash, or an error if the file is empty.
+pub fn hash_key_file(key_file: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    if key_file.is_empty() {
+        return Err(anyhow!("the key file is empty"));
+    }
+    if let Some(key) = parse_key_file(key_file) {
+        return Ok(key);
+    }
+
+    let mut hash = Zeroizing::new([0u8; 32]);
+    Sha256::new()
+        .chain_update(key_file)
+        .finalize_into(GenericArray::from_mut_slice(&mut *hash));
+    Ok(hash)
+}
+
+/// Extracts the key of a key file generated by `create_key_file`.
+///
+/// # Arguments
+/// * `key_file` - The contents of the key file.
+///
+/// # Returns
+/// * `Option<Zeroizing<[u8; 32]>>` - The key, or `None` if the file was not generated by Lockkey.
+fn parse_key_file(key_file: &[u8]) -> Option<Zeroizing<[u8; 32]>> {
+    let text = std::str::from_utf8(key_file).ok()?;
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next()? != KEY_FILE_HEADER {
+        return None;
+    }
+    let encoded = lines.next()?;
+    if lines.next().is_some() {
+        return None;
+    }
+
+    let mut key = Zeroizing::new([0u8; KEY_FILE_KEY_LEN]);
+    match BASE64.decode_slice(encoded, &mut *key) {
+        Ok(KEY_FILE_KEY_LEN) => Some(key),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that generated key files are random and yield the key they contain, whatever
+    /// their line endings.
+    #[test]
+    fn can_create_and_hash_key_file() {
+        let key_file = create_key_file();
+        assert_ne!(*key_file, *create_key_file());
+        assert!(key_file.starts_with(KEY_FILE_HEADER.as_bytes()));
+
+        let hash = hash_key_file(&key_file).unwrap();
+        let windows = String::from_utf8(key_file.to_vec())
+            .unwrap()
+            .replace('\n', "\r\n");
+        assert_eq!(hash_key_file(windows.as_bytes()).unwrap(), hash);
+        assert_ne!(*hash, *Sha256::digest(&*key_file));
+    }
+
+    /// Tests that arbitrary files are hashed with SHA-256, and that empty files are refused.
+    #[test]
+    fn should_hash_arbitrary_files() {
+        assert_eq!(*hash_key_file(b"abc").unwrap(), *Sha256::digest(b"abc"));
+        let truncated = format!("{KEY_FILE_HEADER}\nAAAA\n");
+        assert_eq!(
+            *hash_key_file(truncated.as_bytes()).unwrap(),
+            *Sha256::digest(truncated.as_bytes())
+        );
+        assert!(hash_key_file(b"").is_err());
+    }
+}
@@ -1,35 +1,238 @@
 mod auth;
 mod data;
 mod encryption;
+mod envelope;
+mod keyfile;
+mod padding;
+mod recovery;
+mod strength;
+mod throttle;
+mod totp;
 
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::time::Instant;
 
 use anyhow::{anyhow, Result};
-use auth::{hash_password, verify_passwd};
-use data::{Kind, RetrieveLabelsQueryResult};
-use zeroize::Zeroize;
+use auth::{check_verifier, encode_verifier, is_legacy_hash, verify_passwd};
+use data::{Credentials, Kind, SecretRow, SecretVersionRow};
+use unicode_normalization::UnicodeNormalization;
+use zeroize::{Zeroize, Zeroizing};
 
 use crate::data::DbConn;
 use crate::encryption::*;
+use crate::keyfile::hash_key_file;
+use crate::recovery::{derive_recovery_kek, generate_recovery_key};
+use crate::strength::check_strength;
+use crate::throttle::{check_login_allowed, check_not_locked_out, unix_now};
+use crate::totp::TotpState;
 
-/// Represents a user session, including the user's ID, encryption key,
-/// database connection, and timestamp of the last activity.
+pub use crate::data::{FolderDeletion, SecretTimestamps};
+pub use crate::encryption::{KdfParams, KDF_TARGET_DURATION, MIN_KDF_PARAMS};
+pub use crate::envelope::{CipherId, PaddingId};
+pub use crate::keyfile::create_key_file;
+pub use crate::strength::{
+    estimate_strength, PasswordStrength, WeakPasswordError, MAX_PASSWORD_SCORE, MIN_PASSWORD_SCORE,
+};
+pub use crate::throttle::{LoginError, MIN_LOCKOUT_THRESHOLD};
+pub use crate::totp::TotpEnrollment;
+
+const USERNAME_MAX_LEN: usize = 24; // maximum username length in characters
+const LABEL_MIN_LEN: usize = 3; // minimum label length in characters
+const LABEL_MAX_LEN: usize = 32; // maximum label length in characters
+const REDACTED: &str = "[REDACTED]"; // shown in place of secret data in debug output
+const MIN_PIN_LEN: usize = 4; // minimum quick-unlock PIN length in characters
+pub const MAX_PIN_ATTEMPTS: u32 = 3; // wrong PINs before a locked session is destroyed
+const DEFAULT_HISTORY_LIMIT: u32 = 10; // earlier versions kept per secret by default
+const NAME_MAX_LEN: usize = 32; // maximum folder and tag name length in characters
+const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30; // days deleted secrets stay in the trash by default
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Represents a user session, including the user's ID, vault key, the encryption policy of the
+/// vault, the hash of the key file it was unlocked with, a two-factor enrolment awaiting
+/// confirmation, the key derived from the quick-unlock PIN, database connection, and timestamp
+/// of the last activity.
 pub struct Session {
     user_id: i64,
-    key: [u8; 32],
+    key: Zeroizing<[u8; 32]>,
+    policy: EncryptionPolicy,
+    key_file: Option<Zeroizing<[u8; 32]>>,
+    pending_totp: Option<TotpState>,
+    pin_key: Option<PinKey>,
     db_conn: DbConn,
     pub last_activity: Instant,
 }
 
-/// Represents a stored secret, containing a label, encrypted data, and its type.
-#[derive(serde::Serialize, Debug, PartialEq)]
+/// Represents a session locked after inactivity, which the quick-unlock PIN reopens without
+/// the master password. Only the vault key and key file hash wrapped by a key derived from
+/// the PIN are kept, and only in memory, so the PIN no longer works once the app restarts.
+/// After `MAX_PIN_ATTEMPTS` wrong PINs the wrapped keys are destroyed.
+pub struct LockedSession {
+    user_id: i64,
+    policy: EncryptionPolicy,
+    pin_salt: [u8; 16],
+    wrapped_keys: Option<(Vec<u8>, Option<Vec<u8>>)>,
+    attempts_left: u32,
+    db_conn: DbConn,
+}
+
+/// Key derived from the quick-unlock PIN, with the salt it was derived with.
+struct PinKey {
+    key: Zeroizing<[u8; 32]>,
+    salt: [u8; 16],
+}
+
+/// Represents a stored secret, containing a label, decrypted data, and its type.
+/// The data is wiped from memory when the secret is dropped and redacted from `Debug` output.
+#[derive(serde::Serialize, PartialEq)]
 pub struct Secret {
     label: String,
-    data: String,
+    data: Zeroizing<String>,
+    kind: String,
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Secret")
+            .field("label", &self.label)
+            .field("data", &REDACTED)
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+/// Represents a stored secret with a binary payload, containing a label, the decrypted
+/// bytes, its type and the encoding of the payload.
+/// The bytes are wiped from memory when the secret is dropped and redacted from `Debug` output.
+#[derive(PartialEq)]
+pub struct SecretBytes {
+    pub label: String,
+    pub data: Zeroizing<Vec<u8>>,
+    pub kind: String,
+    pub encoding: Encoding,
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretBytes")
+            .field("label", &self.label)
+            .field("data", &REDACTED)
+            .field("kind", &self.kind)
+            .field("encoding", &self.encoding)
+            .finish()
+    }
+}
+
+/// Represents the label, kind, payload encoding, folder, tags and timestamps of a stored
+/// secret, without its data.
+pub struct RetrieveLabelsQueryResult {
+    pub kind: String,
+    pub label: String,
+    pub encoding: Encoding,
+    pub folder_id: Option<i64>,
+    pub tags: Vec<String>,
+    pub timestamps: SecretTimestamps,
+}
+
+/// Restricts the secrets listed by `list_labels` to those directly in a folder, `None` being
+/// the root, or to those with a tag.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelFilter {
+    Folder(Option<i64>),
+    Tag(String),
+}
+
+/// Represents a folder: its ID, the folder it is in, `None` for the root, and its name.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Folder {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub name: String,
+}
+
+/// The order secrets are listed in by `retrieve_sorted_labels`. Apart from the label order,
+/// the most recent secrets come first and those without the timestamp last.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelOrder {
+    /// By label, ignoring case.
+    Label,
+    /// By the time the secret was stored.
+    Created,
+    /// By the time the secret was last edited.
+    Updated,
+    /// By the time the secret was last read.
+    LastUsed,
+}
+
+/// Represents an earlier version of a secret: the ID it is restored by, the label it had, and
+/// when it was replaced, in seconds since the Unix epoch.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct SecretVersion {
+    pub id: i64,
+    pub label: String,
+    pub replaced_at: u64,
+}
+
+/// Represents a secret in the trash: the ID it is restored by, its label and kind, and when it
+/// was deleted, in seconds since the Unix epoch.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct TrashedSecret {
+    pub id: i64,
+    pub label: String,
+    pub kind: String,
+    pub deleted_at: u64,
+}
+
+/// Identifies the vault database: a random ID generated when it was created, and the version
+/// of Lockkey that created it.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct VaultInfo {
+    pub vault_id: String,
+    pub created_by: String,
+}
+
+/// The encoding of a secret's payload, recorded in its metadata.
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// UTF-8 text, stored and retrieved with the text API.
+    #[default]
+    Utf8,
+    /// Arbitrary bytes such as key files or DER certificates.
+    Binary,
+}
+
+/// The label, kind and payload encoding of a secret, which are stored encrypted next to its
+/// data. Metadata stored before encodings were recorded only describes text secrets.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SecretMeta {
+    label: String,
     kind: String,
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+/// A secret decrypted from any format it may be stored in, used when re-encrypting secrets.
+struct DecryptedSecret {
+    id: i64,
+    meta: SecretMeta,
+    data: Zeroizing<Vec<u8>>,
+    outdated: bool,
 }
 
 /// Creates a new user account by storing a username and password in the database.
+/// The password must reach `MIN_PASSWORD_SCORE`, otherwise a `WeakPasswordError` explains why
+/// it was refused. The Argon2 parameters for the account are calibrated on this machine so
+/// that unlocking takes roughly `KDF_TARGET_DURATION` per derivation. A random vault key is
+/// generated for the account and stored wrapped by the key derived from the password. Secrets
+/// are encrypted with AES-256-GCM if this machine has AES instructions, and XChaCha20-Poly1305
+/// otherwise. Usernames must be at most 24 characters long; a taken one gives a uniform error
+/// after the same Argon2 work as a successful signup.
 ///
 /// # Arguments
 /// * `username` - The username of the new account.
@@ -38,56 +241,404 @@ pub struct Secret {
 ///
 /// # Returns
 /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
-pub fn create_new_account(username: &str, mut passwd: String, db_path: &str) -> Result<()> {
+pub fn create_new_account(username: &str, passwd: String, db_path: &str) -> Result<()> {
+    create_account(username, passwd, None, db_path, false)?;
+    Ok(())
+}
+
+/// Creates a new user account like `create_new_account`, and also generates a recovery key
+/// that can unlock the vault if the master password is lost.
+///
+/// # Arguments
+/// * `username` - The username of the new account.
+/// * `passwd` - The password associated with the account (will be hashed and zeroized after use).
+/// * `db_path` - The path to the database where user data is stored.
+///
+/// # Returns
+/// * `Result<Zeroizing<String>>` - The printable recovery key, which is not stored and must be shown to the user.
+pub fn create_new_account_with_recovery_key(
+    username: &str,
+    passwd: String,
+    db_path: &str,
+) -> Result<Zeroizing<String>> {
+    match create_account(username, passwd, None, db_path, true)? {
+        Some(recovery_key) => Ok(recovery_key),
+        None => Err(anyhow!("failed to generate a recovery key")),
+    }
+}
+
+/// Creates a new user account like `create_new_account`, with a key file that is required
+/// together with the master password to unlock the vault. Optionally, a recovery key is
+/// generated as well, which unlocks the vault without either of them.
+///
+/// # Arguments
+/// * `username` - The username of the new account.
+/// * `passwd` - The password associated with the account (will be hashed and zeroized after use).
+/// * `key_file` - The contents of the key file, e.g. from `create_key_file` (will be zeroized after use).
+/// * `with_recovery_key` - Whether to generate a recovery key.
+/// * `db_path` - The path to the database where user data is stored.
+///
+/// # Returns
+/// * `Result<Option<Zeroizing<String>>>` - The printable recovery key if one was requested.
+pub fn create_new_account_with_key_file(
+    username: &str,
+    passwd: String,
+    key_file: Vec<u8>,
+    with_recovery_key: bool,
+    db_path: &str,
+) -> Result<Option<Zeroizing<String>>> {
+    let key_file = Zeroizing::new(key_file);
+    create_account(
+        username,
+        passwd,
+        Some(&key_file),
+        db_path,
+        with_recovery_key,
+    )
+}
+
+/// Creates a new user account, optionally with a key file and a recovery key.
+///
+/// # Arguments
+/// * `username` - The username of the new account.
+/// * `passwd` - The password associated with the account (will be hashed and zeroized after use).
+/// * `key_file` - The contents of the key file, if the account should require one.
+/// * `db_path` - The path to the database where user data is stored.
+/// * `with_recovery_key` - Whether to generate a recovery key.
+///
+/// # Returns
+/// * `Result<Option<Zeroizing<String>>>` - The printable recovery key if one was requested.
+fn create_account(
+    username: &str,
+    passwd: String,
+    key_file: Option<&[u8]>,
+    db_path: &str,
+    with_recovery_key: bool,
+) -> Result<Option<Zeroizing<String>>> {
+    let passwd = Zeroizing::new(passwd);
+    validate_username(username)?;
+    check_strength(&passwd, &[username], MIN_PASSWORD_SCORE)?;
+    let key_file = key_file.map(hash_key_file).transpose()?;
     let db_conn = DbConn::new(db_path)?;
+    let kdf_params = KdfParams::calibrate(KDF_TARGET_DURATION)?;
     let enc_salt = generate_salt();
-    let passwd_hash = hash_password(&passwd)?;
-    db_conn.create_user(username, &passwd_hash, &enc_salt)?;
+    let keys = derive_master_keys(&passwd, key_file.as_deref(), &enc_salt, &kdf_params)?;
+    let vault_key = generate_vault_key();
+    let credentials = Credentials {
+        passwd_hash: encode_verifier(&keys.verifier),
+        enc_salt: enc_salt.to_vec(),
+        kdf_params,
+        wrapped_key: wrap_key(&keys.kek, &vault_key)?,
+        key_file: key_file.is_some(),
+    };
+    // the Argon2 work is done whether or not the username is free
+    if db_conn.get_user_id(username)?.is_some() {
+        return Err(anyhow!("this username is not available"));
+    }
+    db_conn.create_user(username, &credentials, EncryptionPolicy::for_new_vault())?;
 
-    passwd.zeroize();
-    Ok(())
+    let recovery_key = match with_recovery_key {
+        true => match db_conn.get_user_id(username)? {
+            Some(user_id) => Some(store_recovery_key(&db_conn, user_id, &vault_key)?),
+            None => return Err(anyhow!("user has missing data")),
+        },
+        false => None,
+    };
+
+    Ok(recovery_key)
 }
 
 impl Session {
-    /// Creates a new session for an existing user by verifying the password and retrieving the encryption key.
+    /// Creates a new session for an existing user by verifying the password and unwrapping the vault key.
+    /// Accounts created before vault keys were introduced are migrated on their first login: a
+    /// vault key is generated and every secret is re-encrypted under it.
+    ///
+    /// # Arguments
+    /// * `usrname` - The username of the user.
+    /// * `passwd` - The user's password (will be zeroized after use).
+    /// * `db_path` - The path to the database where user data is stored.
+    ///
+    /// # Returns
+    /// * `Result<Session>` - A new session if the login is successful, otherwise an error.
+    pub fn new(usrname: &str, passwd: String, db_path: &str) -> Result<Session> {
+        Session::open(usrname, passwd, None, None, unix_now(), db_path)
+    }
+
+    /// Creates a new session like `Session::new` for a user who registered a key file.
+    ///
+    /// # Arguments
+    /// * `usrname` - The username of the user.
+    /// * `passwd` - The user's password (will be zeroized after use).
+    /// * `key_file` - The contents of the user's key file (will be zeroized after use).
+    /// * `db_path` - The path to the database where user data is stored.
+    ///
+    /// # Returns
+    /// * `Result<Session>` - A new session if the login is successful, otherwise an error.
+    pub fn new_with_key_file(
+        usrname: &str,
+        passwd: String,
+        key_file: Vec<u8>,
+        db_path: &str,
+    ) -> Result<Session> {
+        Session::new_with_factors(usrname, passwd, Some(key_file), None, db_path)
+    }
+
+    /// Creates a new session like `Session::new` for a user who registered a key file or
+    /// enrolled in two-factor authentication, or both.
+    ///
+    /// # Arguments
+    /// * `usrname` - The username of the user.
+    /// * `passwd` - The user's password (will be zeroized after use).
+    /// * `key_file` - The contents of the user's key file, if any (will be zeroized after use).
+    /// * `totp_code` - A code from the user's authenticator app or one of their backup codes,
+    ///   if any.
+    /// * `db_path` - The path to the database where user data is stored.
+    ///
+    /// # Returns
+    /// * `Result<Session>` - A new session if the login is successful, otherwise an error.
+    pub fn new_with_factors(
+        usrname: &str,
+        passwd: String,
+        key_file: Option<Vec<u8>>,
+        totp_code: Option<String>,
+        db_path: &str,
+    ) -> Result<Session> {
+        let key_file = key_file.map(Zeroizing::new);
+        Session::open(
+            usrname,
+            passwd,
+            key_file.as_deref().map(Vec::as_slice),
+            totp_code.as_deref(),
+            unix_now(),
+            db_path,
+        )
+    }
+
+    /// Creates a new session for an existing user. The password, and the key file if the user
+    /// registered one, are checked and the vault key unwrapped with a single Argon2 run; an
+    /// unknown username runs the same Argon2 work before failing, and every wrong credential
+    /// gives `LoginError::InvalidCredentials`, so that usernames cannot be probed. Accounts
+    /// whose password hash predates single-run derivation, or whose Argon2 parameters are
+    /// below `MIN_KDF_PARAMS`, are rehashed within the same login.
+    /// Users enrolled in two-factor authentication must then give a valid code, otherwise
+    /// `LoginError::TotpRequired` is returned; a wrong code counts as a failed attempt.
+    /// Failed attempts are recorded, those made with unknown usernames included, and after a
+    /// few of them further attempts are refused with `LoginError::Throttled` until a delay has
+    /// passed. Past the user's lockout threshold, every password, the right one included, is
+    /// refused with `LoginError::LockedOut` after the same Argon2 work and counted as a failure.
     ///
     /// # Arguments
     /// * `usrname` - The username of the user.
     /// * `passwd` - The user's password (will be zeroized after use).
+    /// * `key_file` - The contents of the user's key file, if any.
+    /// * `totp_code` - The two-factor code, if any.
+    /// * `now` - The current time, in seconds since the Unix epoch.
     /// * `db_path` - The path to the database where user data is stored.
     ///
     /// # Returns
     /// * `Result<Session>` - A new session if the login is successful, otherwise an error.
-    pub fn new(usrname: &str, mut passwd: String, db_path: &str) -> Result<Session> {
-        let db_conn = DbConn::new(db_path)?;
+    fn open(
+        usrname: &str,
+        passwd: String,
+        key_file: Option<&[u8]>,
+        totp_code: Option<&str>,
+        now: u64,
+        db_path: &str,
+    ) -> Result<Session> {
+        let passwd = Zeroizing::new(passwd);
+        let mut db_conn = DbConn::new(db_path)?;
+        let key_file = key_file.map(hash_key_file).transpose()?;
+
+        let (user_id, passwd_hash) = match (
+            db_conn.get_user_id(usrname)?,
+            db_conn.get_user_passwd_hash(usrname)?,
+        ) {
+            (Some(id), Some(hash)) => (id, hash),
+            _ => {
+                check_login_allowed(&db_conn.get_unknown_login_attempts(usrname)?, now)?;
+                derive_dummy_kek(&db_conn, usrname, &passwd, key_file.as_deref())?;
+                db_conn.record_unknown_failed_login(usrname, now)?;
+                return Err(LoginError::InvalidCredentials.into());
+            }
+        };
 
-        let passwd_hash = match db_conn.get_user_passwd_hash(&usrname)? {
-            Some(hash) => hash,
-            None => return Err(anyhow!("invalid username or password")),
+        let attempts = db_conn.get_login_attempts(user_id)?;
+        check_login_allowed(&attempts, now)?;
+        let locked_out = check_not_locked_out(&attempts);
+
+        // a missing or unexpected key file fails like a wrong password, so that the error does
+        // not tell whether the account uses one
+        // a locked out account gets the same answer after the same work whatever the password,
+        // so that guesses cannot be confirmed
+        let kek = match (
+            derive_kek(&db_conn, usrname, &passwd, key_file.as_deref())?,
+            locked_out,
+        ) {
+            (Some(kek), Ok(())) => kek,
+            (_, locked_out) => {
+                db_conn.record_failed_login(user_id, now)?;
+                locked_out?;
+                return Err(LoginError::InvalidCredentials.into());
+            }
+        };
+
+        let policy = db_conn.get_user_policy(user_id)?;
+        let key = match db_conn.get_user_wrapped_key(usrname)? {
+            Some(wrapped_key) => unwrap_key(&kek, &wrapped_key)?,
+            None => migrate_to_vault_key(&mut db_conn, user_id, &kek, policy)?,
         };
 
-        if !verify_passwd(&passwd, &passwd_hash)? {
-            return Err(anyhow!("invalid username or password"));
+        if let Some(mut totp) = load_totp(&db_conn, user_id, &key)? {
+            let totp_code = totp_code.ok_or(LoginError::TotpRequired)?;
+            if !totp.verify(totp_code, now) {
+                db_conn.record_failed_login(user_id, now)?;
+                return Err(anyhow!("invalid two-factor code"));
+            }
+            // the accepted code is consumed so that it cannot be replayed
+            store_totp(&db_conn, user_id, &key, policy, Some(&totp))?;
         }
+        db_conn.reset_failed_logins(user_id)?;
+        upgrade_secrets(&mut db_conn, user_id, &key, policy)?;
+        purge_trash(&db_conn, user_id, now)?;
 
-        let key = match db_conn.get_user_enc_salt(usrname)? {
-            Some(salt) => derive_encryption_key(&passwd, &salt)?,
-            None => return Err(anyhow!("user has missing data")),
+        let session = Session {
+            user_id,
+            key,
+            policy,
+            key_file,
+            pending_totp: None,
+            pin_key: None,
+            db_conn,
+            last_activity: Instant::now(),
+        };
+        let params = session.kdf_params()?;
+        if is_legacy_hash(&passwd_hash) || params.is_weaker_than(&MIN_KDF_PARAMS) {
+            session.set_master_passwd(
+                &passwd,
+                session.key_file.as_deref(),
+                &params.max(MIN_KDF_PARAMS),
+            )?;
+        }
+
+        Ok(session)
+    }
+
+    /// Creates a new session using the recovery key instead of the master password.
+    /// Since the master password was lost, a new one must be given and is set right after
+    /// the vault key is unwrapped, provided it reaches the user's minimum strength score. A
+    /// registered key file may have been lost as well, so it is no longer required afterwards,
+    /// and two-factor authentication is skipped since the authenticator may have been lost
+    /// too. Failed login attempts are cleared, which also lifts a lockout. The recovery key
+    /// stays valid afterwards.
+    ///
+    /// # Arguments
+    /// * `usrname` - The username of the user.
+    /// * `recovery_key` - The printed recovery key (will be zeroized after use).
+    /// * `new_passwd` - The new master password (will be zeroized after use).
+    /// * `db_path` - The path to the database where user data is stored.
+    ///
+    /// # Returns
+    /// * `Result<Session>` - A new session if the recovery key is valid, otherwise an error.
+    pub fn new_with_recovery_key(
+        usrname: &str,
+        recovery_key: String,
+        new_passwd: String,
+        db_path: &str,
+    ) -> Result<Session> {
+        let recovery_key = Zeroizing::new(recovery_key);
+        let new_passwd = Zeroizing::new(new_passwd);
+        let mut db_conn = DbConn::new(db_path)?;
+
+        let (salt, wrapped_key) = match db_conn.get_user_recovery_key(usrname)? {
+            Some(recovery) => recovery,
+            None => return Err(anyhow!("invalid username or recovery key")),
         };
 
-        let user_id = match db_conn.get_user_id(&usrname)? {
+        let kek = derive_recovery_kek(&recovery_key, &salt)?;
+        let key = unwrap_key(&kek, &wrapped_key)
+            .map_err(|_| anyhow!("invalid username or recovery key"))?;
+
+        let user_id = match db_conn.get_user_id(usrname)? {
             Some(id) => id,
             None => return Err(anyhow!("user has missing data")),
         };
+        check_strength(
+            &new_passwd,
+            &[usrname],
+            min_password_score(&db_conn, user_id)?,
+        )?;
+        db_conn.reset_failed_logins(user_id)?;
+        let policy = db_conn.get_user_policy(user_id)?;
+        upgrade_secrets(&mut db_conn, user_id, &key, policy)?;
+        purge_trash(&db_conn, user_id, unix_now())?;
 
-        passwd.zeroize();
-
-        Ok(Session {
+        let session = Session {
             user_id,
             key,
+            policy,
+            key_file: None,
+            pending_totp: None,
+            pin_key: None,
             db_conn,
             last_activity: Instant::now(),
-        })
+        };
+        session.set_master_passwd(&new_passwd, None, &session.kdf_params()?)?;
+
+        Ok(session)
+    }
+
+    /// Generates a new recovery key for the current user, replacing any previous one.
+    ///
+    /// # Returns
+    /// * `Result<Zeroizing<String>>` - The printable recovery key, which is not stored and must be shown to the user.
+    pub fn create_recovery_key(&self) -> Result<Zeroizing<String>> {
+        store_recovery_key(&self.db_conn, self.user_id, &self.key)
+    }
+
+    /// Removes the recovery key of the current user, so only the master password can unlock the vault.
+    /// The lockout after failed logins is disabled as well, since only the recovery key can lift it.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn remove_recovery_key(&self) -> Result<()> {
+        self.db_conn.set_lockout_threshold(self.user_id, None)?;
+        self.db_conn.remove_user_recovery_key(self.user_id)
+    }
+
+    /// Returns the number of failed logins after which the user is locked out, if any.
+    ///
+    /// # Returns
+    /// * `Result<Option<u32>>` - The lockout threshold, or `None` if the lockout is disabled.
+    pub fn lockout_threshold(&self) -> Result<Option<u32>> {
+        Ok(self.db_conn.get_login_attempts(self.user_id)?.lockout_after)
+    }
+
+    /// Sets the number of failed logins after which the user is locked out until they use
+    /// their recovery key, or disables the lockout. A recovery key is required to enable it,
+    /// so that a lockout never makes the vault unrecoverable.
+    ///
+    /// # Arguments
+    /// * `lockout_after` - The lockout threshold, at least `MIN_LOCKOUT_THRESHOLD`, or `None`.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn set_lockout_threshold(&self, lockout_after: Option<u32>) -> Result<()> {
+        if let Some(lockout_after) = lockout_after {
+            if lockout_after < MIN_LOCKOUT_THRESHOLD {
+                return Err(anyhow!(
+                    "the lockout threshold must be at least {MIN_LOCKOUT_THRESHOLD}"
+                ));
+            }
+            let usrname = match self.db_conn.get_username(&self.user_id)? {
+                Some(usrname) => usrname,
+                None => return Err(anyhow!("user does not exist")),
+            };
+            if self.db_conn.get_user_recovery_key(&usrname)?.is_none() {
+                return Err(anyhow!("a recovery key is required to enable the lockout"));
+            }
+        }
+        self.db_conn
+            .set_lockout_threshold(self.user_id, lockout_after)
     }
 
     /// Deletes the current user from the database after verifying the password.
@@ -97,289 +648,3810 @@ impl Session {
     ///
     /// # Returns
     /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
-    pub fn delete_user(&self, mut passwd: String) -> Result<()> {
+    pub fn delete_user(&self, passwd: String) -> Result<()> {
+        let passwd = Zeroizing::new(passwd);
+        let usrname = self.verify_current_passwd(&passwd)?;
+
+        self.db_conn.delete_user(&usrname)?;
+
+        Ok(())
+    }
+
+    /// Retrieves the Argon2 parameters currently used for the user's master password.
+    ///
+    /// # Returns
+    /// * `Result<KdfParams>` - The user's key derivation parameters.
+    pub fn kdf_params(&self) -> Result<KdfParams> {
         let usrname = match self.db_conn.get_username(&self.user_id)? {
             Some(usrname) => usrname,
             None => return Err(anyhow!("user does not exist")),
         };
 
-        let stored_hash = match self.db_conn.get_user_passwd_hash(&usrname)? {
-            Some(hash) => hash,
-            None => return Err(anyhow!("user does not have a stored hash")),
-        };
+        match self.db_conn.get_user_kdf_params(&usrname)? {
+            Some(params) => Ok(params),
+            None => Err(anyhow!("user has missing data")),
+        }
+    }
 
-        if !verify_passwd(&passwd, &stored_hash)? {
-            return Err(anyhow!("invalid password"));
+    /// Retrieves the ID of the vault database and the version of Lockkey that created it.
+    ///
+    /// # Returns
+    /// * `Result<VaultInfo>` - The vault ID and creator version.
+    pub fn vault_info(&self) -> Result<VaultInfo> {
+        match (
+            self.db_conn.get_meta("vault_id")?,
+            self.db_conn.get_meta("created_by")?,
+        ) {
+            (Some(vault_id), Some(created_by)) => Ok(VaultInfo {
+                vault_id,
+                created_by,
+            }),
+            _ => Err(anyhow!("vault has missing data")),
         }
+    }
 
-        self.db_conn.delete_user(&usrname)?;
+    /// Returns the AEAD cipher the user's secrets are encrypted with.
+    pub fn cipher(&self) -> CipherId {
+        self.policy.cipher
+    }
 
-        passwd.zeroize();
+    /// Changes the AEAD cipher the user's secrets are encrypted with. Every secret, including
+    /// those in the trash and earlier versions, is re-encrypted with the new cipher in the same
+    /// transaction that records it, so the vault never mixes ciphers for long; ciphertexts
+    /// record their cipher either way.
+    ///
+    /// # Arguments
+    /// * `cipher` - The cipher to encrypt with from now on.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn set_cipher(&mut self, cipher: CipherId) -> Result<()> {
+        self.set_policy(EncryptionPolicy {
+            cipher,
+            ..self.policy
+        })
+    }
 
-        Ok(())
+    /// Returns how the user's secrets are padded before encryption.
+    pub fn padding(&self) -> PaddingId {
+        self.policy.padding
     }
 
-    /// Encrypts and stores a secret in the database for the current user.
+    /// Changes how the user's secrets are padded before encryption. Padding hides the length
+    /// of secrets from anyone reading the database; every secret is re-encrypted with the new
+    /// padding, as with `set_cipher`.
     ///
     /// # Arguments
-    /// * `kind` - The type of the secret (e.g., password, text, etc.).
-    /// * `label` - The label for the secret.
-    /// * `data` - The secret data to be encrypted and stored (will be zeroized after use).
+    /// * `padding` - The padding to apply from now on.
     ///
     /// # Returns
     /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
-    pub fn store_secret(&self, kind: &str, label: &str, mut data: String) -> Result<()> {
-        let encrypted = encrypt_using_key(&self.key, &data)?;
-        self.db_conn
-            .store_secret(self.user_id, Kind::from_str(kind)?, label, encrypted)?;
-        data.zeroize();
-        Ok(())
+    pub fn set_padding(&mut self, padding: PaddingId) -> Result<()> {
+        self.set_policy(EncryptionPolicy {
+            padding,
+            ..self.policy
+        })
     }
 
-    /// Updates an existing secret in the database with a new label or data.
+    /// Re-encrypts every secret and version not yet encrypted with the given policy and records
+    /// the policy, in a single transaction.
     ///
     /// # Arguments
-    /// * `label` - The current label of the secret to be updated.
-    /// * `new_label` - The new label for the secret.
-    /// * `new_data` - The new secret data (will be encrypted).
+    /// * `policy` - The cipher and padding to encrypt with from now on.
     ///
     /// # Returns
     /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
-    pub fn edit_secret(&self, label: &str, new_label: &str, new_data: String) -> Result<()> {
-        let encrypted = encrypt_using_key(&self.key, &new_data)?;
+    fn set_policy(&mut self, policy: EncryptionPolicy) -> Result<()> {
+        let secrets = reencrypt_outdated_secrets(&self.db_conn, self.user_id, &self.key, policy)?;
+        let versions = reencrypt_outdated_versions(&self.db_conn, self.user_id, &self.key, policy)?;
         self.db_conn
-            .edit_secret(self.user_id, label, new_label, encrypted)?;
+            .set_user_policy(self.user_id, policy, secrets, versions)?;
+        self.policy = policy;
         Ok(())
     }
 
-    /// Retrieves and decrypts a secret by its label.
+    /// Strengthens the Argon2 parameters used for the user's master password.
+    /// The password is rehashed and the key-encryption key re-derived with a fresh salt, then the
+    /// vault key is re-wrapped under it. Parameters are never lowered: each of them is raised to
+    /// at least its current value. Parameters above `MAX_KDF_PARAMS`, or estimated to take longer
+    /// than `MAX_KDF_DURATION` to derive on this machine, are refused before any derivation so
+    /// the vault stays unlockable.
     ///
     /// # Arguments
-    /// * `label` - The label of the secret to retrieve.
+    /// * `passwd` - The user's password (will be zeroized after use).
+    /// * `params` - The desired key derivation parameters.
     ///
     /// # Returns
-    /// * `Result<Option<Secret>>` - The decrypted secret if found, otherwise `None`.
-    pub fn retrieve_secret(&self, label: &str) -> Result<Option<Secret>> {
-        let (kind, encrypted_data) = match self.db_conn.get_secret(self.user_id, label)? {
-            Some(d) => d,
-            None => return Ok(None),
-        };
+    /// * `Result<KdfParams>` - The parameters now in use.
+    pub fn strengthen_kdf(&self, passwd: String, params: KdfParams) -> Result<KdfParams> {
+        let passwd = Zeroizing::new(passwd);
+        self.verify_current_passwd(&passwd)?;
 
-        let decrypted_data = decrypt_using_key(&self.key, encrypted_data)?;
-        let secret = Secret {
-            label: label.into(),
-            kind,
-            data: decrypted_data,
-        };
-        Ok(Some(secret))
+        let params = params.max(self.kdf_params()?);
+        params.check_usable()?;
+        self.set_master_passwd(&passwd, self.key_file.as_deref(), &params)?;
+
+        Ok(params)
+    }
+
+    /// Changes the user's master password after verifying the current one. The new password
+    /// must reach the user's minimum strength score. It is hashed and a fresh encryption salt
+    /// is generated, and the vault key is re-wrapped under the new key-encryption key. All of
+    /// these are written in a single update, so the secrets, which stay encrypted under the
+    /// vault key, are never half-migrated.
+    ///
+    /// # Arguments
+    /// * `old_passwd` - The user's current password (will be zeroized after use).
+    /// * `new_passwd` - The new password (will be zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn change_master_password(&self, old_passwd: String, new_passwd: String) -> Result<()> {
+        let old_passwd = Zeroizing::new(old_passwd);
+        let new_passwd = Zeroizing::new(new_passwd);
+        let usrname = self.verify_current_passwd(&old_passwd)?;
+        check_strength(&new_passwd, &[&usrname], self.min_password_score()?)?;
+
+        self.set_master_passwd(&new_passwd, self.key_file.as_deref(), &self.kdf_params()?)?;
+
+        Ok(())
     }
 
-    /// Retrieves a list of labels for all stored secrets belonging to the current user.
+    /// Returns the minimum strength score new master passwords of the user must reach.
     ///
     /// # Returns
-    /// * `Result<Vec<RetrieveLabelsQueryResult>>` - A list of labels for the user's stored
-    /// secrets.
-    pub fn retrieve_labels(&self) -> Result<Vec<RetrieveLabelsQueryResult>> {
-        Ok(self.db_conn.get_labels(self.user_id)?)
+    /// * `Result<u8>` - The minimum score, `MIN_PASSWORD_SCORE` unless the user changed it.
+    pub fn min_password_score(&self) -> Result<u8> {
+        min_password_score(&self.db_conn, self.user_id)
     }
 
-    /// Deletes a secret from the database by its label.
+    /// Sets the minimum strength score new master passwords of the user must reach. The
+    /// current password is kept even if it is weaker.
     ///
     /// # Arguments
-    /// * `label` - The label of the secret to be deleted.
+    /// * `min_score` - The minimum score, at most `MAX_PASSWORD_SCORE`.
     ///
     /// # Returns
     /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
-    pub fn delete_secret(&self, label: &str) -> Result<()> {
-        self.db_conn.delete_secret(self.user_id, label)?;
-        Ok(())
+    pub fn set_min_password_score(&self, min_score: u8) -> Result<()> {
+        if min_score > MAX_PASSWORD_SCORE {
+            return Err(anyhow!(
+                "the minimum password score must be at most {MAX_PASSWORD_SCORE}"
+            ));
+        }
+        self.db_conn.set_min_password_score(self.user_id, min_score)
     }
 
-    /// Logs the user out by zeroizing the encryption key.
+    /// Returns the number of earlier versions kept for each secret of the user.
     ///
     /// # Returns
-    /// * `Result<()>` - An empty result indicating the user has been logged out.
-    pub fn logout(&mut self) -> Result<()> {
-        self.key.zeroize();
-        Ok(())
+    /// * `Result<u32>` - The number of versions, `DEFAULT_HISTORY_LIMIT` unless the user
+    ///   changed it.
+    pub fn history_limit(&self) -> Result<u32> {
+        history_limit(&self.db_conn, self.user_id)
     }
-}
 
-impl Drop for Session {
-    /// Ensures the database connection is closed and the encryption key is zeroized when the session is dropped.
-    fn drop(&mut self) {
-        self.db_conn.close().expect("should close db connection");
-        self.key.zeroize();
+    /// Sets the number of earlier versions kept for each secret of the user. Versions past
+    /// the new limit are deleted right away.
+    ///
+    /// # Arguments
+    /// * `history_limit` - The number of versions to keep, 0 to keep none.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn set_history_limit(&self, history_limit: u32) -> Result<()> {
+        self.db_conn.set_history_limit(self.user_id, history_limit)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::{
-        fs,
-        path::PathBuf,
-        sync::{
-            atomic::{AtomicUsize, Ordering},
-            Arc, Mutex,
-        },
-        thread,
-        time::Duration,
-    };
+    /// Retrieves the number of days deleted secrets stay in the trash before they are deleted
+    /// for good.
+    ///
+    /// # Returns
+    /// * `Result<u32>` - The number of days, `DEFAULT_TRASH_RETENTION_DAYS` unless the user
+    ///   changed it.
+    pub fn trash_retention_days(&self) -> Result<u32> {
+        trash_retention_days(&self.db_conn, self.user_id)
+    }
+
+    /// Sets the number of days deleted secrets stay in the trash. Secrets that have been in
+    /// the trash for longer are deleted right away, and the others when they reach it, the
+    /// next time the user logs in.
+    ///
+    /// # Arguments
+    /// * `days` - The number of days, 0 to empty the trash right away and at every login.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn set_trash_retention_days(&self, days: u32) -> Result<()> {
+        self.db_conn.set_trash_retention(self.user_id, days)?;
+        purge_trash(&self.db_conn, self.user_id, unix_now())
+    }
+
+    /// Registers a key file that is required together with the master password to unlock the
+    /// vault, replacing any previous one, or removes the key file requirement. The vault key is
+    /// re-wrapped under a key derived from the password and the new key file.
+    ///
+    /// # Arguments
+    /// * `passwd` - The user's password (will be zeroized after use).
+    /// * `key_file` - The contents of the new key file, or `None` to stop requiring one (will
+    ///   be zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn set_key_file(&mut self, passwd: String, key_file: Option<Vec<u8>>) -> Result<()> {
+        let passwd = Zeroizing::new(passwd);
+        let key_file = key_file.map(Zeroizing::new);
+        self.verify_current_passwd(&passwd)?;
+
+        let key_file = key_file.as_deref().map(|k| hash_key_file(k)).transpose()?;
+        self.set_master_passwd(&passwd, key_file.as_deref(), &self.kdf_params()?)?;
+        self.key_file = key_file;
+
+        Ok(())
+    }
+
+    /// Checks whether the vault requires a key file to be unlocked.
+    pub fn has_key_file(&self) -> bool {
+        self.key_file.is_some()
+    }
+
+    /// Starts enrolling the current user in two-factor authentication: a new secret and backup
+    /// codes are generated and returned to be shown once. Two-factor authentication is only
+    /// enabled once a code from the authenticator app is confirmed with `confirm_totp`, so that
+    /// a badly scanned secret cannot lock the user out.
+    ///
+    /// # Returns
+    /// * `Result<TotpEnrollment>` - The `otpauth://` URI, its QR code and the backup codes.
+    pub fn enroll_totp(&mut self) -> Result<TotpEnrollment> {
+        let usrname = match self.db_conn.get_username(&self.user_id)? {
+            Some(usrname) => usrname,
+            None => return Err(anyhow!("user does not exist")),
+        };
+
+        let (totp, enrollment) = TotpState::generate(&usrname)?;
+        self.pending_totp = Some(totp);
+        Ok(enrollment)
+    }
+
+    /// Enables two-factor authentication started with `enroll_totp`, replacing any previous
+    /// enrolment, once the user typed a valid code from their authenticator app.
+    ///
+    /// # Arguments
+    /// * `code` - A code from the authenticator app.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn confirm_totp(&mut self, code: &str) -> Result<()> {
+        self.confirm_totp_at(code, unix_now())
+    }
+
+    /// Enables two-factor authentication like `confirm_totp`, at the given time.
+    ///
+    /// # Arguments
+    /// * `code` - A code from the authenticator app.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    fn confirm_totp_at(&mut self, code: &str, now: u64) -> Result<()> {
+        let totp = match self.pending_totp.as_mut() {
+            Some(totp) => totp,
+            None => return Err(anyhow!("no two-factor enrolment is in progress")),
+        };
+        if !totp.verify(code, now) {
+            return Err(anyhow!("invalid two-factor code"));
+        }
+
+        store_totp(
+            &self.db_conn,
+            self.user_id,
+            &self.key,
+            self.policy,
+            self.pending_totp.as_ref(),
+        )?;
+        self.pending_totp = None;
+        Ok(())
+    }
+
+    /// Disables two-factor authentication after verifying the password.
+    ///
+    /// # Arguments
+    /// * `passwd` - The user's password (will be zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn disable_totp(&self, passwd: String) -> Result<()> {
+        let passwd = Zeroizing::new(passwd);
+        self.verify_current_passwd(&passwd)?;
+
+        self.db_conn.set_user_totp(self.user_id, None)
+    }
+
+    /// Checks whether the vault requires a two-factor code to be unlocked.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - Whether the user enrolled in two-factor authentication.
+    pub fn has_totp(&self) -> Result<bool> {
+        Ok(self.db_conn.get_user_totp(self.user_id)?.is_some())
+    }
+
+    /// Checks the given password, together with the key file the session was unlocked with,
+    /// against the stored verifier of the current user.
+    ///
+    /// # Arguments
+    /// * `passwd` - The password to verify.
+    ///
+    /// # Returns
+    /// * `Result<String>` - The username if the password is correct, otherwise an error.
+    fn verify_current_passwd(&self, passwd: &str) -> Result<String> {
+        let usrname = match self.db_conn.get_username(&self.user_id)? {
+            Some(usrname) => usrname,
+            None => return Err(anyhow!("user does not exist")),
+        };
+
+        match derive_kek(&self.db_conn, &usrname, passwd, self.key_file.as_deref())? {
+            Some(_) => Ok(usrname),
+            None => Err(anyhow!("invalid password")),
+        }
+    }
+
+    /// Sets the master password and key file of the current user: the verifier and the
+    /// key-encryption key are derived with a fresh salt, and the vault key is re-wrapped under
+    /// the latter.
+    ///
+    /// # Arguments
+    /// * `passwd` - The master password to set.
+    /// * `key_file` - The hash of the key file to require, if any.
+    /// * `params` - The key derivation parameters to use.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    fn set_master_passwd(
+        &self,
+        passwd: &str,
+        key_file: Option<&[u8; 32]>,
+        params: &KdfParams,
+    ) -> Result<()> {
+        let enc_salt = generate_salt();
+        let keys = derive_master_keys(passwd, key_file, &enc_salt, params)?;
+        let credentials = Credentials {
+            passwd_hash: encode_verifier(&keys.verifier),
+            enc_salt: enc_salt.to_vec(),
+            kdf_params: *params,
+            wrapped_key: wrap_key(&keys.kek, &self.key)?,
+            key_file: key_file.is_some(),
+        };
+
+        self.db_conn
+            .update_user_credentials(self.user_id, &credentials)
+    }
+
+    /// Encrypts and stores a text secret in the database for the current user.
+    /// The label and kind are encrypted as well, and the secret is looked up by a blind index
+    /// of its normalized label.
+    ///
+    /// # Arguments
+    /// * `kind` - The type of the secret (e.g., password, text, etc.).
+    /// * `label` - The label for the secret, between 3 and 32 characters.
+    /// * `data` - The secret data to be encrypted and stored (will be zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn store_secret(&self, kind: &str, label: &str, data: String) -> Result<()> {
+        self.store(kind, label, data.into_bytes(), Encoding::Utf8)
+    }
+
+    /// Encrypts and stores a secret with a binary payload, such as a key file or a
+    /// certificate, in the database for the current user.
+    ///
+    /// # Arguments
+    /// * `kind` - The type of the secret (e.g., password, text, etc.).
+    /// * `label` - The label for the secret, between 3 and 32 characters.
+    /// * `data` - The bytes to be encrypted and stored (will be zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn store_secret_bytes(&self, kind: &str, label: &str, data: Vec<u8>) -> Result<()> {
+        self.store(kind, label, data, Encoding::Binary)
+    }
+
+    /// Updates an existing secret in the database with a new label or text data.
+    /// The secret keeps its ID and kind, so the new data is bound to the same row.
+    ///
+    /// # Arguments
+    /// * `label` - The current label of the secret to be updated.
+    /// * `new_label` - The new label for the secret, between 3 and 32 characters.
+    /// * `new_data` - The new secret data (will be encrypted and zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn edit_secret(&self, label: &str, new_label: &str, new_data: String) -> Result<()> {
+        self.edit(label, new_label, new_data.into_bytes(), Encoding::Utf8)
+    }
+
+    /// Updates an existing secret in the database with a new label or binary data.
+    ///
+    /// # Arguments
+    /// * `label` - The current label of the secret to be updated.
+    /// * `new_label` - The new label for the secret, between 3 and 32 characters.
+    /// * `new_data` - The new bytes of the secret (will be encrypted and zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn edit_secret_bytes(&self, label: &str, new_label: &str, new_data: Vec<u8>) -> Result<()> {
+        self.edit(label, new_label, new_data, Encoding::Binary)
+    }
+
+    /// Retrieves and decrypts a text secret by its label.
+    /// Secrets with a binary payload are refused and must be read with `retrieve_secret_bytes`.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret to retrieve.
+    ///
+    /// # Returns
+    /// * `Result<Option<Secret>>` - The decrypted secret if found, otherwise `None`.
+    pub fn retrieve_secret(&self, label: &str) -> Result<Option<Secret>> {
+        let mut secret = match self.retrieve_secret_bytes(label)? {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+        if secret.encoding != Encoding::Utf8 {
+            return Err(anyhow!("secret holds binary data"));
+        }
+
+        let data = String::from_utf8(std::mem::take(&mut *secret.data)).map_err(|e| {
+            e.into_bytes().zeroize();
+            anyhow!("secret is not valid UTF-8")
+        })?;
+        let secret = Secret {
+            label: std::mem::take(&mut secret.label),
+            kind: std::mem::take(&mut secret.kind),
+            data: Zeroizing::new(data),
+        };
+        Ok(Some(secret))
+    }
+
+    /// Retrieves and decrypts a secret by its label, returning its payload as bytes whatever
+    /// its encoding.
+    /// The label is matched after normalization, so case and surrounding whitespace are ignored.
+    /// The ciphertexts must be bound to the user and ID of the row they are read from, so
+    /// data that was moved to another row or reclassified outside the app is refused.
+    /// The time the secret was read is recorded as its last use.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret to retrieve.
+    ///
+    /// # Returns
+    /// * `Result<Option<SecretBytes>>` - The decrypted secret if found, otherwise `None`.
+    pub fn retrieve_secret_bytes(&self, label: &str) -> Result<Option<SecretBytes>> {
+        let secret = match self
+            .db_conn
+            .get_secret(self.user_id, &self.label_index(label)?)?
+        {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+
+        let id = secret.id;
+        let (meta, data) = decrypt_secret(&self.key, self.user_id, secret)
+            .map_err(|_| anyhow!("secret could not be decrypted or was tampered with"))?;
+        self.db_conn
+            .record_secret_use(self.user_id, id, unix_now())?;
+
+        let secret = SecretBytes {
+            label: meta.label,
+            kind: meta.kind,
+            encoding: meta.encoding,
+            data,
+        };
+        Ok(Some(secret))
+    }
+
+    /// Retrieves and decrypts the labels and kinds of all stored secrets belonging to the
+    /// current user.
+    ///
+    /// # Returns
+    /// * `Result<Vec<RetrieveLabelsQueryResult>>` - A list of labels for the user's stored
+    /// secrets.
+    pub fn retrieve_labels(&self) -> Result<Vec<RetrieveLabelsQueryResult>> {
+        self.list_labels(None, None)
+    }
+
+    /// Retrieves the labels like `retrieve_labels`, optionally filtered and sorted. Filtering
+    /// by folder only lists the secrets directly in it, not those in its subfolders.
+    ///
+    /// # Arguments
+    /// * `filter` - The folder or tag to list the secrets of, or `None` to list them all.
+    /// * `order` - The order to list the secrets in, or `None` for no particular order.
+    ///
+    /// # Returns
+    /// * `Result<Vec<RetrieveLabelsQueryResult>>` - The labels of the matching secrets.
+    pub fn list_labels(
+        &self,
+        filter: Option<&LabelFilter>,
+        order: Option<LabelOrder>,
+    ) -> Result<Vec<RetrieveLabelsQueryResult>> {
+        let tag_id = match filter {
+            Some(LabelFilter::Tag(tag)) => {
+                match self
+                    .db_conn
+                    .get_tag_id(self.user_id, &self.tag_index(tag)?)?
+                {
+                    Some(tag_id) => Some(tag_id),
+                    None => return Ok(Vec::new()),
+                }
+            }
+            _ => None,
+        };
+
+        let tag_names = self.tag_names()?;
+        let mut secret_tags: HashMap<i64, Vec<i64>> = HashMap::new();
+        for (secret_id, tag_id) in self.db_conn.get_secret_tags(self.user_id)? {
+            secret_tags.entry(secret_id).or_default().push(tag_id);
+        }
+
+        let mut labels = self
+            .db_conn
+            .get_labels(self.user_id)?
+            .into_iter()
+            .filter(|row| match filter {
+                Some(LabelFilter::Folder(folder_id)) => row.folder_id == *folder_id,
+                Some(LabelFilter::Tag(_)) => secret_tags
+                    .get(&row.id)
+                    .is_some_and(|tag_ids| tag_ids.iter().any(|id| Some(*id) == tag_id)),
+                None => true,
+            })
+            .map(|row| {
+                let meta = decrypt_meta(&self.key, self.user_id, row.id, row.meta)?;
+                let mut tags: Vec<String> = secret_tags
+                    .get(&row.id)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| tag_names.get(id).cloned())
+                    .collect();
+                tags.sort_by_cached_key(|tag| normalize_label(tag));
+                Ok(RetrieveLabelsQueryResult {
+                    kind: meta.kind,
+                    label: meta.label,
+                    encoding: meta.encoding,
+                    folder_id: row.folder_id,
+                    tags,
+                    timestamps: row.timestamps,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match order {
+            Some(LabelOrder::Label) => labels.sort_by_cached_key(|x| normalize_label(&x.label)),
+            Some(LabelOrder::Created) => labels.sort_by_key(|x| Reverse(x.timestamps.created_at)),
+            Some(LabelOrder::Updated) => labels.sort_by_key(|x| Reverse(x.timestamps.updated_at)),
+            Some(LabelOrder::LastUsed) => {
+                labels.sort_by_key(|x| Reverse(x.timestamps.last_used_at))
+            }
+            None => {}
+        }
+        Ok(labels)
+    }
+
+    /// Retrieves the labels like `retrieve_labels`, sorted in the given order.
+    ///
+    /// # Arguments
+    /// * `order` - The order to list the secrets in.
+    ///
+    /// # Returns
+    /// * `Result<Vec<RetrieveLabelsQueryResult>>` - The sorted labels of the user's secrets.
+    pub fn retrieve_sorted_labels(
+        &self,
+        order: LabelOrder,
+    ) -> Result<Vec<RetrieveLabelsQueryResult>> {
+        self.list_labels(None, Some(order))
+    }
+
+    /// Lists the earlier versions of a secret, newest first. A version is kept every time the
+    /// secret is edited, up to the user's history limit.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret.
+    ///
+    /// # Returns
+    /// * `Result<Vec<SecretVersion>>` - The versions of the secret, without their data.
+    pub fn secret_versions(&self, label: &str) -> Result<Vec<SecretVersion>> {
+        let (secret_id, _) = self.find_secret(label)?;
+        self.db_conn
+            .get_secret_versions(self.user_id, secret_id)?
+            .into_iter()
+            .map(|version| {
+                let (meta, _) = decrypt_version(&self.key, self.user_id, &version)?;
+                Ok(SecretVersion {
+                    id: version.id,
+                    label: meta.label,
+                    replaced_at: version.replaced_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Retrieves and decrypts an earlier version of a secret.
+    ///
+    /// # Arguments
+    /// * `label` - The current label of the secret.
+    /// * `version_id` - The ID of the version, as listed by `secret_versions`.
+    ///
+    /// # Returns
+    /// * `Result<Option<SecretBytes>>` - The version as it was stored, or `None` if the secret
+    ///   has no such version.
+    pub fn retrieve_secret_version(
+        &self,
+        label: &str,
+        version_id: i64,
+    ) -> Result<Option<SecretBytes>> {
+        let version = match self.find_version(label, version_id)? {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        let secret = SecretBytes {
+            label: version.meta.label,
+            kind: version.meta.kind,
+            encoding: version.meta.encoding,
+            data: version.data,
+        };
+        Ok(Some(secret))
+    }
+
+    /// Rolls a secret back to an earlier version, restoring its label and data. This is an
+    /// edit like any other, so the replaced data is kept as the newest version.
+    ///
+    /// # Arguments
+    /// * `label` - The current label of the secret.
+    /// * `version_id` - The ID of the version to restore, as listed by `secret_versions`.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn restore_secret_version(&self, label: &str, version_id: i64) -> Result<()> {
+        match self.find_version(label, version_id)? {
+            Some(version) => self.replace(version.id, &version.meta, &version.data),
+            None => Err(anyhow!("version not found")),
+        }
+    }
+
+    /// Lists the folders of the current user, decrypting their names.
+    ///
+    /// # Returns
+    /// * `Result<Vec<Folder>>` - The folders, which form a tree through their parent IDs.
+    pub fn folders(&self) -> Result<Vec<Folder>> {
+        self.db_conn
+            .get_folders(self.user_id)?
+            .into_iter()
+            .map(|row| {
+                let name =
+                    decrypt_using_key(&*self.key, row.name, &folder_context(self.user_id, row.id))?;
+                Ok(Folder {
+                    id: row.id,
+                    parent_id: row.parent_id,
+                    name: String::from_utf8(name.to_vec())?,
+                })
+            })
+            .collect()
+    }
+
+    /// Creates a folder.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the folder, between 1 and 32 characters.
+    /// * `parent_id` - The folder to create it in, or `None` for the root.
+    ///
+    /// # Returns
+    /// * `Result<i64>` - The ID of the new folder.
+    pub fn create_folder(&self, name: &str, parent_id: Option<i64>) -> Result<i64> {
+        validate_name(name)?;
+        self.db_conn.create_folder(self.user_id, parent_id, |id| {
+            self.encrypt_folder_name(id, name)
+        })
+    }
+
+    /// Renames a folder.
+    ///
+    /// # Arguments
+    /// * `folder_id` - The ID of the folder.
+    /// * `name` - The new name of the folder, between 1 and 32 characters.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn rename_folder(&self, folder_id: i64, name: &str) -> Result<()> {
+        validate_name(name)?;
+        self.db_conn.rename_folder(
+            self.user_id,
+            folder_id,
+            &self.encrypt_folder_name(folder_id, name)?,
+        )
+    }
+
+    /// Moves a folder, with everything in it, into another folder. A folder cannot be moved
+    /// into itself or one of its subfolders.
+    ///
+    /// # Arguments
+    /// * `folder_id` - The ID of the folder.
+    /// * `parent_id` - The folder to move it into, or `None` for the root.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn move_folder(&self, folder_id: i64, parent_id: Option<i64>) -> Result<()> {
+        self.db_conn.move_folder(self.user_id, folder_id, parent_id)
+    }
+
+    /// Deletes a folder. Its subfolders are either deleted with it, with all their secrets moved
+    /// to the trash, or moved to the folder it was in along with its secrets.
+    ///
+    /// # Arguments
+    /// * `folder_id` - The ID of the folder.
+    /// * `deletion` - What happens to the contents of the folder.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn delete_folder(&self, folder_id: i64, deletion: FolderDeletion) -> Result<()> {
+        self.db_conn
+            .delete_folder(self.user_id, folder_id, deletion, unix_now())
+    }
+
+    /// Moves a secret into a folder.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret.
+    /// * `folder_id` - The folder to move it into, or `None` for the root.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn move_secret(&self, label: &str, folder_id: Option<i64>) -> Result<()> {
+        let (secret_id, _) = self.find_secret(label)?;
+        self.db_conn
+            .set_secret_folder(self.user_id, secret_id, folder_id)
+    }
+
+    /// Lists the tags of the current user, sorted by name ignoring case. Tags only exist as
+    /// long as a secret has them.
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>>` - The names of the tags.
+    pub fn tags(&self) -> Result<Vec<String>> {
+        let mut tags: Vec<String> = self.tag_names()?.into_values().collect();
+        tags.sort_by_cached_key(|tag| normalize_label(tag));
+        Ok(tags)
+    }
+
+    /// Tags a secret. Tags are matched ignoring case, surrounding whitespace and Unicode
+    /// representation, and created the first time they are used.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret.
+    /// * `tag` - The name of the tag, between 1 and 32 characters.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn tag_secret(&self, label: &str, tag: &str) -> Result<()> {
+        validate_name(tag)?;
+        let (secret_id, _) = self.find_secret(label)?;
+        self.db_conn
+            .tag_secret(self.user_id, secret_id, &self.tag_index(tag)?, |id| {
+                encrypt_using_key(
+                    &*self.key,
+                    self.policy,
+                    tag.trim().as_bytes(),
+                    &tag_context(self.user_id, id),
+                )
+            })
+    }
+
+    /// Removes a tag from a secret.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret.
+    /// * `tag` - The name of the tag.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn untag_secret(&self, label: &str, tag: &str) -> Result<()> {
+        let (secret_id, _) = self.find_secret(label)?;
+        self.db_conn
+            .untag_secret(self.user_id, secret_id, &self.tag_index(tag)?)
+    }
+
+    /// Moves a secret to the trash by its label. It stays there, encrypted as it was, until
+    /// it is restored, deleted for good, or purged once the trash retention has passed.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret to be deleted.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn delete_secret(&self, label: &str) -> Result<()> {
+        self.db_conn
+            .trash_secret(self.user_id, &self.label_index(label)?, unix_now())?;
+        Ok(())
+    }
+
+    /// Lists the secrets in the trash, most recently deleted first.
+    ///
+    /// # Returns
+    /// * `Result<Vec<TrashedSecret>>` - The secrets in the trash, without their data.
+    pub fn trash(&self) -> Result<Vec<TrashedSecret>> {
+        self.db_conn
+            .get_trash(self.user_id)?
+            .into_iter()
+            .map(|row| {
+                let meta = decrypt_meta(&self.key, self.user_id, row.id, row.meta)?;
+                Ok(TrashedSecret {
+                    id: row.id,
+                    label: meta.label,
+                    kind: meta.kind,
+                    deleted_at: row.deleted_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Takes a secret out of the trash, with its versions, folder and tags. This fails if
+    /// another secret was stored with the same label in the meantime.
+    ///
+    /// # Arguments
+    /// * `trash_id` - The ID of the secret, as listed by `trash`.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn restore_secret(&self, trash_id: i64) -> Result<()> {
+        let row = match self
+            .db_conn
+            .get_trash(self.user_id)?
+            .into_iter()
+            .find(|row| row.id == trash_id)
+        {
+            Some(row) => row,
+            None => return Err(anyhow!("secret not found in the trash")),
+        };
+        let meta = decrypt_meta(&self.key, self.user_id, row.id, row.meta)?;
+        self.db_conn
+            .restore_secret(self.user_id, row.id, &self.label_index(&meta.label)?)
+    }
+
+    /// Deletes a secret in the trash for good, with its earlier versions.
+    ///
+    /// # Arguments
+    /// * `trash_id` - The ID of the secret, as listed by `trash`.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn delete_secret_permanently(&self, trash_id: i64) -> Result<()> {
+        self.db_conn.delete_trashed_secret(self.user_id, trash_id)
+    }
+
+    /// Encrypts and stores a secret with the given payload encoding.
+    ///
+    /// # Arguments
+    /// * `kind` - The type of the secret.
+    /// * `label` - The label for the secret, between 3 and 32 characters.
+    /// * `data` - The payload to be encrypted and stored (will be zeroized after use).
+    /// * `encoding` - The encoding of the payload.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    fn store(&self, kind: &str, label: &str, data: Vec<u8>, encoding: Encoding) -> Result<()> {
+        let data = Zeroizing::new(data);
+        validate_label(label)?;
+        let meta = SecretMeta {
+            label: label.trim().to_string(),
+            kind: Kind::from_str(kind)?.to_str(),
+            encoding,
+        };
+        self.db_conn
+            .store_secret(self.user_id, &self.label_index(label)?, unix_now(), |id| {
+                let secret =
+                    encrypt_secret(&self.key, self.policy, self.user_id, id, &meta, &data)?;
+                Ok((secret.meta, secret.data))
+            })?;
+        Ok(())
+    }
+
+    /// Replaces the label and payload of an existing secret, keeping its ID and kind so the
+    /// new data is bound to the same row.
+    ///
+    /// # Arguments
+    /// * `label` - The current label of the secret to be updated.
+    /// * `new_label` - The new label for the secret, between 3 and 32 characters.
+    /// * `new_data` - The new payload (will be zeroized after use).
+    /// * `encoding` - The encoding of the new payload.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    fn edit(
+        &self,
+        label: &str,
+        new_label: &str,
+        new_data: Vec<u8>,
+        encoding: Encoding,
+    ) -> Result<()> {
+        let new_data = Zeroizing::new(new_data);
+        validate_label(new_label)?;
+        let (secret_id, meta) = self.find_secret(label)?;
+
+        let meta = SecretMeta {
+            label: new_label.trim().to_string(),
+            kind: meta.kind,
+            encoding,
+        };
+        self.replace(secret_id, &meta, &new_data)
+    }
+
+    /// Finds a secret by its label and decrypts its metadata. The secret is decrypted in full
+    /// to verify it was not tampered with before it is replaced or its versions are read.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret.
+    ///
+    /// # Returns
+    /// * `Result<(i64, SecretMeta)>` - The ID and metadata of the secret, or an error if it
+    ///   does not exist.
+    fn find_secret(&self, label: &str) -> Result<(i64, SecretMeta)> {
+        let secret = match self
+            .db_conn
+            .get_secret(self.user_id, &self.label_index(label)?)?
+        {
+            Some(secret) => secret,
+            None => return Err(anyhow!("secret not found")),
+        };
+        let id = secret.id;
+        let (meta, _) = decrypt_secret(&self.key, self.user_id, secret)?;
+        Ok((id, meta))
+    }
+
+    /// Finds an earlier version of a secret and decrypts it.
+    ///
+    /// # Arguments
+    /// * `label` - The current label of the secret.
+    /// * `version_id` - The ID of the version.
+    ///
+    /// # Returns
+    /// * `Result<Option<DecryptedSecret>>` - The version with the ID of the secret, or `None`
+    ///   if the secret has no such version.
+    fn find_version(&self, label: &str, version_id: i64) -> Result<Option<DecryptedSecret>> {
+        let (secret_id, _) = self.find_secret(label)?;
+        let version = match self
+            .db_conn
+            .get_secret_version(self.user_id, secret_id, version_id)?
+        {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        let outdated = !is_current_ciphertext(&version.data, self.policy)
+            || !is_current_ciphertext(&version.meta, self.policy);
+        let outdated = outdated || version.legacy_context;
+        let (meta, data) = decrypt_version(&self.key, self.user_id, &version)
+            .map_err(|_| anyhow!("secret could not be decrypted or was tampered with"))?;
+        Ok(Some(DecryptedSecret {
+            id: secret_id,
+            meta,
+            data,
+            outdated,
+        }))
+    }
+
+    /// Encrypts new metadata and data for an existing secret and replaces it, keeping the
+    /// replaced ones as a version of the secret, re-encrypted bound to the version's row.
+    ///
+    /// # Arguments
+    /// * `secret_id` - The ID of the secret.
+    /// * `meta` - The new label, kind and encoding of the secret.
+    /// * `data` - The new data of the secret.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    fn replace(&self, secret_id: i64, meta: &SecretMeta, data: &[u8]) -> Result<()> {
+        self.db_conn.edit_secret(
+            self.user_id,
+            encrypt_secret(&self.key, self.policy, self.user_id, secret_id, meta, data)?,
+            history_limit(&self.db_conn, self.user_id)?,
+            unix_now(),
+            |version_id, replaced| {
+                let (old_meta, old_data) = decrypt_secret(&self.key, self.user_id, replaced)?;
+                encrypt_version(
+                    &self.key,
+                    self.policy,
+                    self.user_id,
+                    secret_id,
+                    version_id,
+                    &old_meta,
+                    &old_data,
+                )
+            },
+        )
+    }
+
+    /// Encrypts the name of a folder, bound to its row.
+    ///
+    /// # Arguments
+    /// * `folder_id` - The ID of the folder.
+    /// * `name` - The name of the folder.
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>>` - The encrypted name.
+    fn encrypt_folder_name(&self, folder_id: i64, name: &str) -> Result<Vec<u8>> {
+        encrypt_using_key(
+            &*self.key,
+            self.policy,
+            name.trim().as_bytes(),
+            &folder_context(self.user_id, folder_id),
+        )
+    }
+
+    /// Decrypts the names of the current user's tags.
+    ///
+    /// # Returns
+    /// * `Result<HashMap<i64, String>>` - The names of the tags by ID.
+    fn tag_names(&self) -> Result<HashMap<i64, String>> {
+        self.db_conn
+            .get_tags(self.user_id)?
+            .into_iter()
+            .map(|row| {
+                let name =
+                    decrypt_using_key(&*self.key, row.name, &tag_context(self.user_id, row.id))?;
+                Ok((row.id, String::from_utf8(name.to_vec())?))
+            })
+            .collect()
+    }
+
+    /// Computes the blind index a tag is looked up by from its name.
+    ///
+    /// # Arguments
+    /// * `tag` - The name of the tag.
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>>` - The blind index of the normalized name.
+    fn tag_index(&self, tag: &str) -> Result<Vec<u8>> {
+        compute_tag_index(&*self.key, &normalize_label(tag))
+    }
+
+    /// Computes the blind index a secret is looked up by from its label.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the secret.
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>>` - The blind index of the normalized label.
+    fn label_index(&self, label: &str) -> Result<Vec<u8>> {
+        compute_label_index(&*self.key, &normalize_label(label))
+    }
+
+    /// Sets the PIN that reopens the session once it is locked, or disables quick unlock.
+    /// The PIN is not stored: only a key derived from it is kept until the session is locked.
+    ///
+    /// # Arguments
+    /// * `pin` - The PIN, at least 4 characters, or `None` to disable quick unlock (will be
+    ///   zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+    pub fn set_quick_unlock_pin(&mut self, pin: Option<String>) -> Result<()> {
+        let pin = pin.map(Zeroizing::new);
+        self.pin_key = match pin {
+            Some(pin) if pin.chars().count() < MIN_PIN_LEN => {
+                return Err(anyhow!("the PIN must be at least {MIN_PIN_LEN} characters"))
+            }
+            Some(pin) => {
+                let salt = generate_salt();
+                Some(PinKey {
+                    key: derive_pin_key(&pin, &salt)?,
+                    salt,
+                })
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Checks whether the session can be locked and reopened with a PIN.
+    pub fn has_quick_unlock(&self) -> bool {
+        self.pin_key.is_some()
+    }
+
+    /// Locks the session: the vault key and key file hash are wrapped with the key derived
+    /// from the quick-unlock PIN and then zeroized, like on logout. The session must be
+    /// dropped afterwards, and the returned locked session reopened with the PIN.
+    ///
+    /// # Returns
+    /// * `Result<LockedSession>` - The locked session, or an error if no PIN was set.
+    pub fn lock(&mut self) -> Result<LockedSession> {
+        let pin_key = match self.pin_key.as_ref() {
+            Some(pin_key) => pin_key,
+            None => return Err(anyhow!("quick unlock is not enabled")),
+        };
+        let wrapped_key = wrap_key(&pin_key.key, &self.key)?;
+        let wrapped_key_file = self
+            .key_file
+            .as_deref()
+            .map(|key_file| wrap_key(&pin_key.key, key_file))
+            .transpose()?;
+
+        let locked = LockedSession {
+            user_id: self.user_id,
+            policy: self.policy,
+            pin_salt: pin_key.salt,
+            wrapped_keys: Some((wrapped_key, wrapped_key_file)),
+            attempts_left: MAX_PIN_ATTEMPTS,
+            db_conn: self.db_conn.take(),
+        };
+        self.pin_key = None;
+        self.key_file = None;
+        self.pending_totp = None;
+        self.logout()?;
+        Ok(locked)
+    }
+
+    /// Logs the user out by zeroizing the encryption key.
+    ///
+    /// # Returns
+    /// * `Result<()>` - An empty result indicating the user has been logged out.
+    pub fn logout(&mut self) -> Result<()> {
+        self.key.zeroize();
+        Ok(())
+    }
+}
+
+impl LockedSession {
+    /// Reopens the locked session with the quick-unlock PIN. A wrong PIN uses up an attempt,
+    /// and once none are left the wrapped keys are destroyed so that only the master password
+    /// can unlock the vault.
+    ///
+    /// # Arguments
+    /// * `pin` - The quick-unlock PIN (will be zeroized after use).
+    ///
+    /// # Returns
+    /// * `Result<Session>` - The reopened session if the PIN is correct, otherwise an error.
+    pub fn unlock(&mut self, pin: String) -> Result<Session> {
+        let pin = Zeroizing::new(pin);
+        let (wrapped_key, wrapped_key_file) = match self.wrapped_keys.as_ref() {
+            Some(wrapped_keys) => wrapped_keys,
+            None => return Err(anyhow!("quick unlock is no longer available, log in again")),
+        };
+
+        let pin_key = derive_pin_key(&pin, &self.pin_salt)?;
+        let key = match unwrap_key(&pin_key, wrapped_key) {
+            Ok(key) => key,
+            Err(_) => {
+                self.attempts_left -= 1;
+                if self.attempts_left == 0 {
+                    self.wrapped_keys = None;
+                    return Err(anyhow!("too many wrong PINs, log in again"));
+                }
+                return Err(anyhow!("invalid PIN"));
+            }
+        };
+        let key_file = wrapped_key_file
+            .as_deref()
+            .map(|wrapped| unwrap_key(&pin_key, wrapped))
+            .transpose()?;
+        self.wrapped_keys = None;
+
+        Ok(Session {
+            user_id: self.user_id,
+            key,
+            policy: self.policy,
+            key_file,
+            pending_totp: None,
+            pin_key: Some(PinKey {
+                key: pin_key,
+                salt: self.pin_salt,
+            }),
+            db_conn: self.db_conn.take(),
+            last_activity: Instant::now(),
+        })
+    }
+
+    /// Returns the number of PINs that may still be tried, zero once the session can only be
+    /// unlocked with the master password.
+    pub fn attempts_left(&self) -> u32 {
+        match self.wrapped_keys {
+            Some(_) => self.attempts_left,
+            None => 0,
+        }
+    }
+}
+
+/// Derives the key that wraps the vault key of a locked session from the quick-unlock PIN.
+/// The minimum Argon2 parameters keep unlocking fast; the PIN only protects a copy of the key
+/// held in memory for a few attempts.
+///
+/// # Arguments
+/// * `pin` - The quick-unlock PIN.
+/// * `salt` - The salt generated when the PIN was set.
+///
+/// # Returns
+/// * `Result<Zeroizing<[u8; 32]>>` - The PIN key.
+fn derive_pin_key(pin: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    derive_encryption_key(pin, None, salt, &MIN_KDF_PARAMS)
+}
+
+/// Generates a recovery key and stores the vault key wrapped by it for a user.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `vault_key` - The user's vault key.
+///
+/// # Returns
+/// * `Result<Zeroizing<String>>` - The printable recovery key.
+fn store_recovery_key(
+    db_conn: &DbConn,
+    user_id: i64,
+    vault_key: &[u8; 32],
+) -> Result<Zeroizing<String>> {
+    let recovery_key = generate_recovery_key();
+    let salt = generate_salt();
+    let kek = derive_recovery_kek(&recovery_key, &salt)?;
+    let wrapped_key = wrap_key(&kek, vault_key)?;
+
+    db_conn.set_user_recovery_key(user_id, &salt, &wrapped_key)?;
+    Ok(recovery_key)
+}
+
+/// Retrieves the minimum strength score new master passwords of a user must reach.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+///
+/// # Returns
+/// * `Result<u8>` - The minimum score, `MIN_PASSWORD_SCORE` unless the user changed it.
+fn min_password_score(db_conn: &DbConn, user_id: i64) -> Result<u8> {
+    Ok(db_conn
+        .get_min_password_score(user_id)?
+        .unwrap_or(MIN_PASSWORD_SCORE))
+}
+
+/// Retrieves the number of earlier versions kept for each secret of a user.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+///
+/// # Returns
+/// * `Result<u32>` - The number of versions, `DEFAULT_HISTORY_LIMIT` unless the user changed
+///   it.
+fn history_limit(db_conn: &DbConn, user_id: i64) -> Result<u32> {
+    Ok(db_conn
+        .get_history_limit(user_id)?
+        .unwrap_or(DEFAULT_HISTORY_LIMIT))
+}
+
+/// Retrieves the number of days deleted secrets of a user stay in the trash.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+///
+/// # Returns
+/// * `Result<u32>` - The number of days, `DEFAULT_TRASH_RETENTION_DAYS` unless the user
+///   changed it.
+fn trash_retention_days(db_conn: &DbConn, user_id: i64) -> Result<u32> {
+    Ok(db_conn
+        .get_trash_retention(user_id)?
+        .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS))
+}
+
+/// Deletes for good the secrets of a user that have been in the trash for longer than their
+/// trash retention.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `now` - The current time, in seconds since the Unix epoch.
+///
+/// # Returns
+/// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+fn purge_trash(db_conn: &DbConn, user_id: i64, now: u64) -> Result<()> {
+    let retention = u64::from(trash_retention_days(db_conn, user_id)?) * SECS_PER_DAY;
+    db_conn.purge_trash(user_id, now.saturating_sub(retention))
+}
+
+/// Loads and decrypts the two-factor state of a user, if they enrolled.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `vault_key` - The user's vault key.
+///
+/// # Returns
+/// * `Result<Option<TotpState>>` - The two-factor state, or `None` if the user did not enroll.
+fn load_totp(db_conn: &DbConn, user_id: i64, vault_key: &[u8; 32]) -> Result<Option<TotpState>> {
+    let encrypted = match db_conn.get_user_totp(user_id)? {
+        Some(encrypted) => encrypted,
+        None => return Ok(None),
+    };
+    let json = decrypt_using_key(vault_key, encrypted, &totp_context(user_id))?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
+
+/// Encrypts and stores the two-factor state of a user, or disables two-factor authentication.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `vault_key` - The user's vault key.
+/// * `policy` - The cipher and padding to encrypt the state with.
+/// * `totp` - The two-factor state, or `None` to disable it.
+///
+/// # Returns
+/// * `Result<()>` - An empty result if the operation is successful, otherwise an error.
+fn store_totp(
+    db_conn: &DbConn,
+    user_id: i64,
+    vault_key: &[u8; 32],
+    policy: EncryptionPolicy,
+    totp: Option<&TotpState>,
+) -> Result<()> {
+    let encrypted = match totp {
+        Some(totp) => {
+            let json = Zeroizing::new(serde_json::to_vec(totp)?);
+            Some(encrypt_using_key(
+                vault_key,
+                policy,
+                &json,
+                &totp_context(user_id),
+            )?)
+        }
+        None => None,
+    };
+    db_conn.set_user_totp(user_id, encrypted.as_deref())
+}
+
+/// Checks the master password and key file of a user and derives their key-encryption key.
+/// The verifier and the key-encryption key come from the same Argon2 run; accounts that still
+/// store a PHC password hash are checked against it, and their key-encryption key is the
+/// Argon2 output itself.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `usrname` - The username of the user.
+/// * `passwd` - The master password to check.
+/// * `key_file` - The hash of the user's key file, if they registered one.
+///
+/// # Returns
+/// * `Result<Option<Zeroizing<[u8; 32]>>>` - The key-encryption key, or `None` if the user does
+///   not exist or the password or key file is wrong.
+fn derive_kek(
+    db_conn: &DbConn,
+    usrname: &str,
+    passwd: &str,
+    key_file: Option<&[u8; 32]>,
+) -> Result<Option<Zeroizing<[u8; 32]>>> {
+    let passwd_hash = match db_conn.get_user_passwd_hash(usrname)? {
+        Some(hash) => hash,
+        None => return Ok(None),
+    };
+    let (salt, kdf_params) = match (
+        db_conn.get_user_enc_salt(usrname)?,
+        db_conn.get_user_kdf_params(usrname)?,
+    ) {
+        (Some(salt), Some(params)) => (salt, params),
+        _ => return Err(anyhow!("user has missing data")),
+    };
+
+    if is_legacy_hash(&passwd_hash) {
+        if !verify_passwd(passwd, &passwd_hash)? {
+            return Ok(None);
+        }
+        return Ok(Some(derive_encryption_key(
+            passwd,
+            key_file,
+            &salt,
+            &kdf_params,
+        )?));
+    }
+
+    let keys = derive_master_keys(passwd, key_file, &salt, &kdf_params)?;
+    match check_verifier(&keys.verifier, &passwd_hash)? {
+        true => Ok(Some(keys.kek)),
+        false => Ok(None),
+    }
+}
+
+/// Runs the Argon2 work of checking a password for a login with an unknown username, so that
+/// response times do not reveal which usernames exist. Parameters are per user, so those of one
+/// real user are used, picked from the given username so that retrying it takes as long.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `usrname` - The username that was given.
+/// * `passwd` - The password that was given.
+/// * `key_file` - The hash of the key file that was given, if any.
+///
+/// # Returns
+/// * `Result<()>` - An empty result, or an error if the derivation failed.
+fn derive_dummy_kek(
+    db_conn: &DbConn,
+    usrname: &str,
+    passwd: &str,
+    key_file: Option<&[u8; 32]>,
+) -> Result<()> {
+    let params = db_conn.pick_kdf_params(usrname)?.unwrap_or(MIN_KDF_PARAMS);
+    derive_master_keys(passwd, key_file, &generate_salt(), &params)?;
+    Ok(())
+}
+
+/// Generates a vault key for an account created before vault keys were introduced, where the
+/// password-derived key encrypted the secrets directly, and re-encrypts every secret under it.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user to migrate.
+/// * `legacy_key` - The password-derived key the secrets are currently encrypted with.
+/// * `policy` - The cipher and padding to encrypt the secrets with.
+///
+/// # Returns
+/// * `Result<Zeroizing<[u8; 32]>>` - The new vault key.
+fn migrate_to_vault_key(
+    db_conn: &mut DbConn,
+    user_id: i64,
+    legacy_key: &[u8; 32],
+    policy: EncryptionPolicy,
+) -> Result<Zeroizing<[u8; 32]>> {
+    let vault_key = generate_vault_key();
+
+    let mut secrets = Vec::new();
+    for secret in decrypt_all_secrets(db_conn, user_id, legacy_key, policy)? {
+        secrets.push(encrypt_secret(
+            &vault_key,
+            policy,
+            user_id,
+            secret.id,
+            &secret.meta,
+            &secret.data,
+        )?);
+    }
+
+    db_conn.set_user_vault_key(user_id, &wrap_key(legacy_key, &vault_key)?, secrets)?;
+    Ok(vault_key)
+}
+
+/// Re-encrypts the secrets and versions of a user that are stored in an older format: secrets
+/// with plaintext labels and kinds, versions bound to their secret's row rather than their own,
+/// and ciphertexts produced by another cipher or padding than the vault's.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `vault_key` - The user's vault key.
+/// * `policy` - The encryption policy of the user's vault.
+///
+/// # Returns
+/// * `Result<()>` - An empty result if all secrets are up to date, otherwise an error.
+fn upgrade_secrets(
+    db_conn: &mut DbConn,
+    user_id: i64,
+    vault_key: &[u8; 32],
+    policy: EncryptionPolicy,
+) -> Result<()> {
+    let secrets = reencrypt_outdated_secrets(db_conn, user_id, vault_key, policy)?;
+    let versions = reencrypt_outdated_versions(db_conn, user_id, vault_key, policy)?;
+    if !secrets.is_empty() || !versions.is_empty() {
+        db_conn.update_secrets(user_id, secrets, versions)?;
+    }
+    Ok(())
+}
+
+/// Decrypts the secrets of a user and re-encrypts those that are not up to date for a vault
+/// using the given policy, without storing them.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `vault_key` - The user's vault key.
+/// * `policy` - The cipher and padding the secrets should be encrypted with.
+///
+/// # Returns
+/// * `Result<Vec<SecretRow>>` - The re-encrypted secrets.
+fn reencrypt_outdated_secrets(
+    db_conn: &DbConn,
+    user_id: i64,
+    vault_key: &[u8; 32],
+    policy: EncryptionPolicy,
+) -> Result<Vec<SecretRow>> {
+    let mut secrets = Vec::new();
+    for secret in decrypt_all_secrets(db_conn, user_id, vault_key, policy)? {
+        if secret.outdated {
+            secrets.push(encrypt_secret(
+                vault_key,
+                policy,
+                user_id,
+                secret.id,
+                &secret.meta,
+                &secret.data,
+            )?);
+        }
+    }
+    Ok(secrets)
+}
+
+/// Decrypts the earlier versions of a user's secrets and re-encrypts those that are still bound
+/// to their secret's row or not up to date for a vault using the given policy, without storing
+/// them.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `vault_key` - The user's vault key.
+/// * `policy` - The cipher and padding the versions should be encrypted with.
+///
+/// # Returns
+/// * `Result<Vec<SecretVersionRow>>` - The re-encrypted versions.
+fn reencrypt_outdated_versions(
+    db_conn: &DbConn,
+    user_id: i64,
+    vault_key: &[u8; 32],
+    policy: EncryptionPolicy,
+) -> Result<Vec<SecretVersionRow>> {
+    let mut versions = Vec::new();
+    for version in db_conn.get_all_secret_versions(user_id)? {
+        if version.legacy_context
+            || !is_current_ciphertext(&version.meta, policy)
+            || !is_current_ciphertext(&version.data, policy)
+        {
+            let (meta, data) = decrypt_version(vault_key, user_id, &version)?;
+            let (meta, data) = encrypt_version(
+                vault_key,
+                policy,
+                user_id,
+                version.secret_id,
+                version.id,
+                &meta,
+                &data,
+            )?;
+            versions.push(SecretVersionRow {
+                meta,
+                data,
+                legacy_context: false,
+                ..version
+            });
+        }
+    }
+    Ok(versions)
+}
+
+/// Decrypts all secrets of a user, whatever format they are stored in. Secrets stored with
+/// plaintext labels were unique by exact label only, so their labels are made unique after
+/// normalization by appending a counter where needed. Secrets in the trash are included.
+///
+/// # Arguments
+/// * `db_conn` - The database connection.
+/// * `user_id` - The ID of the user.
+/// * `key` - The key the secrets are encrypted with.
+/// * `policy` - The encryption policy of the user's vault; secrets encrypted otherwise are
+///   outdated.
+///
+/// # Returns
+/// * `Result<Vec<DecryptedSecret>>` - The decrypted secrets.
+fn decrypt_all_secrets(
+    db_conn: &DbConn,
+    user_id: i64,
+    key: &[u8; 32],
+    policy: EncryptionPolicy,
+) -> Result<Vec<DecryptedSecret>> {
+    let mut secrets = Vec::new();
+    for secret in db_conn.get_all_secrets(user_id)? {
+        let id = secret.id;
+        let outdated = !is_current_ciphertext(&secret.data, policy)
+            || !is_current_ciphertext(&secret.meta, policy);
+        let meta = decrypt_meta(key, user_id, id, secret.meta)?;
+        let data = decrypt_using_key(key, secret.data, &secret_context(user_id, id, &meta.kind))?;
+        secrets.push(DecryptedSecret {
+            id,
+            meta,
+            data,
+            outdated,
+        });
+    }
+
+    let mut labels: HashSet<String> = secrets
+        .iter()
+        .map(|secret| normalize_label(&secret.meta.label))
+        .collect();
+    for secret in db_conn.get_legacy_secrets(user_id)? {
+        let data = decrypt_legacy_data(key, user_id, secret.id, &secret.kind, secret.data)?;
+        let mut label = secret.label.clone();
+        let mut counter = 2;
+        while !labels.insert(normalize_label(&label)) {
+            label = format!("{} ({counter})", secret.label);
+            counter += 1;
+        }
+        secrets.push(DecryptedSecret {
+            id: secret.id,
+            meta: SecretMeta {
+                label,
+                kind: secret.kind,
+                encoding: Encoding::Utf8,
+            },
+            data,
+            outdated: true,
+        });
+    }
+
+    for secret in db_conn.get_trashed_secrets(user_id)? {
+        let id = secret.id;
+        let outdated = !is_current_ciphertext(&secret.data, policy)
+            || !is_current_ciphertext(&secret.meta, policy);
+        let meta = decrypt_meta(key, user_id, id, secret.meta)?;
+        let data = decrypt_using_key(key, secret.data, &secret_context(user_id, id, &meta.kind))?;
+        secrets.push(DecryptedSecret {
+            id,
+            meta,
+            data,
+            outdated,
+        });
+    }
+    Ok(secrets)
+}
+
+/// Encrypts the metadata and data of a secret, bound to the row it is stored in, and
+/// computes the blind index of its label.
+///
+/// # Arguments
+/// * `key` - The vault key.
+/// * `policy` - The encryption policy of the vault.
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+/// * `meta` - The label, kind and encoding of the secret.
+/// * `data` - The secret data.
+///
+/// # Returns
+/// * `Result<SecretRow>` - The secret as stored in the database.
+fn encrypt_secret(
+    key: &[u8; 32],
+    policy: EncryptionPolicy,
+    user_id: i64,
+    secret_id: i64,
+    meta: &SecretMeta,
+    data: &[u8],
+) -> Result<SecretRow> {
+    Ok(SecretRow {
+        id: secret_id,
+        label_index: compute_label_index(key, &normalize_label(&meta.label))?,
+        meta: encrypt_using_key(
+            key,
+            policy,
+            &Zeroizing::new(serde_json::to_vec(meta)?),
+            &meta_context(user_id, secret_id),
+        )?,
+        data: encrypt_using_key(
+            key,
+            policy,
+            data,
+            &secret_context(user_id, secret_id, &meta.kind),
+        )?,
+    })
+}
+
+/// Decrypts the metadata and data of a secret. The blind index the secret was found by
+/// must match its decrypted label, so blind indexes swapped between rows are refused.
+///
+/// # Arguments
+/// * `key` - The vault key.
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret` - The secret as stored in the database.
+///
+/// # Returns
+/// * `Result<(SecretMeta, Zeroizing<Vec<u8>>)>` - The metadata and the data of the secret.
+fn decrypt_secret(
+    key: &[u8; 32],
+    user_id: i64,
+    secret: SecretRow,
+) -> Result<(SecretMeta, Zeroizing<Vec<u8>>)> {
+    let meta = decrypt_meta(key, user_id, secret.id, secret.meta)?;
+    if compute_label_index(key, &normalize_label(&meta.label))? != secret.label_index {
+        return Err(anyhow!("secret label does not match its index"));
+    }
+    let data = decrypt_using_key(
+        key,
+        secret.data,
+        &secret_context(user_id, secret.id, &meta.kind),
+    )?;
+    Ok((meta, data))
+}
+
+/// Encrypts the metadata and data a secret had before an edit, bound to the row of the version
+/// they are kept as.
+///
+/// # Arguments
+/// * `key` - The vault key.
+/// * `policy` - The encryption policy of the vault.
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+/// * `version_id` - The ID of the version's row.
+/// * `meta` - The label, kind and encoding of the secret.
+/// * `data` - The secret data.
+///
+/// # Returns
+/// * `Result<(Vec<u8>, Vec<u8>)>` - The encrypted metadata and data of the version.
+fn encrypt_version(
+    key: &[u8; 32],
+    policy: EncryptionPolicy,
+    user_id: i64,
+    secret_id: i64,
+    version_id: i64,
+    meta: &SecretMeta,
+    data: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let context = version_context(user_id, secret_id, version_id);
+    Ok((
+        encrypt_using_key(
+            key,
+            policy,
+            &Zeroizing::new(serde_json::to_vec(meta)?),
+            &context,
+        )?,
+        encrypt_using_key(
+            key,
+            policy,
+            data,
+            &[&context[..], meta.kind.as_bytes()].concat(),
+        )?,
+    ))
+}
+
+/// Decrypts the metadata and data of an earlier version of a secret. Versions kept before
+/// they were bound to their own row are decrypted with the contexts of their secret's row.
+///
+/// # Arguments
+/// * `key` - The vault key.
+/// * `user_id` - The ID of the user owning the secret.
+/// * `version` - The version as stored in the database.
+///
+/// # Returns
+/// * `Result<(SecretMeta, Zeroizing<Vec<u8>>)>` - The metadata and the data of the version.
+fn decrypt_version(
+    key: &[u8; 32],
+    user_id: i64,
+    version: &SecretVersionRow,
+) -> Result<(SecretMeta, Zeroizing<Vec<u8>>)> {
+    // the data context is the metadata context followed by the kind, as for secrets
+    let context = match version.legacy_context {
+        true => meta_context(user_id, version.secret_id),
+        false => version_context(user_id, version.secret_id, version.id),
+    };
+    let json = decrypt_using_key(key, version.meta.clone(), &context)?;
+    let meta: SecretMeta = serde_json::from_slice(&json)?;
+    let data = decrypt_using_key(
+        key,
+        version.data.clone(),
+        &[&context[..], meta.kind.as_bytes()].concat(),
+    )?;
+    Ok((meta, data))
+}
+
+/// Decrypts the label, kind and encoding of a secret.
+///
+/// # Arguments
+/// * `key` - The vault key.
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+/// * `meta` - The encrypted metadata.
+///
+/// # Returns
+/// * `Result<SecretMeta>` - The metadata of the secret.
+fn decrypt_meta(key: &[u8; 32], user_id: i64, secret_id: i64, meta: Vec<u8>) -> Result<SecretMeta> {
+    let json = decrypt_using_key(key, meta, &meta_context(user_id, secret_id))?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Decrypts the data of a secret stored before its metadata was encrypted, accepting ciphertexts
+/// that are not yet bound to their row. Secrets with encrypted metadata were always stored bound,
+/// so their data must never be decrypted this way.
+///
+/// # Arguments
+/// * `key` - The key the data is encrypted with.
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+/// * `kind` - The kind of the secret.
+/// * `data` - The encrypted data.
+///
+/// # Returns
+/// * `Result<Zeroizing<Vec<u8>>>` - The decrypted data.
+fn decrypt_legacy_data(
+    key: &[u8; 32],
+    user_id: i64,
+    secret_id: i64,
+    kind: &str,
+    data: Vec<u8>,
+) -> Result<Zeroizing<Vec<u8>>> {
+    if is_bound_ciphertext(&data) {
+        decrypt_using_key(key, data, &secret_context(user_id, secret_id, kind))
+    } else {
+        decrypt_unbound_using_key(key, data)
+    }
+}
+
+/// Builds the associated data that binds a secret's ciphertext to the row it is stored in.
+///
+/// # Arguments
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+/// * `kind` - The kind of the secret.
+///
+/// # Returns
+/// * `Vec<u8>` - The associated data for the secret.
+fn secret_context(user_id: i64, secret_id: i64, kind: &str) -> Vec<u8> {
+    [
+        &user_id.to_be_bytes()[..],
+        &secret_id.to_be_bytes(),
+        kind.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Builds the associated data that binds a secret's encrypted metadata to its row. It is
+/// shorter than any data context since kinds are never empty, so the two cannot collide.
+///
+/// # Arguments
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+///
+/// # Returns
+/// * `Vec<u8>` - The associated data for the metadata.
+fn meta_context(user_id: i64, secret_id: i64) -> Vec<u8> {
+    [user_id.to_be_bytes(), secret_id.to_be_bytes()].concat()
+}
+
+/// Builds the associated data that binds the encrypted metadata of a version to its row; the
+/// data of the version is bound to it followed by the kind. At 31 bytes, it is longer than the
+/// metadata and data contexts of secrets and than every other context, so none can collide.
+///
+/// # Arguments
+/// * `user_id` - The ID of the user owning the secret.
+/// * `secret_id` - The ID of the secret's row.
+/// * `version_id` - The ID of the version's row.
+///
+/// # Returns
+/// * `Vec<u8>` - The associated data for the version's metadata.
+fn version_context(user_id: i64, secret_id: i64, version_id: i64) -> Vec<u8> {
+    [
+        &b"version"[..],
+        &user_id.to_be_bytes(),
+        &secret_id.to_be_bytes(),
+        &version_id.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Builds the associated data that binds the encrypted two-factor state to its user. Its
+/// length differs from that of every secret context, so the two cannot collide.
+///
+/// # Arguments
+/// * `user_id` - The ID of the user.
+///
+/// # Returns
+/// * `Vec<u8>` - The associated data for the two-factor state.
+fn totp_context(user_id: i64) -> Vec<u8> {
+    [&b"totp"[..], &user_id.to_be_bytes()].concat()
+}
+
+/// Builds the associated data that binds an encrypted folder name to its row. At 22 bytes,
+/// its length differs from that of every secret and two-factor context.
+///
+/// # Arguments
+/// * `user_id` - The ID of the user owning the folder.
+/// * `folder_id` - The ID of the folder's row.
+///
+/// # Returns
+/// * `Vec<u8>` - The associated data for the folder name.
+fn folder_context(user_id: i64, folder_id: i64) -> Vec<u8> {
+    [
+        &b"folder"[..],
+        &user_id.to_be_bytes(),
+        &folder_id.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Builds the associated data that binds an encrypted tag name to its row. At 19 bytes, its
+/// length differs from that of every other context.
+///
+/// # Arguments
+/// * `user_id` - The ID of the user owning the tag.
+/// * `tag_id` - The ID of the tag's row.
+///
+/// # Returns
+/// * `Vec<u8>` - The associated data for the tag name.
+fn tag_context(user_id: i64, tag_id: i64) -> Vec<u8> {
+    [&b"tag"[..], &user_id.to_be_bytes(), &tag_id.to_be_bytes()].concat()
+}
+
+/// Normalizes a label for the blind index, so that labels differing only in case,
+/// surrounding whitespace or Unicode representation are treated as the same label.
+///
+/// # Arguments
+/// * `label` - The label to normalize.
+///
+/// # Returns
+/// * `String` - The normalized label.
+fn normalize_label(label: &str) -> String {
+    label.trim().nfkc().collect::<String>().to_lowercase()
+}
+
+/// Checks that a label is between 3 and 32 characters long, ignoring surrounding whitespace.
+///
+/// # Arguments
+/// * `label` - The label to validate.
+///
+/// # Returns
+/// * `Result<()>` - An empty result if the label is valid, otherwise an error.
+fn validate_label(label: &str) -> Result<()> {
+    let len = label.trim().chars().count();
+    if !(LABEL_MIN_LEN..=LABEL_MAX_LEN).contains(&len) {
+        return Err(anyhow!(
+            "label must be between {LABEL_MIN_LEN} and {LABEL_MAX_LEN} characters"
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a username is between 1 and 24 characters long, as the database requires.
+///
+/// # Arguments
+/// * `username` - The username to validate.
+///
+/// # Returns
+/// * `Result<()>` - An empty result if the username is valid, otherwise an error.
+fn validate_username(username: &str) -> Result<()> {
+    let len = username.chars().count();
+    if !(1..=USERNAME_MAX_LEN).contains(&len) {
+        return Err(anyhow!(
+            "username must be between 1 and {USERNAME_MAX_LEN} characters"
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a folder or tag name is between 1 and 32 characters long, ignoring surrounding
+/// whitespace.
+///
+/// # Arguments
+/// * `name` - The name to validate.
+///
+/// # Returns
+/// * `Result<()>` - An empty result if the name is valid, otherwise an error.
+fn validate_name(name: &str) -> Result<()> {
+    let len = name.trim().chars().count();
+    if !(1..=NAME_MAX_LEN).contains(&len) {
+        return Err(anyhow!(
+            "name must be between 1 and {NAME_MAX_LEN} characters"
+        ));
+    }
+    Ok(())
+}
+
+impl Drop for Session {
+    /// Ensures the database connection is closed and the encryption key is zeroized when the session is dropped.
+    fn drop(&mut self) {
+        self.db_conn.close().expect("should close db connection");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::hash_password;
+    use crate::encryption::take_kdf_runs;
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
 
     /// A struct representing a test database that generates a unique temporary SQLite file.
     struct TestDb {
         path: PathBuf,
     }
 
-    impl TestDb {
-        /// Creates a new test database with a unique file path.
-        /// This uses an atomic counter to ensure uniqueness across test runs.
-        fn new() -> Self {
-            static TEST_ID: AtomicUsize = AtomicUsize::new(0);
-            let test_id = TEST_ID.fetch_add(1, Ordering::SeqCst);
-            let mut path = std::env::temp_dir();
-            path.push(format!("lockkey_lib_test_db_{test_id:?}.sqlite"));
-            TestDb { path }
-        }
+    impl TestDb {
+        /// Creates a new test database with a unique file path.
+        /// This uses an atomic counter to ensure uniqueness across test runs.
+        fn new() -> Self {
+            static TEST_ID: AtomicUsize = AtomicUsize::new(0);
+            let test_id = TEST_ID.fetch_add(1, Ordering::SeqCst);
+            let mut path = std::env::temp_dir();
+            path.push(format!("lockkey_lib_test_db_{test_id:?}.sqlite"));
+            TestDb { path }
+        }
+
+        /// Returns the file path of the test database as a string slice.
+        fn get_path(&self) -> &str {
+            &self.path.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TestDb {
+        /// Deletes the test database file when the struct goes out of scope.
+        /// This attempts to delete the file up to five times if the operation initially fails.
+        fn drop(&mut self) {
+            let mut attempts = 0;
+            while attempts < 5 {
+                if fs::remove_file(&self.path).is_ok() {
+                    return;
+                }
+
+                attempts += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+            fs::remove_file(&self.path).expect("should delete the tst database file");
+        }
+    }
+
+    /// Test to verify that a secret can be stored and retrieved successfully.
+    #[test]
+    fn can_store_and_retrieve_secret() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let label = String::from("mypass");
+        let secret = String::from("mysecret");
+
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        sess.store_secret("password", &label, secret.to_string())
+            .unwrap();
+
+        let retrieved_secret = sess.retrieve_secret(&label).unwrap();
+
+        assert_eq!(
+            Some(Secret {
+                label,
+                kind: "password".to_string(),
+                data: secret.into()
+            }),
+            retrieved_secret
+        );
+    }
+
+    /// Test to verify that labels for stored secrets can be retrieved.
+    #[test]
+    fn can_retrieve_labels() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let label1 = String::from("mypass");
+        let label2 = String::from("mypass2");
+        let label3 = String::from("mypass3");
+        let secret = String::from("mysecret");
+
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        sess.store_secret("text", &label1, secret.to_string())
+            .expect("should insert secret into db");
+        sess.store_secret("password", &label2, secret.to_string())
+            .expect("should insert secret into db");
+        sess.store_secret("text", &label3, secret.to_string())
+            .expect("should insert secret into db");
+
+        let labels = vec![label1.to_string(), label2.to_string(), label3.to_string()];
+        let query = sess.retrieve_labels().expect("should retrieve labels");
+
+        for result in query {
+            assert!(labels.contains(&result.label));
+        }
+    }
+
+    /// Test to verify that retrieving a non-existent secret returns `None`.
+    #[test]
+    fn should_return_empty_on_nonexistent_labels() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        let data = sess.retrieve_secret("test").unwrap();
+        assert_eq!(data, None);
+    }
+
+    /// Test to verify that a secret can be deleted successfully.
+    #[test]
+    fn can_delete_data() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let label = String::from("mypass");
+        let secret = String::from("mysecret");
+
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        sess.store_secret("password", &label, secret.to_string())
+            .unwrap();
+
+        // check if data is inserted successfully
+        let retrieved_secret = sess
+            .retrieve_secret(&label)
+            .expect("should retrieve secret from db");
+
+        assert_eq!(
+            retrieved_secret,
+            Some(Secret {
+                label: label.clone(),
+                kind: "password".to_string(),
+                data: secret.into()
+            })
+        );
+
+        // delete data
+        sess.delete_secret(&label).expect("should delete data");
+
+        // check if data is deleted
+        let retrieved_secret = sess
+            .retrieve_secret(&label)
+            .expect("should retrieve secret from db");
+        assert_eq!(retrieved_secret, None);
+    }
+
+    /// Test to verify that deleted secrets go to the trash, from which they can be restored or
+    /// deleted for good, and that they are purged once the trash retention has passed.
+    #[test]
+    fn can_restore_secrets_from_trash() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        for label in ["bank", "mail", "cloud"] {
+            sess.store_secret("password", label, "secret".to_string())
+                .unwrap();
+        }
+        sess.tag_secret("bank", "money").unwrap();
+        sess.edit_secret("bank", "Bank", "new secret".to_string())
+            .unwrap();
+        let before = unix_now();
+        for label in ["bank", "mail", "cloud"] {
+            sess.delete_secret(label).unwrap();
+        }
+        assert!(sess.retrieve_labels().unwrap().is_empty());
+        let trash = sess.trash().unwrap();
+        assert_eq!(trash.len(), 3);
+        let bank = trash.iter().find(|x| x.label == "Bank").unwrap();
+        assert_eq!(bank.kind, "password");
+        assert!(bank.deleted_at >= before);
+
+        sess.store_secret("text", "bank", "other".to_string())
+            .unwrap();
+        assert!(sess.restore_secret(bank.id).is_err());
+        sess.delete_secret("bank").unwrap();
+        sess.restore_secret(bank.id).unwrap();
+        assert!(sess.restore_secret(bank.id).is_err());
+        assert_eq!(
+            *sess.retrieve_secret("bank").unwrap().unwrap().data,
+            "new secret"
+        );
+        assert_eq!(sess.secret_versions("bank").unwrap().len(), 1);
+        assert_eq!(sess.retrieve_labels().unwrap()[0].tags, ["money"]);
+
+        let newest = sess.trash().unwrap()[0].id;
+        sess.delete_secret_permanently(newest).unwrap();
+        assert!(sess.delete_secret_permanently(newest).is_err());
+        assert!(sess.restore_secret(newest).is_err());
+        assert_eq!(sess.trash().unwrap().len(), 2);
+
+        assert_eq!(
+            sess.trash_retention_days().unwrap(),
+            DEFAULT_TRASH_RETENTION_DAYS
+        );
+        sess.set_trash_retention_days(7).unwrap();
+        assert_eq!(sess.trash().unwrap().len(), 2);
+        drop(sess);
+
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        conn.execute(
+            "UPDATE secrets SET deleted_at = ?1 WHERE id = (SELECT MIN(id) FROM secrets
+            WHERE deleted_at IS NOT NULL)",
+            [before - 7 * SECS_PER_DAY],
+        )
+        .unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.trash().unwrap().len(), 1);
+        sess.set_trash_retention_days(0).unwrap();
+        assert!(sess.trash().unwrap().is_empty());
+        assert_eq!(sess.retrieve_labels().unwrap().len(), 1);
+
+        sess.set_trash_retention_days(7).unwrap();
+        sess.delete_secret("bank").unwrap();
+        drop(sess);
+        let later = unix_now() + 7 * SECS_PER_DAY + 1;
+        let sess = Session::open(&username, passwd, None, None, later, db_path).unwrap();
+        assert!(sess.trash().unwrap().is_empty());
+    }
+
+    /// Test to verify that secrets record when they were stored, edited and read, and that
+    /// labels can be sorted by label or by any of these times.
+    #[test]
+    fn can_sort_labels_by_timestamps() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd, db_path).unwrap();
+
+        let before = unix_now();
+        for label in ["bank", "Cloud", "api key"] {
+            sess.store_secret("password", label, "secret".to_string())
+                .unwrap();
+        }
+        let timestamps = sess.retrieve_labels().unwrap()[0].timestamps;
+        assert!(timestamps.created_at.unwrap() >= before);
+        assert_eq!(timestamps.updated_at, timestamps.created_at);
+        assert_eq!(timestamps.last_used_at, None);
+
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        for (id, created_at, updated_at) in [(1, 300, 400), (2, 100, 500), (3, 200, 200)] {
+            conn.execute(
+                "UPDATE secrets SET created_at = ?1, updated_at = ?2 WHERE id = ?3",
+                rusqlite::params![created_at, updated_at, id],
+            )
+            .unwrap();
+        }
+        sess.retrieve_secret("cloud").unwrap();
+
+        let sorted = |order| {
+            sess.retrieve_sorted_labels(order)
+                .unwrap()
+                .into_iter()
+                .map(|x| x.label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(LabelOrder::Label), ["api key", "bank", "Cloud"]);
+        assert_eq!(sorted(LabelOrder::Created), ["bank", "api key", "Cloud"]);
+        assert_eq!(sorted(LabelOrder::Updated), ["Cloud", "bank", "api key"]);
+        assert_eq!(sorted(LabelOrder::LastUsed)[0], "Cloud");
+    }
+
+    /// Test to verify that secrets can be organized in folders and tagged, that labels can be
+    /// listed by folder or tag, and that folder and tag names are stored encrypted.
+    #[test]
+    fn can_organize_secrets_in_folders_and_tags() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd, db_path).unwrap();
+
+        for label in ["bank", "mail", "cloud"] {
+            sess.store_secret("password", label, "secret".to_string())
+                .unwrap();
+        }
+        let work = sess.create_folder("Work", None).unwrap();
+        let archive = sess.create_folder(" Archive ", Some(work)).unwrap();
+        assert!(sess.create_folder(" ", None).is_err());
+        assert!(sess.create_folder("Orphan", Some(42)).is_err());
+        sess.rename_folder(archive, "Old").unwrap();
+        assert!(sess.move_folder(work, Some(archive)).is_err());
+        assert_eq!(
+            sess.folders().unwrap(),
+            vec![
+                Folder {
+                    id: work,
+                    parent_id: None,
+                    name: "Work".to_string(),
+                },
+                Folder {
+                    id: archive,
+                    parent_id: Some(work),
+                    name: "Old".to_string(),
+                },
+            ]
+        );
+
+        sess.move_secret("Mail", Some(work)).unwrap();
+        sess.move_secret("cloud", Some(archive)).unwrap();
+        sess.tag_secret("mail", "Shared").unwrap();
+        sess.tag_secret("bank", "shared ").unwrap();
+        sess.tag_secret("bank", "Money").unwrap();
+        assert!(sess.tag_secret("nothing", "Money").is_err());
+        assert_eq!(sess.tags().unwrap(), ["Money", "Shared"]);
+
+        let listed = |filter: LabelFilter| {
+            sess.list_labels(Some(&filter), Some(LabelOrder::Label))
+                .unwrap()
+                .into_iter()
+                .map(|x| x.label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(listed(LabelFilter::Folder(None)), ["bank"]);
+        assert_eq!(listed(LabelFilter::Folder(Some(work))), ["mail"]);
+        assert_eq!(
+            listed(LabelFilter::Tag("SHARED".to_string())),
+            ["bank", "mail"]
+        );
+        assert!(listed(LabelFilter::Tag("missing".to_string())).is_empty());
+        let bank = sess.list_labels(None, Some(LabelOrder::Label)).unwrap();
+        assert_eq!(bank[0].tags, ["Money", "Shared"]);
+        assert_eq!(bank[2].folder_id, Some(work));
+
+        sess.untag_secret("bank", "money").unwrap();
+        assert_eq!(sess.tags().unwrap(), ["Shared"]);
+
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let names: Vec<Vec<u8>> = conn
+            .prepare("SELECT name FROM folders UNION ALL SELECT name FROM tags")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(names.len(), 3);
+        for name in names {
+            assert!(!name.windows(3).any(|w| w == b"Old" || w == b"Sha"));
+        }
+
+        sess.delete_folder(work, FolderDeletion::MoveToParent)
+            .unwrap();
+        assert_eq!(sess.folders().unwrap()[0].parent_id, None);
+        assert_eq!(listed(LabelFilter::Folder(None)), ["bank", "mail"]);
+        sess.delete_folder(archive, FolderDeletion::Cascade)
+            .unwrap();
+        assert!(sess.folders().unwrap().is_empty());
+        assert!(sess.retrieve_secret("cloud").unwrap().is_none());
+
+        let trash = sess.trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].label, "cloud");
+        sess.restore_secret(trash[0].id).unwrap();
+        assert_eq!(listed(LabelFilter::Folder(None)), ["bank", "cloud", "mail"]);
+        assert!(sess.retrieve_secret("cloud").unwrap().is_some());
+    }
+
+    /// Test to verify that edits keep earlier versions up to the history limit, that they can
+    /// be revealed and restored, and that restoring keeps the replaced version.
+    #[test]
+    fn can_restore_secret_versions() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd, db_path).unwrap();
+        assert_eq!(sess.history_limit().unwrap(), DEFAULT_HISTORY_LIMIT);
+
+        sess.store_secret("password", "mail", "first".to_string())
+            .unwrap();
+        sess.edit_secret("mail", "mail", "second".to_string())
+            .unwrap();
+        sess.edit_secret("mail", "email", "third".to_string())
+            .unwrap();
+        assert!(sess.secret_versions("mail").is_err());
+
+        let versions = sess.secret_versions("email").unwrap();
+        assert_eq!(
+            versions
+                .iter()
+                .map(|version| version.label.as_str())
+                .collect::<Vec<_>>(),
+            ["mail", "mail"]
+        );
+        let first = sess
+            .retrieve_secret_version("email", versions[1].id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(*first.data, b"first");
+        assert_eq!(sess.retrieve_secret_version("email", -1).unwrap(), None);
+
+        sess.restore_secret_version("email", versions[1].id)
+            .unwrap();
+        assert_eq!(
+            *sess.retrieve_secret("mail").unwrap().unwrap().data,
+            "first"
+        );
+        let versions = sess.secret_versions("mail").unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0].label, "email");
+        assert_eq!(
+            *sess
+                .retrieve_secret_version("mail", versions[0].id)
+                .unwrap()
+                .unwrap()
+                .data,
+            b"third"
+        );
+
+        sess.set_history_limit(1).unwrap();
+        assert_eq!(sess.history_limit().unwrap(), 1);
+        assert_eq!(sess.secret_versions("mail").unwrap(), versions[..1]);
+        assert!(sess.restore_secret_version("mail", versions[1].id).is_err());
+    }
+
+    /// Test to verify that versions are bound to their own row, so that a version copied over
+    /// its secret is refused, and that versions kept before that are re-bound on login.
+    #[test]
+    fn should_bind_versions_to_their_row() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", "mail", "first".to_string())
+            .unwrap();
+        sess.edit_secret("mail", "mail", "second".to_string())
+            .unwrap();
+
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let copy_version_over_secret = || {
+            conn.execute(
+                "UPDATE secrets SET (meta, data) = (SELECT meta, data FROM secret_versions)",
+                [],
+            )
+            .unwrap()
+        };
+        let current: (Vec<u8>, Vec<u8>) = conn
+            .query_row("SELECT meta, data FROM secrets", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        copy_version_over_secret();
+        assert!(sess.retrieve_secret("mail").is_err());
+        conn.execute(
+            "UPDATE secrets SET meta = ?1, data = ?2",
+            [&current.0, &current.1],
+        )
+        .unwrap();
+
+        // a version kept before versions were bound to their own row
+        let meta = SecretMeta {
+            label: "mail".to_string(),
+            kind: "password".to_string(),
+            encoding: Encoding::Utf8,
+        };
+        let legacy =
+            encrypt_secret(&sess.key, sess.policy, sess.user_id, 1, &meta, b"first").unwrap();
+        conn.execute(
+            "UPDATE secret_versions SET meta = ?1, data = ?2, legacy_context = 1",
+            [&legacy.meta, &legacy.data],
+        )
+        .unwrap();
+        let version = sess.secret_versions("mail").unwrap()[0].id;
+        assert_eq!(
+            *sess
+                .retrieve_secret_version("mail", version)
+                .unwrap()
+                .unwrap()
+                .data,
+            b"first"
+        );
+        drop(sess);
+
+        let sess = Session::new(&username, passwd, db_path).unwrap();
+        let legacy_context: bool = conn
+            .query_row("SELECT legacy_context FROM secret_versions", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(!legacy_context);
+        assert_eq!(
+            *sess
+                .retrieve_secret_version("mail", version)
+                .unwrap()
+                .unwrap()
+                .data,
+            b"first"
+        );
+        copy_version_over_secret();
+        assert!(sess.retrieve_secret("mail").is_err());
+    }
+
+    /// Test to verify that strengthening the KDF parameters keeps secrets readable
+    /// and that the new parameters are used on the next login.
+    #[test]
+    fn can_strengthen_kdf_params() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let label = String::from("mypass");
+        let secret = String::from("mysecret");
+
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", &label, secret.clone())
+            .unwrap();
+
+        let current = sess.kdf_params().unwrap();
+        let stronger = KdfParams {
+            t_cost: current.t_cost + 1,
+            ..current
+        };
+        assert!(sess.strengthen_kdf("wrong_pass".into(), stronger).is_err());
+        assert_eq!(
+            sess.strengthen_kdf(passwd.clone(), stronger).unwrap(),
+            stronger
+        );
+
+        // parameters are never lowered
+        assert_eq!(
+            sess.strengthen_kdf(passwd.clone(), KdfParams::default())
+                .unwrap(),
+            stronger
+        );
+
+        // parameters that would make the vault impractical to unlock are refused
+        for too_strong in [
+            KdfParams {
+                m_cost: MAX_KDF_PARAMS.m_cost + 1,
+                ..stronger
+            },
+            KdfParams {
+                t_cost: MAX_KDF_PARAMS.t_cost + 1,
+                ..stronger
+            },
+            KdfParams {
+                p_cost: MAX_KDF_PARAMS.p_cost + 1,
+                ..stronger
+            },
+        ] {
+            assert!(sess.strengthen_kdf(passwd.clone(), too_strong).is_err());
+        }
+        assert_eq!(sess.kdf_params().unwrap(), stronger);
+        drop(sess);
+
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.kdf_params().unwrap(), stronger);
+        assert_eq!(
+            sess.retrieve_secret(&label).unwrap(),
+            Some(Secret {
+                label,
+                kind: "password".to_string(),
+                data: secret.into()
+            })
+        );
+    }
+
+    /// Test to verify that the master password can be changed and secrets stay readable.
+    #[test]
+    fn can_change_master_password() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let new_passwd = String::from("amber-quill-harbor-53");
+        let label = String::from("mypass");
+        let secret = String::from("mysecret");
+
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", &label, secret.clone())
+            .unwrap();
+
+        assert!(sess
+            .change_master_password("wrong_pass".into(), new_passwd.clone())
+            .is_err());
+        sess.change_master_password(passwd.clone(), new_passwd.clone())
+            .unwrap();
+        drop(sess);
+
+        assert!(Session::new(&username, passwd.clone(), db_path).is_err());
+        let sess = Session::new(&username, new_passwd.clone(), db_path).unwrap();
+        assert_eq!(
+            sess.retrieve_secret(&label).unwrap(),
+            Some(Secret {
+                label,
+                kind: "password".to_string(),
+                data: secret.into()
+            })
+        );
+    }
+
+    /// Test to verify that weak master passwords are refused when creating an account and
+    /// changing the password, down to the minimum score the user set.
+    #[test]
+    fn should_enforce_password_strength() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+
+        let err = create_new_account(&username, "password1".into(), db_path)
+            .err()
+            .unwrap();
+        let weak = err.downcast_ref::<WeakPasswordError>().unwrap();
+        assert_eq!(weak.min_score, MIN_PASSWORD_SCORE);
+        assert!(weak.strength.warning.is_some());
+        assert!(create_new_account(&username, "test_user2024".into(), db_path).is_err());
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.min_password_score().unwrap(), MIN_PASSWORD_SCORE);
+        let err = sess
+            .change_master_password(passwd.clone(), "qwerty123".into())
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<WeakPasswordError>().is_some());
+
+        assert!(sess.set_min_password_score(MAX_PASSWORD_SCORE + 1).is_err());
+        sess.set_min_password_score(0).unwrap();
+        assert_eq!(sess.min_password_score().unwrap(), 0);
+        sess.change_master_password(passwd, "qwerty123".into())
+            .unwrap();
+        drop(sess);
+
+        Session::new(&username, "qwerty123".into(), db_path).unwrap();
+    }
+
+    /// Test to verify that a vault created with a key file only unlocks with the password and
+    /// that key file, and that the requirement survives a password change until it is removed.
+    #[test]
+    fn can_require_key_file_to_unlock() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let key_file = create_key_file().to_vec();
+
+        let recovery_key = create_new_account_with_key_file(
+            &username,
+            passwd.clone(),
+            key_file.clone(),
+            false,
+            db_path,
+        )
+        .unwrap();
+        assert!(recovery_key.is_none());
+
+        let err = Session::new(&username, passwd.clone(), db_path)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoginError>(),
+            Some(&LoginError::InvalidCredentials)
+        );
+        let err = Session::new_with_key_file(
+            &username,
+            passwd.clone(),
+            create_key_file().to_vec(),
+            db_path,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoginError>(),
+            Some(&LoginError::InvalidCredentials)
+        );
+        assert!(Session::new_with_key_file(
+            &username,
+            "wrong_pass".into(),
+            key_file.clone(),
+            db_path
+        )
+        .is_err());
+        // the failures above would throttle the next attempt
+        DbConn::new(db_path)
+            .unwrap()
+            .reset_failed_logins(1)
+            .unwrap();
+
+        let sess = Session::new_with_key_file(&username, passwd.clone(), key_file.clone(), db_path)
+            .unwrap();
+        assert!(sess.has_key_file());
+        sess.store_secret("password", "mypass", "mysecret".to_string())
+            .unwrap();
+        sess.change_master_password(passwd.clone(), "fjord-lantern-mosaic-7".into())
+            .unwrap();
+        drop(sess);
+
+        assert!(Session::new(&username, "fjord-lantern-mosaic-7".into(), db_path).is_err());
+        let mut sess = Session::new_with_key_file(
+            &username,
+            "fjord-lantern-mosaic-7".into(),
+            key_file.clone(),
+            db_path,
+        )
+        .unwrap();
+        sess.set_key_file("fjord-lantern-mosaic-7".into(), None)
+            .unwrap();
+        drop(sess);
+
+        let err = Session::new_with_key_file(
+            &username,
+            "fjord-lantern-mosaic-7".into(),
+            key_file,
+            db_path,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoginError>(),
+            Some(&LoginError::InvalidCredentials)
+        );
+        let sess = Session::new(&username, "fjord-lantern-mosaic-7".into(), db_path).unwrap();
+        assert!(!sess.has_key_file());
+        assert_eq!(
+            *sess.retrieve_secret("mypass").unwrap().unwrap().data,
+            "mysecret"
+        );
+    }
+
+    /// Test to verify that a vault enrolled in two-factor authentication only unlocks with a
+    /// fresh code or an unused backup code, that a wrong code counts as a failed login, and
+    /// that the recovery key bypasses it.
+    #[test]
+    fn can_require_totp_code_to_unlock() {
+        const NOW: u64 = 1_700_000_000;
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let recovery_key =
+            create_new_account_with_recovery_key(&username, passwd.clone(), db_path).unwrap();
+
+        let mut sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert!(!sess.has_totp().unwrap());
+        let enrollment = sess.enroll_totp().unwrap();
+        assert!(enrollment
+            .uri
+            .starts_with("otpauth://totp/Lockkey:test_user?"));
+        let pending = sess.pending_totp.as_ref().unwrap();
+        let (code, next_code) = (pending.code_at(NOW), pending.code_at(NOW + 30));
+        assert!(sess.confirm_totp_at("000000", NOW).is_err());
+        assert!(!sess.has_totp().unwrap());
+        sess.confirm_totp_at(&code, NOW).unwrap();
+        assert!(sess.has_totp().unwrap());
+        sess.store_secret("password", "mypass", "mysecret".to_string())
+            .unwrap();
+        drop(sess);
+
+        let err = Session::new(&username, passwd.clone(), db_path)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoginError>(),
+            Some(&LoginError::TotpRequired)
+        );
+        let err = Session::open(&username, passwd.clone(), None, Some(&code), NOW, db_path)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "invalid two-factor code");
+        assert!(Session::open(
+            &username,
+            "wrong_pass".into(),
+            None,
+            Some(&next_code),
+            NOW + 30,
+            db_path
+        )
+        .is_err());
+
+        let conn = DbConn::new(db_path).unwrap();
+        assert_eq!(conn.get_login_attempts(1).unwrap().failed, 2);
+        Session::open(
+            &username,
+            passwd.clone(),
+            None,
+            Some(&next_code),
+            NOW + 30,
+            db_path,
+        )
+        .unwrap();
+        assert_eq!(conn.get_login_attempts(1).unwrap().failed, 0);
+        assert!(Session::open(
+            &username,
+            passwd.clone(),
+            None,
+            Some(&next_code),
+            NOW + 30,
+            db_path
+        )
+        .is_err());
+
+        let backup_code = &enrollment.backup_codes[0];
+        Session::open(
+            &username,
+            passwd.clone(),
+            None,
+            Some(backup_code),
+            NOW + 60,
+            db_path,
+        )
+        .unwrap();
+        assert!(Session::open(
+            &username,
+            passwd.clone(),
+            None,
+            Some(backup_code),
+            NOW + 60,
+            db_path
+        )
+        .is_err());
+
+        let sess = Session::new_with_recovery_key(
+            &username,
+            recovery_key.to_string(),
+            "fjord-lantern-mosaic-7".into(),
+            db_path,
+        )
+        .unwrap();
+        assert!(sess.has_totp().unwrap());
+        assert!(sess.disable_totp(passwd).is_err());
+        sess.disable_totp("fjord-lantern-mosaic-7".into()).unwrap();
+        drop(sess);
+
+        let sess = Session::new(&username, "fjord-lantern-mosaic-7".into(), db_path).unwrap();
+        assert_eq!(
+            *sess.retrieve_secret("mypass").unwrap().unwrap().data,
+            "mysecret"
+        );
+    }
+
+    /// Test to verify that a locked session reopens with the quick-unlock PIN, keeping its key
+    /// file, and that the wrapped keys are destroyed after too many wrong PINs.
+    #[test]
+    fn can_reopen_locked_session_with_pin() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let key_file = create_key_file().to_vec();
+        create_new_account_with_key_file(
+            &username,
+            passwd.clone(),
+            key_file.clone(),
+            false,
+            db_path,
+        )
+        .unwrap();
+
+        let mut sess = Session::new_with_key_file(&username, passwd, key_file, db_path).unwrap();
+        sess.store_secret("password", "mypass", "mysecret".to_string())
+            .unwrap();
+        assert!(sess.lock().is_err());
+        assert!(sess.set_quick_unlock_pin(Some("123".into())).is_err());
+        assert!(!sess.has_quick_unlock());
+        sess.set_quick_unlock_pin(Some("1234".into())).unwrap();
+
+        let mut locked = sess.lock().unwrap();
+        assert_eq!(*sess.key, [0u8; 32]);
+        assert!(sess.retrieve_secret("mypass").is_err());
+        drop(sess);
+
+        assert!(locked.unlock("0000".into()).is_err());
+        assert_eq!(locked.attempts_left(), MAX_PIN_ATTEMPTS - 1);
+        let mut sess = locked.unlock("1234".into()).unwrap();
+        assert!(sess.has_key_file());
+        assert!(sess.has_quick_unlock());
+        assert_eq!(
+            *sess.retrieve_secret("mypass").unwrap().unwrap().data,
+            "mysecret"
+        );
+        assert!(locked.unlock("1234".into()).is_err());
+
+        let mut locked = sess.lock().unwrap();
+        drop(sess);
+        for _ in 0..MAX_PIN_ATTEMPTS {
+            assert!(locked.unlock("0000".into()).is_err());
+        }
+        assert_eq!(locked.attempts_left(), 0);
+        assert!(locked.unlock("1234".into()).is_err());
+    }
+
+    /// Test to verify that failed logins are counted, that further attempts are refused with a
+    /// structured error while throttled or locked out, and that the counters are reset by a
+    /// successful login or the recovery key.
+    #[test]
+    fn should_throttle_failed_logins() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let recovery_key =
+            create_new_account_with_recovery_key(&username, passwd.clone(), db_path).unwrap();
+        let db_conn = DbConn::new(db_path).unwrap();
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let set_failures = |failed: u32, at: u64| {
+            conn.execute(
+                "UPDATE users SET failed_logins = ?1, last_failed_login = ?2",
+                rusqlite::params![failed, at],
+            )
+            .unwrap();
+        };
+
+        for _ in 0..2 {
+            let err = Session::new(&username, "wrong_pass".into(), db_path)
+                .err()
+                .unwrap();
+            assert_eq!(
+                err.downcast_ref::<LoginError>(),
+                Some(&LoginError::InvalidCredentials)
+            );
+        }
+        assert_eq!(db_conn.get_login_attempts(1).unwrap().failed, 2);
+
+        let now = unix_now();
+        set_failures(10, now);
+        let err = Session::new(&username, passwd.clone(), db_path)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoginError>(),
+            Some(&LoginError::Throttled {
+                retry_at: now + 128
+            })
+        );
+
+        set_failures(10, now - 1000);
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(db_conn.get_login_attempts(1).unwrap().failed, 0);
+
+        assert!(sess
+            .set_lockout_threshold(Some(MIN_LOCKOUT_THRESHOLD - 1))
+            .is_err());
+        sess.set_lockout_threshold(Some(MIN_LOCKOUT_THRESHOLD))
+            .unwrap();
+        assert_eq!(
+            sess.lockout_threshold().unwrap(),
+            Some(MIN_LOCKOUT_THRESHOLD)
+        );
+        drop(sess);
+
+        set_failures(MIN_LOCKOUT_THRESHOLD, 0);
+        let err = Session::new(&username, passwd.clone(), db_path)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<LoginError>(),
+            Some(&LoginError::LockedOut)
+        );
+
+        let sess = Session::new_with_recovery_key(
+            &username,
+            recovery_key.to_string(),
+            passwd.clone(),
+            db_path,
+        )
+        .unwrap();
+        assert_eq!(db_conn.get_login_attempts(1).unwrap().failed, 0);
+        sess.remove_recovery_key().unwrap();
+        assert_eq!(sess.lockout_threshold().unwrap(), None);
+        assert!(sess
+            .set_lockout_threshold(Some(MIN_LOCKOUT_THRESHOLD))
+            .is_err());
+        drop(sess);
+
+        assert!(Session::new(&username, passwd, db_path).is_ok());
+    }
+
+    /// Test to verify that an unknown username does the same Argon2 work as a wrong password
+    /// for one of the existing users, even if their parameters differ, and fails with the same
+    /// error, at login and at signup.
+    #[test]
+    fn should_not_reveal_existing_usernames() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        create_new_account("newer_user", passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        let newer = Session::new("newer_user", passwd.clone(), db_path).unwrap();
+        let params = sess.kdf_params().unwrap().max(newer.kdf_params().unwrap());
+        let stronger = KdfParams {
+            t_cost: params.t_cost + 1,
+            ..params
+        };
+        sess.strengthen_kdf(passwd.clone(), stronger).unwrap();
+        let newer_params = newer.kdf_params().unwrap();
+        drop(sess);
+        drop(newer);
+        take_kdf_runs();
+
+        let wrong_passwd_runs = |usrname: &str| {
+            let err = Session::new(usrname, "wrong_pass".into(), db_path)
+                .err()
+                .unwrap();
+            assert_eq!(
+                err.downcast_ref::<LoginError>(),
+                Some(&LoginError::InvalidCredentials)
+            );
+            take_kdf_runs()
+        };
+        let existing_runs = [
+            wrong_passwd_runs(&username),
+            wrong_passwd_runs("newer_user"),
+        ];
+        assert_eq!(existing_runs, [vec![stronger], vec![newer_params]]);
+
+        // each unknown username does the work of one of the existing users, and both of them
+        // are picked by some username
+        let unknown_runs: Vec<_> = (0..16)
+            .map(|i| wrong_passwd_runs(&format!("nobody{i}")))
+            .collect();
+        for runs in &existing_runs {
+            assert!(unknown_runs.contains(runs));
+        }
+        for runs in &unknown_runs {
+            assert!(existing_runs.contains(runs));
+        }
+        let picked = unknown_runs[0].clone();
+        assert_eq!(wrong_passwd_runs("nobody0"), picked);
+
+        let taken = create_new_account(&username, passwd.clone(), db_path)
+            .err()
+            .unwrap();
+        let taken_runs = take_kdf_runs();
+        create_new_account("other_user", passwd.clone(), db_path).unwrap();
+        assert_eq!(taken_runs.len(), take_kdf_runs().len());
+        assert_eq!(taken.to_string(), "this username is not available");
+
+        // a username the database would refuse is rejected before any Argon2 work
+        let too_long = create_new_account(&"a".repeat(25), passwd.clone(), db_path)
+            .err()
+            .unwrap();
+        assert!(take_kdf_runs().is_empty());
+        assert!(too_long.to_string().contains("between 1 and 24"));
+        assert!(create_new_account("", passwd, db_path).is_err());
+    }
+
+    /// Test to verify that repeated failed logins get the same errors after the same Argon2
+    /// work whether the username exists or not until the lockout threshold of the existing
+    /// user is reached, after which the right password gets the same answer as a wrong one.
+    #[test]
+    fn should_throttle_unknown_usernames_like_existing_ones() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account_with_recovery_key(&username, passwd.clone(), db_path).unwrap();
+        DbConn::new(db_path)
+            .unwrap()
+            .set_lockout_threshold(1, Some(MIN_LOCKOUT_THRESHOLD))
+            .unwrap();
+        take_kdf_runs();
+
+        let attempts = |usrname: &str| {
+            [1000, 1000, 1000, 1000, 1000, 2000, 3000, 4000, 4000, 5000]
+                .into_iter()
+                .map(|now| {
+                    let err = Session::open(usrname, "wrong_pass".into(), None, None, now, db_path)
+                        .err()
+                        .unwrap();
+                    (err.downcast::<LoginError>().unwrap(), take_kdf_runs())
+                })
+                .collect::<Vec<_>>()
+        };
+        let existing = attempts(&username);
+        let unknown = attempts("nobody");
+
+        // only the lockout of the existing user, reached after 5 failures, tells them apart
+        assert_eq!(existing[..7], unknown[..7]);
+        assert_eq!(existing[8], unknown[8]);
+        assert!(existing.iter().zip(&unknown).all(|(x, y)| x.1 == y.1));
+        assert_eq!(unknown[9].0, LoginError::InvalidCredentials);
+        assert_eq!(existing[0].0, LoginError::InvalidCredentials);
+        assert_eq!(existing[0].1.len(), 1);
+        assert_eq!(existing[3].0, LoginError::Throttled { retry_at: 1001 });
+        assert!(existing[3].1.is_empty());
+        assert_eq!(existing[7].0, LoginError::LockedOut);
+        assert_eq!(existing[9].0, LoginError::LockedOut);
+        let failed = || {
+            DbConn::new(db_path)
+                .unwrap()
+                .get_login_attempts(1)
+                .unwrap()
+                .failed
+        };
+        assert_eq!(failed(), 7);
+
+        // once locked out, the right password gets the same answer as a wrong one
+        let attempt = |passwd: &str, now| {
+            let err = Session::open(&username, passwd.into(), None, None, now, db_path)
+                .err()
+                .unwrap();
+            (err.downcast::<LoginError>().unwrap(), take_kdf_runs())
+        };
+        let right = attempt(&passwd, 6000);
+        let wrong = attempt("wrong_pass", 7000);
+        assert_eq!(right, wrong);
+        assert_eq!(right.0, LoginError::LockedOut);
+        assert_eq!(right.1.len(), 1);
+        assert_eq!(failed(), 9);
+    }
+
+    /// Test to verify that a recovery key unlocks the vault and forces a new master password.
+    #[test]
+    fn can_recover_account_with_recovery_key() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        let new_passwd = String::from("amber-quill-harbor-53");
+        let label = String::from("mypass");
+        let secret = String::from("mysecret");
+
+        let recovery_key =
+            create_new_account_with_recovery_key(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", &label, secret.clone())
+            .unwrap();
+        drop(sess);
+
+        let wrong_key = generate_recovery_key();
+        assert!(Session::new_with_recovery_key(
+            &username,
+            wrong_key.to_string(),
+            new_passwd.clone(),
+            db_path
+        )
+        .is_err());
 
-        /// Returns the file path of the test database as a string slice.
-        fn get_path(&self) -> &str {
-            &self.path.to_str().unwrap()
-        }
-    }
+        let sess = Session::new_with_recovery_key(
+            &username,
+            recovery_key.to_string(),
+            new_passwd.clone(),
+            db_path,
+        )
+        .unwrap();
+        assert_eq!(
+            sess.retrieve_secret(&label).unwrap(),
+            Some(Secret {
+                label: label.clone(),
+                kind: "password".to_string(),
+                data: secret.clone().into()
+            })
+        );
+        drop(sess);
 
-    impl Drop for TestDb {
-        /// Deletes the test database file when the struct goes out of scope.
-        /// This attempts to delete the file up to five times if the operation initially fails.
-        fn drop(&mut self) {
-            let mut attempts = 0;
-            while attempts < 5 {
-                if fs::remove_file(&self.path).is_ok() {
-                    return;
-                }
+        // the old master password no longer works
+        assert!(Session::new(&username, passwd.clone(), db_path).is_err());
+        let sess = Session::new(&username, new_passwd.clone(), db_path).unwrap();
 
-                attempts += 1;
-                thread::sleep(Duration::from_millis(100));
-            }
-            fs::remove_file(&self.path).expect("should delete the tst database file");
-        }
+        // a regenerated recovery key replaces the old one, and can be removed
+        let recovery_key = sess.create_recovery_key().unwrap();
+        sess.remove_recovery_key().unwrap();
+        drop(sess);
+        assert!(Session::new_with_recovery_key(
+            &username,
+            recovery_key.to_string(),
+            passwd.clone(),
+            db_path
+        )
+        .is_err());
     }
 
-    /// Test to verify that a secret can be stored and retrieved successfully.
+    /// Test to verify that accounts created before vault keys existed are migrated on login.
     #[test]
-    fn can_store_and_retrieve_secret() {
+    fn should_migrate_legacy_account_to_vault_key() {
         let test_db = TestDb::new();
         let db_path = test_db.get_path();
 
         let username = String::from("test_user");
-        let passwd = String::from("test_pass");
+        let passwd = String::from("plum-orbit-cactus-19");
         let label = String::from("mypass");
         let secret = String::from("mysecret");
 
+        // an account whose secrets are encrypted directly with the password-derived key
         create_new_account(&username, passwd.clone(), db_path).unwrap();
-        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        let mut db_conn = DbConn::new(db_path).unwrap();
+        let salt = db_conn.get_user_enc_salt(&username).unwrap().unwrap();
+        let params = db_conn.get_user_kdf_params(&username).unwrap().unwrap();
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        conn.execute(
+            "UPDATE users SET wrapped_key = NULL, passwd_hash = ?1",
+            [hash_password(&passwd, &params).unwrap()],
+        )
+        .unwrap();
+        let legacy_key = derive_encryption_key(&passwd, None, &salt, &params).unwrap();
+        insert_legacy_secret(
+            db_path,
+            "password",
+            &label,
+            encrypt_unbound_using_key(&*legacy_key, secret.as_bytes()).unwrap(),
+        );
 
-        sess.store_secret("password", &label, secret.to_string())
-            .unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_ne!(sess.key, legacy_key);
+        assert!(db_conn.get_user_wrapped_key(&username).unwrap().is_some());
+        drop(sess);
 
-        let retrieved_secret = sess.retrieve_secret(&label).unwrap();
+        let secrets = db_conn.get_all_secrets(1).unwrap();
+        assert_eq!(secrets.len(), 1);
+        assert!(db_conn.get_legacy_secrets(1).unwrap().is_empty());
+        assert!(is_bound_ciphertext(&secrets[0].data));
+        assert!(decrypt_secret(&legacy_key, 1, secrets[0].clone()).is_err());
 
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
         assert_eq!(
+            sess.retrieve_secret(&label).unwrap(),
             Some(Secret {
                 label,
                 kind: "password".to_string(),
-                data: secret
-            }),
-            retrieved_secret
+                data: secret.into()
+            })
         );
+        db_conn.close().unwrap();
     }
 
-    /// Test to verify that labels for stored secrets can be retrieved.
+    /// Test to verify that accounts storing a PHC password hash, whose vault key is wrapped by
+    /// the Argon2 output itself, are migrated on login to a verifier split from the same Argon2
+    /// run as the key-encryption key.
     #[test]
-    fn can_retrieve_labels() {
+    fn should_migrate_legacy_password_hash_on_login() {
         let test_db = TestDb::new();
         let db_path = test_db.get_path();
 
         let username = String::from("test_user");
-        let passwd = String::from("test_pass");
-        let label1 = String::from("mypass");
-        let label2 = String::from("mypass2");
-        let label3 = String::from("mypass3");
-        let secret = String::from("mysecret");
-
+        let passwd = String::from("plum-orbit-cactus-19");
         create_new_account(&username, passwd.clone(), db_path).unwrap();
         let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", "mypass", "mysecret".to_string())
+            .unwrap();
+        let vault_key = sess.key.clone();
+        drop(sess);
 
-        sess.store_secret("text", &label1, secret.to_string())
-            .expect("should insert secret into db");
-        sess.store_secret("password", &label2, secret.to_string())
-            .expect("should insert secret into db");
-        sess.store_secret("text", &label3, secret.to_string())
-            .expect("should insert secret into db");
+        let db_conn = DbConn::new(db_path).unwrap();
+        let salt = db_conn.get_user_enc_salt(&username).unwrap().unwrap();
+        let params = db_conn.get_user_kdf_params(&username).unwrap().unwrap();
+        let legacy_kek = derive_encryption_key(&passwd, None, &salt, &params).unwrap();
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        conn.execute(
+            "UPDATE users SET passwd_hash = ?1, wrapped_key = ?2",
+            rusqlite::params![
+                hash_password(&passwd, &params).unwrap(),
+                wrap_key(&legacy_kek, &vault_key).unwrap()
+            ],
+        )
+        .unwrap();
 
-        let labels = vec![label1.to_string(), label2.to_string(), label3.to_string()];
-        let query = sess.retrieve_labels().expect("should retrieve labels");
+        assert!(Session::new(&username, "wrong_pass".into(), db_path).is_err());
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.key, vault_key);
+        drop(sess);
 
-        for result in query {
-            assert!(labels.contains(&result.label));
-        }
+        let passwd_hash = db_conn.get_user_passwd_hash(&username).unwrap().unwrap();
+        assert!(!is_legacy_hash(&passwd_hash));
+        assert_ne!(db_conn.get_user_enc_salt(&username).unwrap().unwrap(), salt);
+
+        assert!(Session::new(&username, "wrong_pass".into(), db_path).is_err());
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(
+            *sess.retrieve_secret("mypass").unwrap().unwrap().data,
+            "mysecret"
+        );
+        assert!(sess
+            .change_master_password("wrong_pass".into(), "fjord-lantern-mosaic-7".into())
+            .is_err());
+        sess.change_master_password(passwd, "fjord-lantern-mosaic-7".into())
+            .unwrap();
     }
 
-    /// Test to verify that retrieving a non-existent secret returns `None`.
+    /// Test to verify that an account whose Argon2 parameters are below the current policy is
+    /// rehashed on its next login, exactly once, and still unlocks afterwards.
     #[test]
-    fn should_return_empty_on_nonexistent_labels() {
+    fn should_rehash_weak_kdf_params_once_on_login() {
         let test_db = TestDb::new();
         let db_path = test_db.get_path();
+
         let username = String::from("test_user");
-        let passwd = String::from("test_pass");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", "mypass", "mysecret".to_string())
+            .unwrap();
+        let weak_params = KdfParams {
+            m_cost: MIN_KDF_PARAMS.m_cost / 2,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        sess.set_master_passwd(&passwd, None, &weak_params).unwrap();
+        drop(sess);
+
+        let db_conn = DbConn::new(db_path).unwrap();
+        let weak_salt = db_conn.get_user_enc_salt(&username).unwrap().unwrap();
+        assert_eq!(
+            db_conn.get_user_kdf_params(&username).unwrap(),
+            Some(weak_params)
+        );
+
+        assert!(Session::new(&username, "wrong_pass".into(), db_path).is_err());
+        assert_eq!(
+            db_conn.get_user_enc_salt(&username).unwrap().unwrap(),
+            weak_salt
+        );
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.kdf_params().unwrap(), MIN_KDF_PARAMS.max(weak_params));
+        drop(sess);
+        let upgraded_salt = db_conn.get_user_enc_salt(&username).unwrap().unwrap();
+        let upgraded_hash = db_conn.get_user_passwd_hash(&username).unwrap();
+        assert_ne!(upgraded_salt, weak_salt);
+
+        let sess = Session::new(&username, passwd, db_path).unwrap();
+        assert_eq!(
+            *sess.retrieve_secret("mypass").unwrap().unwrap().data,
+            "mysecret"
+        );
+        drop(sess);
+        assert_eq!(
+            db_conn.get_user_enc_salt(&username).unwrap().unwrap(),
+            upgraded_salt
+        );
+        assert_eq!(
+            db_conn.get_user_passwd_hash(&username).unwrap(),
+            upgraded_hash
+        );
+    }
+
+    /// Test to verify that secrets whose data, metadata or blind index were moved to another
+    /// row outside the app are refused.
+    #[test]
+    fn should_refuse_secret_moved_to_another_row() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
 
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
         create_new_account(&username, passwd.clone(), db_path).unwrap();
         let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        for (kind, label) in [
+            ("password", "first"),
+            ("password", "second"),
+            ("text", "third"),
+            ("password", "fourth"),
+            ("text", "control"),
+        ] {
+            sess.store_secret(kind, label, format!("{label} secret"))
+                .unwrap();
+        }
 
-        let data = sess.retrieve_secret("test").unwrap();
-        assert_eq!(data, None);
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let index = |id: i64| -> Vec<u8> {
+            conn.query_row(
+                "SELECT label_index FROM secrets WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let (index3, index4) = (index(3), index(4));
+        conn.execute_batch(
+            "UPDATE secrets SET data = (SELECT data FROM secrets WHERE id = 2) WHERE id = 1;
+            UPDATE secrets SET meta = (SELECT meta FROM secrets WHERE id = 1) WHERE id = 2;
+            UPDATE secrets SET label_index = x'00' WHERE id = 3;",
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE secrets SET label_index = ?1 WHERE id = 4",
+            [&index3],
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE secrets SET label_index = ?1 WHERE id = 3",
+            [&index4],
+        )
+        .unwrap();
+
+        assert!(sess.retrieve_secret("first").is_err());
+        assert!(sess.retrieve_secret("second").is_err());
+        assert!(sess.retrieve_secret("third").is_err());
+        assert!(sess.retrieve_secret("fourth").is_err());
+        assert_eq!(
+            *sess.retrieve_secret("control").unwrap().unwrap().data,
+            "control secret"
+        );
     }
 
-    /// Test to verify that a secret can be deleted successfully.
+    /// Test to verify that unbound data is refused for a secret whose metadata is bound, both
+    /// when retrieving it and on login, rather than being re-encrypted bound to the row.
     #[test]
-    fn can_delete_data() {
+    fn should_refuse_unbound_data_of_bound_secrets() {
         let test_db = TestDb::new();
         let db_path = test_db.get_path();
 
         let username = String::from("test_user");
-        let passwd = String::from("test_pass");
+        let passwd = String::from("plum-orbit-cactus-19");
         let label = String::from("mypass");
         let secret = String::from("mysecret");
-
         create_new_account(&username, passwd.clone(), db_path).unwrap();
+
         let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        let meta = SecretMeta {
+            label: label.clone(),
+            kind: "password".to_string(),
+            encoding: Encoding::Utf8,
+        };
+        sess.db_conn
+            .store_secret(sess.user_id, &sess.label_index(&label).unwrap(), 0, |id| {
+                let encrypted = encrypt_secret(
+                    &sess.key,
+                    sess.policy,
+                    sess.user_id,
+                    id,
+                    &meta,
+                    secret.as_bytes(),
+                )?;
+                Ok((
+                    encrypted.meta,
+                    encrypt_unbound_using_key(&*sess.key, secret.as_bytes())?,
+                ))
+            })
+            .unwrap();
+        assert!(sess.retrieve_secret(&label).is_err());
+        drop(sess);
 
-        sess.store_secret("password", &label, secret.to_string())
+        assert!(Session::new(&username, passwd.clone(), db_path).is_err());
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let data: Vec<u8> = conn
+            .query_row("SELECT data FROM secrets", [], |row| row.get(0))
             .unwrap();
+        assert!(!is_bound_ciphertext(&data));
+    }
 
-        // check if data is inserted successfully
-        let retrieved_secret = sess
-            .retrieve_secret(&label)
-            .expect("should retrieve secret from db");
+    /// Test to verify that labels and kinds are only stored encrypted, and that secrets are
+    /// looked up by their normalized label.
+    #[test]
+    fn should_store_labels_encrypted() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.store_secret("password", "My Email", "secret".to_string())
+            .unwrap();
+
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        let row: Vec<u8> = conn
+            .query_row("SELECT label_index, meta, data FROM secrets", [], |row| {
+                Ok([row.get::<_, Vec<u8>>(0)?, row.get(1)?, row.get(2)?].concat())
+            })
+            .unwrap();
+        for plaintext in [&b"My Email"[..], b"my email", b"password"] {
+            assert!(!row.windows(plaintext.len()).any(|w| w == plaintext));
+        }
 
         assert_eq!(
-            retrieved_secret,
+            sess.retrieve_secret("  my EMAIL ").unwrap(),
             Some(Secret {
-                label: label.clone(),
+                label: "My Email".to_string(),
                 kind: "password".to_string(),
-                data: secret
+                data: "secret".to_string().into()
             })
         );
+        assert!(sess
+            .store_secret("text", "MY EMAIL", "other".to_string())
+            .is_err());
 
-        // delete data
-        sess.delete_secret(&label).expect("should delete data");
+        let labels = sess.retrieve_labels().unwrap();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].label, "My Email");
+        assert_eq!(labels[0].kind, "password");
+    }
 
-        // check if data is deleted
-        let retrieved_secret = sess
-            .retrieve_secret(&label)
-            .expect("should retrieve secret from db");
-        assert_eq!(retrieved_secret, None);
+    /// Test to verify that labels are validated like the former database constraints did.
+    #[test]
+    fn should_validate_label_length() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        assert!(sess.store_secret("text", "ab", "secret".into()).is_err());
+        assert!(sess
+            .store_secret("text", &"a".repeat(33), "secret".into())
+            .is_err());
+        assert!(sess
+            .store_secret("text", "  ab  ", "secret".into())
+            .is_err());
+        assert!(sess.store_secret("text", "abc", "secret".into()).is_ok());
+        assert!(sess
+            .store_secret("text", &"é".repeat(32), "secret".into())
+            .is_ok());
+        assert!(sess.edit_secret("abc", "ab", "secret".into()).is_err());
+    }
+
+    /// Test to verify that secrets stored with plaintext labels are encrypted on login, and
+    /// that labels which only differ after normalization are kept apart.
+    #[test]
+    fn should_encrypt_legacy_labels_on_login() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        let key = sess.key.clone();
+        drop(sess);
+
+        insert_legacy_secret(
+            db_path,
+            "password",
+            "Gmail",
+            encrypt_unbound_using_key(&*key, b"secret1").unwrap(),
+        );
+        insert_legacy_secret(
+            db_path,
+            "text",
+            "gmail",
+            encrypt_unbound_using_key(&*key, b"secret2").unwrap(),
+        );
+
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert!(sess.db_conn.get_legacy_secrets(1).unwrap().is_empty());
+
+        let mut labels: Vec<(String, String)> = sess
+            .retrieve_labels()
+            .unwrap()
+            .into_iter()
+            .map(|l| (l.label, l.kind))
+            .collect();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                ("Gmail".to_string(), "password".to_string()),
+                ("gmail (2)".to_string(), "text".to_string()),
+            ]
+        );
+        assert_eq!(
+            *sess.retrieve_secret("gmail (2)").unwrap().unwrap().data,
+            "secret2"
+        );
+        assert_eq!(
+            *sess.retrieve_secret("Gmail").unwrap().unwrap().data,
+            "secret1"
+        );
+    }
+
+    /// Test to verify that binary secrets round trip through the byte API, and that the text
+    /// API refuses them while still working for text secrets.
+    #[test]
+    fn can_store_and_retrieve_binary_secret() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        let key_file: Vec<u8> = (0..=255).collect();
+        sess.store_secret_bytes("text", "key file", key_file.clone())
+            .unwrap();
+        sess.store_secret("password", "text secret", "secret".to_string())
+            .unwrap();
+
+        assert_eq!(
+            sess.retrieve_secret_bytes("key file").unwrap(),
+            Some(SecretBytes {
+                label: "key file".to_string(),
+                data: key_file.clone().into(),
+                kind: "text".to_string(),
+                encoding: Encoding::Binary,
+            })
+        );
+        assert!(sess.retrieve_secret("key file").is_err());
+        assert_eq!(
+            sess.retrieve_secret_bytes("text secret")
+                .unwrap()
+                .unwrap()
+                .encoding,
+            Encoding::Utf8
+        );
+
+        let labels = sess.retrieve_labels().unwrap();
+        let encodings: HashSet<_> = labels
+            .iter()
+            .map(|l| (l.label.as_str(), l.encoding))
+            .collect();
+        assert!(encodings.contains(&("key file", Encoding::Binary)));
+        assert!(encodings.contains(&("text secret", Encoding::Utf8)));
+
+        // editing with the text API turns the secret back into text
+        sess.edit_secret("key file", "key file", "now text".to_string())
+            .unwrap();
+        assert_eq!(
+            *sess.retrieve_secret("key file").unwrap().unwrap().data,
+            "now text"
+        );
+        sess.edit_secret_bytes("text secret", "text secret", vec![0xff, 0xfe])
+            .unwrap();
+        assert_eq!(
+            *sess
+                .retrieve_secret_bytes("text secret")
+                .unwrap()
+                .unwrap()
+                .data,
+            vec![0xff, 0xfe]
+        );
+    }
+
+    /// Test to verify that changing the cipher of a vault re-encrypts every secret with it,
+    /// those in the trash and earlier versions included, and that the choice persists across
+    /// logins.
+    #[test]
+    fn can_change_vault_cipher() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let mut sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        sess.set_cipher(CipherId::Aes256Gcm).unwrap();
+        sess.store_secret("password", "first", "secret1".to_string())
+            .unwrap();
+        sess.store_secret_bytes("text", "second", vec![0, 1, 2])
+            .unwrap();
+        sess.edit_secret("first", "first", "edited1".to_string())
+            .unwrap();
+        sess.delete_secret("second").unwrap();
+
+        sess.set_cipher(CipherId::XChaCha20Poly1305).unwrap();
+        sess.store_secret("password", "third", "secret3".to_string())
+            .unwrap();
+
+        let ciphers = |sess: &Session| -> Vec<u8> {
+            let secrets = [
+                sess.db_conn.get_all_secrets(sess.user_id).unwrap(),
+                sess.db_conn.get_trashed_secrets(sess.user_id).unwrap(),
+            ];
+            let versions = sess.db_conn.get_all_secret_versions(sess.user_id).unwrap();
+            secrets
+                .concat()
+                .into_iter()
+                .map(|secret| (secret.meta, secret.data))
+                .chain(versions.into_iter().map(|v| (v.meta, v.data)))
+                .flat_map(|(meta, data)| [meta[3], data[3]])
+                .collect()
+        };
+        assert_eq!(ciphers(&sess), vec![2; 8]);
+        drop(sess);
+
+        let mut sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.cipher(), CipherId::XChaCha20Poly1305);
+        assert_eq!(
+            *sess.retrieve_secret("first").unwrap().unwrap().data,
+            "edited1"
+        );
+        let version = sess.secret_versions("first").unwrap()[0].id;
+        assert_eq!(
+            *sess
+                .retrieve_secret_version("first", version)
+                .unwrap()
+                .unwrap()
+                .data,
+            b"secret1"
+        );
+        sess.restore_secret(sess.trash().unwrap()[0].id).unwrap();
+        assert_eq!(
+            *sess.retrieve_secret_bytes("second").unwrap().unwrap().data,
+            vec![0, 1, 2]
+        );
+
+        sess.set_cipher(CipherId::Aes256Gcm).unwrap();
+        assert_eq!(ciphers(&sess), vec![1; 8]);
+        assert_eq!(
+            *sess.retrieve_secret("third").unwrap().unwrap().data,
+            "secret3"
+        );
+    }
+
+    /// Test to verify that padded vaults store secrets of different short lengths as
+    /// ciphertexts of the same length, and that turning padding off re-encrypts them unpadded.
+    #[test]
+    fn should_hide_secret_lengths_when_padded() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let mut sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.padding(), PaddingId::Padme);
+        sess.store_secret("password", "short", "1234".to_string())
+            .unwrap();
+        sess.store_secret("password", "long", "correct horse battery".to_string())
+            .unwrap();
+
+        let data_lens = |sess: &Session| -> Vec<usize> {
+            sess.db_conn
+                .get_all_secrets(sess.user_id)
+                .unwrap()
+                .into_iter()
+                .map(|secret| secret.data.len())
+                .collect()
+        };
+        let lens = data_lens(&sess);
+        assert_eq!(lens[0], lens[1]);
+
+        sess.set_padding(PaddingId::None).unwrap();
+        drop(sess);
+
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+        assert_eq!(sess.padding(), PaddingId::None);
+        let lens = data_lens(&sess);
+        assert_ne!(lens[0], lens[1]);
+        assert_eq!(
+            *sess.retrieve_secret("short").unwrap().unwrap().data,
+            "1234"
+        );
+        assert_eq!(
+            *sess.retrieve_secret("long").unwrap().unwrap().data,
+            "correct horse battery"
+        );
+    }
+
+    /// Test to verify that the `Debug` output of retrieved secrets never contains their data.
+    #[test]
+    fn should_redact_secret_data_in_debug_output() {
+        let test_db = TestDb::new();
+        let db_path = test_db.get_path();
+
+        let username = String::from("test_user");
+        let passwd = String::from("plum-orbit-cactus-19");
+        create_new_account(&username, passwd.clone(), db_path).unwrap();
+        let sess = Session::new(&username, passwd.clone(), db_path).unwrap();
+
+        sess.store_secret("password", "text secret", "hunter22".to_string())
+            .unwrap();
+        sess.store_secret_bytes("text", "key file", b"binary key".to_vec())
+            .unwrap();
+
+        let secret = format!("{:?}", sess.retrieve_secret("text secret").unwrap());
+        assert!(secret.contains("text secret"));
+        assert!(secret.contains(REDACTED));
+        assert!(!secret.contains("hunter22"));
+
+        let secret = sess.retrieve_secret_bytes("key file").unwrap().unwrap();
+        let debug = format!("{secret:?}");
+        assert!(debug.contains(REDACTED));
+        assert!(!debug.contains(&format!("{:?}", *secret.data)));
+    }
+
+    /// Inserts a secret the way it was stored before labels and kinds were encrypted.
+    fn insert_legacy_secret(db_path: &str, kind: &str, label: &str, data: Vec<u8>) {
+        let conn = rusqlite::Connection::open(db_path).unwrap();
+        conn.execute(
+            "INSERT INTO secrets (user_id, data, legacy_kind, legacy_label)
+            VALUES (1, ?1, ?2, ?3)",
+            rusqlite::params![data, kind, label],
+        )
+        .unwrap();
     }
 
     /// Test to verify that secrets can be stored and retrieved within an `Arc<Mutex<Session>>`,
@@ -390,7 +4462,7 @@ mod test {
         let db_path = test_db.get_path();
 
         let username = String::from("user");
-        let passwd = String::from("test_pass");
+        let passwd = String::from("plum-orbit-cactus-19");
         let label = String::from("mypass");
         let secret = String::from("mysecret");
 
@@ -415,7 +4487,7 @@ mod test {
             Some(Secret {
                 label: label,
                 kind: "password".to_string(),
-                data: secret
+                data: secret.into()
             })
         );
     }
@@ -8,9 +8,16 @@ use std::{
     time::{Duration, Instant},
 };
 
-use lockkey::{create_new_account, Session};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use lockkey::{
+    create_key_file, create_new_account, create_new_account_with_key_file,
+    create_new_account_with_recovery_key, estimate_strength, CipherId, Encoding, FolderDeletion,
+    KdfParams, LabelFilter, LabelOrder, LockedSession, LoginError, PaddingId, SecretTimestamps,
+    Session, WeakPasswordError, KDF_TARGET_DURATION,
+};
 use serde_json::{json, Value};
 use tauri::Manager;
+use zeroize::Zeroizing;
 
 /// Represents a generic response structure for Tauri commands.
 #[derive(serde::Serialize)]
@@ -46,11 +53,25 @@ impl Response {
     }
 }
 
-/// Represents a secret label with its kind.
+/// Represents a secret label with its kind, payload encoding, folder, tags and timestamps.
 #[derive(serde::Serialize)]
 struct Label {
     label: String,
     kind: String,
+    encoding: Encoding,
+    folder_id: Option<i64>,
+    tags: Vec<String>,
+    #[serde(flatten)]
+    timestamps: SecretTimestamps,
+}
+
+/// Represents a secret with a binary payload, encoded as base64 for IPC.
+#[derive(serde::Serialize)]
+struct SecretBytes {
+    label: String,
+    kind: String,
+    encoding: Encoding,
+    data: Zeroizing<String>,
 }
 
 /// Command to store a new secret.
@@ -109,7 +130,77 @@ fn edit_secret(
     }
 }
 
-/// Command to delete a secret.
+/// Command to store a new secret with a binary payload.
+///
+/// # Arguments
+/// * `kind` - The type of secret (e.g., key file, certificate).
+/// * `label` - The label for the secret.
+/// * `data` - The secret data to store, encoded as base64.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn new_secret_bytes(
+    kind: String,
+    label: String,
+    data: String,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => {
+            let data = Zeroizing::new(data);
+            let result = BASE64
+                .decode(data.as_bytes())
+                .map_err(anyhow::Error::from)
+                .and_then(|data| session.store_secret_bytes(&kind, &label, data));
+            match result {
+                Ok(()) => Response::ok().body(json!("Secret created".to_string())),
+                Err(e) => Response::err().body(json!(format!("Error creating secret: {e:?}"))),
+            }
+        }
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to edit an existing secret, replacing its payload with binary data.
+///
+/// # Arguments
+/// * `label` - The label of the secret to edit.
+/// * `new_label` - The new label for the secret.
+/// * `new_data` - The new data for the secret, encoded as base64.
+/// * `session` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn edit_secret_bytes(
+    label: String,
+    new_label: String,
+    new_data: String,
+    session: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = session.lock().unwrap();
+
+    match *sess_guard {
+        Some(ref session) => {
+            let new_data = Zeroizing::new(new_data);
+            let result = BASE64
+                .decode(new_data.as_bytes())
+                .map_err(anyhow::Error::from)
+                .and_then(|data| session.edit_secret_bytes(&label, &new_label, data));
+            match result {
+                Ok(()) => Response::ok().body(json!("secret edited".to_string())),
+                Err(e) => Response::err().body(json!(format!("Error editing secret: {e:?}"))),
+            }
+        }
+        None => Response::err().body(json!(format!("No running session"))),
+    }
+}
+
+/// Command to move a secret to the trash.
 ///
 /// # Arguments
 /// * `label` - The label of the secret to delete.
@@ -123,39 +214,115 @@ fn delete_secret(label: String, state: tauri::State<Arc<Mutex<Option<Session>>>>
 
     match *sess_guard {
         Some(ref session) => match session.delete_secret(&label) {
-            Ok(()) => Response::ok().body(json!(format!("{:?} deleted", label))),
+            Ok(()) => Response::ok().body(json!(format!("{:?} moved to the trash", label))),
             Err(e) => Response::err().body(json!(format!("Error deleting secret: {e:?}"))),
         },
         None => Response::err().body(json!(format!("No running session"))),
     }
 }
 
-/// Command to retrieve all secret labels.
+/// Command to list the secrets in the trash, most recently deleted first.
 ///
 /// # Arguments
 /// * `state` - A shared state containing the current session.
 ///
 /// # Returns
-/// A `Response` with the list of secret labels, or an error.
+/// A `Response` with the ID, label, kind and deletion time of each secret, or an error.
 #[tauri::command]
-fn get_labels(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+fn get_trash(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
     let sess_guard = state.lock().expect("should get session");
 
     match *sess_guard {
-        Some(ref session) => match session.retrieve_labels() {
-            Ok(labels) => {
-                let labels: Vec<Label> = labels
-                    .into_iter()
-                    .map(|x| Label {
-                        label: x.label,
-                        kind: x.kind,
-                    })
-                    .collect();
-                Response::ok().body(json!(labels))
-            }
-            Err(e) => Response::err().body(json!(format!("Error getting labels: {e:?}"))),
+        Some(ref session) => match session.trash() {
+            Ok(trash) => Response::ok().body(json!(trash)),
+            Err(e) => Response::err().body(json!(format!("Error getting trash: {e:?}"))),
         },
-        None => Response::err().body(json!(format!("No running session"))),
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to take a secret out of the trash.
+///
+/// # Arguments
+/// * `trash_id` - The ID of the secret in the trash.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn restore_secret(trash_id: i64, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.restore_secret(trash_id) {
+            Ok(()) => Response::ok().body(json!("Secret restored")),
+            Err(e) => Response::err().body(json!(format!("Error restoring secret: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to delete a secret in the trash for good.
+///
+/// # Arguments
+/// * `trash_id` - The ID of the secret in the trash.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn delete_secret_permanently(
+    trash_id: i64,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.delete_secret_permanently(trash_id) {
+            Ok(()) => Response::ok().body(json!("Secret permanently deleted")),
+            Err(e) => Response::err().body(json!(format!("Error deleting secret: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the secret labels with their folders, tags and timestamps.
+///
+/// # Arguments
+/// * `sort` - The order to list the secrets in, or `None` for the order they were stored in.
+/// * `filter` - The folder or tag to list the secrets of, or `None` to list them all.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the list of secret labels, or an error.
+#[tauri::command]
+fn get_labels(
+    sort: Option<LabelOrder>,
+    filter: Option<LabelFilter>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    let labels = match *sess_guard {
+        Some(ref session) => session.list_labels(filter.as_ref(), sort),
+        None => return Response::err().body(json!(format!("No running session"))),
+    };
+    match labels {
+        Ok(labels) => {
+            let labels: Vec<Label> = labels
+                .into_iter()
+                .map(|x| Label {
+                    label: x.label,
+                    kind: x.kind,
+                    encoding: x.encoding,
+                    folder_id: x.folder_id,
+                    tags: x.tags,
+                    timestamps: x.timestamps,
+                })
+                .collect();
+            Response::ok().body(json!(labels))
+        }
+        Err(e) => Response::err().body(json!(format!("Error getting labels: {e:?}"))),
     }
 }
 
@@ -183,115 +350,1243 @@ fn get_secret(label: String, state: tauri::State<Arc<Mutex<Option<Session>>>>) -
     }
 }
 
-/// Command to check if a user is authenticated.
+/// Command to retrieve a secret by its label as raw bytes, whatever its payload encoding.
 ///
 /// # Arguments
+/// * `label` - The label of the secret to retrieve.
 /// * `state` - A shared state containing the current session.
 ///
 /// # Returns
-/// A `Response` indicating whether the user is authenticated or not.
+/// A `Response` with the secret data encoded as base64, or an error if the secret does not exist.
 #[tauri::command]
-fn is_authenticated(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
-    let sess_guard = state.lock().expect("should get managed session state");
+fn get_secret_bytes(label: String, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
     match *sess_guard {
-        Some(_) => Response::ok().body(json!(true)),
-        None => Response::ok().body(json!(false)),
+        Some(ref session) => match session.retrieve_secret_bytes(&label) {
+            Ok(result) => match result {
+                Some(s) => Response::ok().body(json!(SecretBytes {
+                    data: Zeroizing::new(BASE64.encode(&*s.data)),
+                    label: s.label,
+                    kind: s.kind,
+                    encoding: s.encoding,
+                })),
+                None => Response::err().body(json!(format!("{:?} does not exist", label))),
+            },
+            Err(e) => Response::err().body(json!(format!("Error getting secret: {e:?}"))),
+        },
+        None => Response::err().body(json!(format!("No running session"))),
     }
 }
 
-/// Command to create a new user.
+/// Command to list the folders of the current user.
 ///
 /// # Arguments
-/// * `usrname` - The username of the new user.
-/// * `passwd` - The password for the new user.
-/// * `db_path` - A shared state containing the database path.
+/// * `state` - A shared state containing the current session.
 ///
 /// # Returns
-/// A `Response` indicating success or failure of user creation.
+/// A `Response` with the ID, parent ID and name of each folder, or an error.
 #[tauri::command]
-fn new_user(
-    usrname: String,
-    passwd: String,
-    db_path: tauri::State<Arc<Mutex<String>>>,
+fn get_folders(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.folders() {
+            Ok(folders) => Response::ok().body(json!(folders)),
+            Err(e) => Response::err().body(json!(format!("Error getting folders: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to create a folder.
+///
+/// # Arguments
+/// * `name` - The name of the folder.
+/// * `parent_id` - The folder to create it in, or `None` for the root.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the ID of the new folder, or an error.
+#[tauri::command]
+fn create_folder(
+    name: String,
+    parent_id: Option<i64>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
 ) -> Response {
-    let db_path = db_path.lock().expect("should get db path");
-    match create_new_account(&usrname, passwd, &db_path) {
-        Ok(()) => Response::ok().body(json!(format!("user `{:?}` created", usrname))),
-        Err(e) => Response::err().body(json!(format!("Error creating a new account: {e:?}"))),
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.create_folder(&name, parent_id) {
+            Ok(folder_id) => Response::ok().body(json!(folder_id)),
+            Err(e) => Response::err().body(json!(format!("Error creating folder: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
     }
 }
 
-/// Command to log in a user.
+/// Command to rename a folder.
 ///
 /// # Arguments
-/// * `usrname` - The username for login.
-/// * `passwd` - The password for login.
-/// * `app_handle` - A handle to the Tauri application.
-/// * `session` - A shared state containing the current session.
-/// * `db_path` - A shared state containing the database path.
+/// * `folder_id` - The ID of the folder.
+/// * `name` - The new name of the folder.
+/// * `state` - A shared state containing the current session.
 ///
 /// # Returns
-/// A `Response` indicating success or failure of login.
+/// A `Response` indicating success or failure.
 #[tauri::command]
-fn login(
-    usrname: String,
-    passwd: String,
-    app_handle: tauri::AppHandle,
-    session: tauri::State<Arc<Mutex<Option<Session>>>>,
-    db_path: tauri::State<Arc<Mutex<String>>>,
+fn rename_folder(
+    folder_id: i64,
+    name: String,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
 ) -> Response {
-    let db_path = db_path.lock().expect("should get db path");
+    let sess_guard = state.lock().expect("should get session");
 
-    // log out existing session if there is one
-    if let Some(sess) = session.lock().unwrap().as_mut() {
-        _ = sess.logout();
+    match *sess_guard {
+        Some(ref session) => match session.rename_folder(folder_id, &name) {
+            Ok(()) => Response::ok().body(json!("Folder renamed")),
+            Err(e) => Response::err().body(json!(format!("Error renaming folder: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
     }
+}
 
-    // create a new session and update app state
-    {
-        let new_session = match Session::new(&usrname, passwd, &db_path) {
-            Ok(sess) => sess,
-            Err(e) => return Response::err().body(json!(format!("Error loggin in: {e:?}"))),
-        };
+/// Command to move a folder into another folder.
+///
+/// # Arguments
+/// * `folder_id` - The ID of the folder.
+/// * `parent_id` - The folder to move it into, or `None` for the root.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn move_folder(
+    folder_id: i64,
+    parent_id: Option<i64>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
 
-        let mut sess_guard = session.lock().unwrap();
-        *sess_guard = Some(new_session);
+    match *sess_guard {
+        Some(ref session) => match session.move_folder(folder_id, parent_id) {
+            Ok(()) => Response::ok().body(json!("Folder moved")),
+            Err(e) => Response::err().body(json!(format!("Error moving folder: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
     }
+}
 
-    // spawn a thread to monitor session timeout every 30 secs
-    let session = Arc::clone(&session);
-    let session_timeout = Duration::from_secs(300);
-    thread::spawn(move || {
-        loop {
-            let mut sess_guard = session.lock().unwrap();
+/// Command to delete a folder, either with its subfolders, moving its secrets to the trash, or
+/// moving its contents to its parent.
+///
+/// # Arguments
+/// * `folder_id` - The ID of the folder.
+/// * `deletion` - What happens to the contents of the folder.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn delete_folder(
+    folder_id: i64,
+    deletion: FolderDeletion,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
 
-            match sess_guard.as_ref() {
-                Some(sess) => {
-                    if sess.last_activity.elapsed() >= session_timeout {
-                        // trigger session timeout action
-                        app_handle
-                            .emit_all("session_timeout", "Logged out due to inactivity")
-                            .unwrap();
-                        *sess_guard = None;
-                        break;
-                    }
-                }
-                None => {
-                    *sess_guard = None;
-                    break;
-                }
-            }
+    match *sess_guard {
+        Some(ref session) => match session.delete_folder(folder_id, deletion) {
+            Ok(()) => Response::ok().body(json!("Folder deleted")),
+            Err(e) => Response::err().body(json!(format!("Error deleting folder: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
 
-            drop(sess_guard);
-            thread::sleep(Duration::from_secs(30)); // check every 30 secs
-        }
-    });
+/// Command to move a secret into a folder.
+///
+/// # Arguments
+/// * `label` - The label of the secret.
+/// * `folder_id` - The folder to move it into, or `None` for the root.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn move_secret(
+    label: String,
+    folder_id: Option<i64>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
 
-    Response::ok().body(json!(format!("logged in as {:?}", usrname)))
+    match *sess_guard {
+        Some(ref session) => match session.move_secret(&label, folder_id) {
+            Ok(()) => Response::ok().body(json!(format!("{:?} moved", label))),
+            Err(e) => Response::err().body(json!(format!("Error moving secret: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
 }
 
+/// Command to list the tags of the current user.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the names of the tags, or an error.
 #[tauri::command]
-fn logout(session: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+fn get_tags(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.tags() {
+            Ok(tags) => Response::ok().body(json!(tags)),
+            Err(e) => Response::err().body(json!(format!("Error getting tags: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to tag a secret, creating the tag if needed.
+///
+/// # Arguments
+/// * `label` - The label of the secret.
+/// * `tag` - The name of the tag.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn tag_secret(
+    label: String,
+    tag: String,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.tag_secret(&label, &tag) {
+            Ok(()) => Response::ok().body(json!(format!("{:?} tagged", label))),
+            Err(e) => Response::err().body(json!(format!("Error tagging secret: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to remove a tag from a secret.
+///
+/// # Arguments
+/// * `label` - The label of the secret.
+/// * `tag` - The name of the tag.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn untag_secret(
+    label: String,
+    tag: String,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.untag_secret(&label, &tag) {
+            Ok(()) => Response::ok().body(json!(format!("{:?} untagged", label))),
+            Err(e) => Response::err().body(json!(format!("Error untagging secret: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to list the earlier versions of a secret, newest first.
+///
+/// # Arguments
+/// * `label` - The label of the secret.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the ID, label and replacement time of each version, or an error.
+#[tauri::command]
+fn get_secret_versions(
+    label: String,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.secret_versions(&label) {
+            Ok(versions) => Response::ok().body(json!(versions)),
+            Err(e) => Response::err().body(json!(format!("Error getting versions: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to reveal an earlier version of a secret.
+///
+/// # Arguments
+/// * `label` - The current label of the secret.
+/// * `version_id` - The ID of the version.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the version's data encoded as base64, or an error if it does not exist.
+#[tauri::command]
+fn get_secret_version(
+    label: String,
+    version_id: i64,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.retrieve_secret_version(&label, version_id) {
+            Ok(Some(s)) => Response::ok().body(json!(SecretBytes {
+                data: Zeroizing::new(BASE64.encode(&*s.data)),
+                label: s.label,
+                kind: s.kind,
+                encoding: s.encoding,
+            })),
+            Ok(None) => {
+                Response::err().body(json!(format!("{:?} has no version {version_id}", label)))
+            }
+            Err(e) => Response::err().body(json!(format!("Error getting version: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to roll a secret back to an earlier version.
+///
+/// # Arguments
+/// * `label` - The current label of the secret.
+/// * `version_id` - The ID of the version to restore.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn restore_secret_version(
+    label: String,
+    version_id: i64,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.restore_secret_version(&label, version_id) {
+            Ok(()) => Response::ok().body(json!(format!("{:?} restored", label))),
+            Err(e) => Response::err().body(json!(format!("Error restoring version: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to check if a user is authenticated.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating whether the user is authenticated or not.
+#[tauri::command]
+fn is_authenticated(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get managed session state");
+    match *sess_guard {
+        Some(_) => Response::ok().body(json!(true)),
+        None => Response::ok().body(json!(false)),
+    }
+}
+
+/// Command to create a new user.
+///
+/// # Arguments
+/// * `usrname` - The username of the new user.
+/// * `passwd` - The password for the new user.
+/// * `with_recovery_key` - Whether to also generate a recovery key.
+/// * `key_file` - The base64 encoded contents of a key file to require at login, if any.
+/// * `db_path` - A shared state containing the database path.
+///
+/// # Returns
+/// A `Response` indicating success or failure of user creation. When a recovery key was
+/// requested, the body contains it so it can be shown to the user. A password that is too
+/// weak is refused with its score and feedback.
+#[tauri::command]
+fn new_user(
+    usrname: String,
+    passwd: String,
+    with_recovery_key: Option<bool>,
+    key_file: Option<String>,
+    db_path: tauri::State<Arc<Mutex<String>>>,
+) -> Response {
+    let db_path = db_path.lock().expect("should get db path");
+
+    if let Some(key_file) = key_file {
+        let key_file = Zeroizing::new(key_file);
+        let result = BASE64
+            .decode(key_file.as_bytes())
+            .map_err(anyhow::Error::from)
+            .and_then(|key_file| {
+                create_new_account_with_key_file(
+                    &usrname,
+                    passwd,
+                    key_file,
+                    with_recovery_key.unwrap_or(false),
+                    &db_path,
+                )
+            });
+        return match result {
+            Ok(Some(recovery_key)) => Response::ok().body(json!({ "recovery_key": recovery_key })),
+            Ok(None) => Response::ok().body(json!(format!("user `{:?}` created", usrname))),
+            Err(e) => password_error("Error creating a new account", e),
+        };
+    }
+
+    if with_recovery_key.unwrap_or(false) {
+        return match create_new_account_with_recovery_key(&usrname, passwd, &db_path) {
+            Ok(recovery_key) => Response::ok().body(json!({ "recovery_key": recovery_key })),
+            Err(e) => password_error("Error creating a new account", e),
+        };
+    }
+
+    match create_new_account(&usrname, passwd, &db_path) {
+        Ok(()) => Response::ok().body(json!(format!("user `{:?}` created", usrname))),
+        Err(e) => password_error("Error creating a new account", e),
+    }
+}
+
+/// Command to log in a user.
+///
+/// # Arguments
+/// * `usrname` - The username for login.
+/// * `passwd` - The password for login.
+/// * `key_file` - The base64 encoded contents of the user's key file, if they registered one.
+/// * `totp_code` - A two-factor code or backup code, if the user enrolled.
+/// * `app_handle` - A handle to the Tauri application.
+/// * `session` - A shared state containing the current session.
+/// * `locked` - A shared state containing the locked session, if any.
+/// * `db_path` - A shared state containing the database path.
+///
+/// # Returns
+/// A `Response` indicating success or failure of login.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn login(
+    usrname: String,
+    passwd: String,
+    key_file: Option<String>,
+    totp_code: Option<String>,
+    app_handle: tauri::AppHandle,
+    session: tauri::State<Arc<Mutex<Option<Session>>>>,
+    locked: tauri::State<Arc<Mutex<Option<LockedSession>>>>,
+    db_path: tauri::State<Arc<Mutex<String>>>,
+) -> Response {
+    let db_path = db_path.lock().expect("should get db path");
+
+    // log out existing session if there is one
+    if let Some(sess) = session.lock().unwrap().as_mut() {
+        _ = sess.logout();
+    }
+    *locked.lock().unwrap() = None;
+
+    // create a new session and update app state
+    {
+        let key_file = key_file.map(Zeroizing::new);
+        let result = key_file
+            .map(|key_file| BASE64.decode(key_file.as_bytes()))
+            .transpose()
+            .map_err(anyhow::Error::from)
+            .and_then(|key_file| {
+                Session::new_with_factors(&usrname, passwd, key_file, totp_code, &db_path)
+            });
+        let new_session = match result {
+            Ok(sess) => sess,
+            // wrong credentials get a single error kind, throttled attempts tell the frontend
+            // when the next one is allowed, and a missing two-factor code that it should ask
+            // for one
+            Err(e) => {
+                return match e.downcast_ref::<LoginError>() {
+                    Some(login_error) => Response::err().body(json!({
+                        "error": login_error,
+                        "message": login_error.to_string(),
+                    })),
+                    None => Response::err().body(json!(format!("Error loggin in: {e:?}"))),
+                }
+            }
+        };
+
+        let mut sess_guard = session.lock().unwrap();
+        *sess_guard = Some(new_session);
+    }
+
+    spawn_session_timeout(app_handle, Arc::clone(&session), Arc::clone(&locked));
+
+    Response::ok().body(json!(format!("logged in as {:?}", usrname)))
+}
+
+/// Command to log in a user with their recovery key and set a new master password.
+///
+/// # Arguments
+/// * `usrname` - The username for login.
+/// * `recovery_key` - The user's printed recovery key.
+/// * `new_passwd` - The new master password.
+/// * `app_handle` - A handle to the Tauri application.
+/// * `session` - A shared state containing the current session.
+/// * `locked` - A shared state containing the locked session, if any.
+/// * `db_path` - A shared state containing the database path.
+///
+/// # Returns
+/// A `Response` indicating success or failure of the recovery.
+#[tauri::command]
+fn recover_account(
+    usrname: String,
+    recovery_key: String,
+    new_passwd: String,
+    app_handle: tauri::AppHandle,
+    session: tauri::State<Arc<Mutex<Option<Session>>>>,
+    locked: tauri::State<Arc<Mutex<Option<LockedSession>>>>,
+    db_path: tauri::State<Arc<Mutex<String>>>,
+) -> Response {
+    let db_path = db_path.lock().expect("should get db path");
+
+    // log out existing session if there is one
+    if let Some(sess) = session.lock().unwrap().as_mut() {
+        _ = sess.logout();
+    }
+    *locked.lock().unwrap() = None;
+
+    {
+        let new_session =
+            match Session::new_with_recovery_key(&usrname, recovery_key, new_passwd, &db_path) {
+                Ok(sess) => sess,
+                Err(e) => return password_error("Error recovering account", e),
+            };
+
+        let mut sess_guard = session.lock().unwrap();
+        *sess_guard = Some(new_session);
+    }
+
+    spawn_session_timeout(app_handle, Arc::clone(&session), Arc::clone(&locked));
+
+    Response::ok().body(json!(format!("logged in as {:?}", usrname)))
+}
+
+/// Spawns a thread that monitors the session every 30 secs and logs the user out after
+/// 5 minutes of inactivity. If the user set a quick-unlock PIN, the session is locked
+/// instead, so that the PIN can reopen it.
+///
+/// # Arguments
+/// * `app_handle` - A handle to the Tauri application.
+/// * `session` - A shared state containing the current session.
+/// * `locked` - A shared state containing the locked session, if any.
+fn spawn_session_timeout(
+    app_handle: tauri::AppHandle,
+    session: Arc<Mutex<Option<Session>>>,
+    locked: Arc<Mutex<Option<LockedSession>>>,
+) {
+    let session_timeout = Duration::from_secs(300);
+    thread::spawn(move || {
+        loop {
+            let mut sess_guard = session.lock().unwrap();
+
+            match sess_guard.as_mut() {
+                Some(sess) => {
+                    if sess.last_activity.elapsed() >= session_timeout {
+                        // trigger session timeout action
+                        match sess.lock() {
+                            Ok(locked_session) => {
+                                *locked.lock().unwrap() = Some(locked_session);
+                                app_handle
+                                    .emit_all("session_locked", "Locked due to inactivity")
+                                    .unwrap();
+                            }
+                            Err(_) => app_handle
+                                .emit_all("session_timeout", "Logged out due to inactivity")
+                                .unwrap(),
+                        }
+                        *sess_guard = None;
+                        break;
+                    }
+                }
+                None => {
+                    *sess_guard = None;
+                    break;
+                }
+            }
+
+            drop(sess_guard);
+            thread::sleep(Duration::from_secs(30)); // check every 30 secs
+        }
+    });
+}
+
+/// Command to set the PIN that reopens the session after it is locked for inactivity, or to
+/// disable quick unlock. The PIN only works until the app restarts.
+///
+/// # Arguments
+/// * `pin` - The PIN, or `None` to disable quick unlock.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_quick_unlock_pin(
+    pin: Option<String>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let mut sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref mut session) => match session.set_quick_unlock_pin(pin) {
+            Ok(()) => Response::ok().body(json!(session.has_quick_unlock())),
+            Err(e) => Response::err().body(json!(format!("Error setting PIN: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to check whether a session is locked and can be reopened with the PIN.
+///
+/// # Arguments
+/// * `locked` - A shared state containing the locked session, if any.
+///
+/// # Returns
+/// A `Response` with whether the session is locked and the PINs that may still be tried.
+#[tauri::command]
+fn is_locked(locked: tauri::State<Arc<Mutex<Option<LockedSession>>>>) -> Response {
+    let locked_guard = locked.lock().expect("should get locked session");
+
+    match *locked_guard {
+        Some(ref locked_session) if locked_session.attempts_left() > 0 => Response::ok()
+            .body(json!({ "locked": true, "attempts_left": locked_session.attempts_left() })),
+        _ => Response::ok().body(json!({ "locked": false, "attempts_left": 0 })),
+    }
+}
+
+/// Command to reopen a locked session with the quick-unlock PIN. After too many wrong PINs
+/// the locked session is destroyed and the user must log in with the master password.
+///
+/// # Arguments
+/// * `pin` - The quick-unlock PIN.
+/// * `app_handle` - A handle to the Tauri application.
+/// * `session` - A shared state containing the current session.
+/// * `locked` - A shared state containing the locked session, if any.
+///
+/// # Returns
+/// A `Response` indicating success or failure, with the PINs that may still be tried.
+#[tauri::command]
+fn quick_unlock(
+    pin: String,
+    app_handle: tauri::AppHandle,
+    session: tauri::State<Arc<Mutex<Option<Session>>>>,
+    locked: tauri::State<Arc<Mutex<Option<LockedSession>>>>,
+) -> Response {
+    {
+        let mut locked_guard = locked.lock().expect("should get locked session");
+        let locked_session = match locked_guard.as_mut() {
+            Some(locked_session) => locked_session,
+            None => return Response::err().body(json!("No locked session")),
+        };
+
+        let new_session = match locked_session.unlock(pin) {
+            Ok(sess) => sess,
+            Err(e) => {
+                let attempts_left = locked_session.attempts_left();
+                if attempts_left == 0 {
+                    *locked_guard = None;
+                }
+                return Response::err().body(json!({
+                    "message": format!("Error unlocking: {e:?}"),
+                    "attempts_left": attempts_left,
+                }));
+            }
+        };
+        *locked_guard = None;
+
+        let mut sess_guard = session.lock().unwrap();
+        *sess_guard = Some(new_session);
+    }
+
+    spawn_session_timeout(app_handle, Arc::clone(&session), Arc::clone(&locked));
+
+    Response::ok().body(json!("session unlocked"))
+}
+
+/// Command to generate a new recovery key for the current user, replacing any previous one.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the printable recovery key, or an error.
+#[tauri::command]
+fn create_recovery_key(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.create_recovery_key() {
+            Ok(recovery_key) => Response::ok().body(json!(recovery_key)),
+            Err(e) => Response::err().body(json!(format!("Error creating recovery key: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to remove the current user's recovery key.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn remove_recovery_key(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.remove_recovery_key() {
+            Ok(()) => Response::ok().body(json!("recovery key removed")),
+            Err(e) => Response::err().body(json!(format!("Error removing recovery key: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the number of failed logins after which the current user is locked out.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the lockout threshold, `null` if the lockout is disabled, or an error.
+#[tauri::command]
+fn get_lockout_threshold(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.lockout_threshold() {
+            Ok(lockout_after) => Response::ok().body(json!(lockout_after)),
+            Err(e) => Response::err().body(json!(format!("Error getting lockout: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the number of days deleted secrets stay in the trash.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the number of days, or an error.
+#[tauri::command]
+fn get_trash_retention(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.trash_retention_days() {
+            Ok(days) => Response::ok().body(json!(days)),
+            Err(e) => Response::err().body(json!(format!("Error getting trash retention: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to set the number of days deleted secrets stay in the trash.
+///
+/// # Arguments
+/// * `days` - The number of days, 0 to empty the trash right away and at every login.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_trash_retention(days: u32, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.set_trash_retention_days(days) {
+            Ok(()) => Response::ok().body(json!(days)),
+            Err(e) => Response::err().body(json!(format!("Error setting trash retention: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the number of earlier versions kept for each secret.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the number of versions, or an error.
+#[tauri::command]
+fn get_history_limit(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.history_limit() {
+            Ok(history_limit) => Response::ok().body(json!(history_limit)),
+            Err(e) => Response::err().body(json!(format!("Error getting history limit: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to set the number of earlier versions kept for each secret.
+///
+/// # Arguments
+/// * `history_limit` - The number of versions to keep, 0 to keep none.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_history_limit(
+    history_limit: u32,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.set_history_limit(history_limit) {
+            Ok(()) => Response::ok().body(json!(history_limit)),
+            Err(e) => Response::err().body(json!(format!("Error setting history limit: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to lock the current user out after a number of failed logins, until they use their
+/// recovery key, or to disable the lockout.
+///
+/// # Arguments
+/// * `lockout_after` - The lockout threshold, or `None` to disable the lockout.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_lockout_threshold(
+    lockout_after: Option<u32>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.set_lockout_threshold(lockout_after) {
+            Ok(()) => Response::ok().body(json!(lockout_after)),
+            Err(e) => Response::err().body(json!(format!("Error setting lockout: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to start enrolling the current user in two-factor authentication.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the `otpauth://` URI, its QR code as SVG and the backup codes, or an
+/// error.
+#[tauri::command]
+fn enroll_totp(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let mut sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref mut session) => match session.enroll_totp() {
+            Ok(enrollment) => Response::ok().body(json!(enrollment)),
+            Err(e) => Response::err().body(json!(format!("Error enrolling in 2FA: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to enable two-factor authentication once a code from the authenticator app is
+/// confirmed.
+///
+/// # Arguments
+/// * `code` - A code from the authenticator app.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn confirm_totp(code: String, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let mut sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref mut session) => match session.confirm_totp(&code) {
+            Ok(()) => Response::ok().body(json!("two-factor authentication enabled")),
+            Err(e) => Response::err().body(json!(format!("Error confirming 2FA: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to disable two-factor authentication for the current user.
+///
+/// # Arguments
+/// * `passwd` - The user's password.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn disable_totp(passwd: String, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.disable_totp(passwd) {
+            Ok(()) => Response::ok().body(json!("two-factor authentication disabled")),
+            Err(e) => Response::err().body(json!(format!("Error disabling 2FA: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to check whether the current user enrolled in two-factor authentication.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with whether two-factor authentication is enabled, or an error.
+#[tauri::command]
+fn get_totp_enabled(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.has_totp() {
+            Ok(enabled) => Response::ok().body(json!(enabled)),
+            Err(e) => Response::err().body(json!(format!("Error checking 2FA: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the Argon2 parameters protecting the current user's master password.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the key derivation parameters, or an error.
+#[tauri::command]
+fn get_kdf_params(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.kdf_params() {
+            Ok(params) => Response::ok().body(json!(params)),
+            Err(e) => Response::err().body(json!(format!("Error getting kdf params: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the ID of the vault and the version of Lockkey that created it.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the vault ID and creator version, or an error.
+#[tauri::command]
+fn get_vault_info(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.vault_info() {
+            Ok(info) => Response::ok().body(json!(info)),
+            Err(e) => Response::err().body(json!(format!("Error getting vault info: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve the cipher the current user's secrets are encrypted with.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the cipher name, or an error.
+#[tauri::command]
+fn get_cipher(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => Response::ok().body(json!(session.cipher().name())),
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to change the cipher the current user's secrets are encrypted with.
+/// Every secret is re-encrypted with the new cipher.
+///
+/// # Arguments
+/// * `cipher` - The cipher name, `aes-256-gcm` or `xchacha20-poly1305`.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_cipher(cipher: String, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let cipher = match CipherId::from_name(&cipher) {
+        Ok(cipher) => cipher,
+        Err(e) => return Response::err().body(json!(format!("Error setting cipher: {e:?}"))),
+    };
+
+    let mut sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref mut session) => match session.set_cipher(cipher) {
+            Ok(()) => Response::ok().body(json!(cipher.name())),
+            Err(e) => Response::err().body(json!(format!("Error setting cipher: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to retrieve how the current user's secrets are padded before encryption.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the padding name, or an error.
+#[tauri::command]
+fn get_padding(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => Response::ok().body(json!(session.padding().name())),
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to change how the current user's secrets are padded before encryption.
+/// Every secret is re-encrypted with the new padding.
+///
+/// # Arguments
+/// * `padding` - The padding name, `padme` or `none`.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_padding(padding: String, state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let padding = match PaddingId::from_name(&padding) {
+        Ok(padding) => padding,
+        Err(e) => return Response::err().body(json!(format!("Error setting padding: {e:?}"))),
+    };
+
+    let mut sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref mut session) => match session.set_padding(padding) {
+            Ok(()) => Response::ok().body(json!(padding.name())),
+            Err(e) => Response::err().body(json!(format!("Error setting padding: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to strengthen the Argon2 parameters protecting the current user's master password.
+/// Parameters above `MAX_KDF_PARAMS` or too slow to derive on this machine are refused.
+///
+/// # Arguments
+/// * `passwd` - The user's password.
+/// * `params` - The desired parameters, or `None` to calibrate them on this machine.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the parameters now in use, or an error.
+#[tauri::command]
+fn strengthen_kdf(
+    passwd: String,
+    params: Option<KdfParams>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let params = match params {
+        Some(params) => params,
+        None => match KdfParams::calibrate(KDF_TARGET_DURATION) {
+            Ok(params) => params,
+            Err(e) => return Response::err().body(json!(format!("Error calibrating kdf: {e:?}"))),
+        },
+    };
+
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.strengthen_kdf(passwd, params) {
+            Ok(params) => Response::ok().body(json!(params)),
+            Err(e) => Response::err().body(json!(format!("Error strengthening kdf: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to change the current user's master password.
+///
+/// # Arguments
+/// * `old_passwd` - The user's current password.
+/// * `new_passwd` - The new password.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn change_master_password(
+    old_passwd: String,
+    new_passwd: String,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.change_master_password(old_passwd, new_passwd) {
+            Ok(()) => Response::ok().body(json!("master password changed")),
+            Err(e) => password_error("Error changing master password", e),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Builds the error response of a command that sets a master password. A password that is
+/// too weak is reported with its score and feedback, so the frontend can explain why it was
+/// refused.
+///
+/// # Arguments
+/// * `context` - What the command was doing.
+/// * `e` - The error.
+///
+/// # Returns
+/// A failed `Response`.
+fn password_error(context: &str, e: anyhow::Error) -> Response {
+    match e.downcast_ref::<WeakPasswordError>() {
+        Some(weak) => Response::err().body(json!({
+            "error": weak,
+            "message": weak.to_string(),
+        })),
+        None => Response::err().body(json!(format!("{context}: {e:?}"))),
+    }
+}
+
+/// Command to estimate the strength of a password while the user types it.
+///
+/// # Arguments
+/// * `passwd` - The password to estimate.
+/// * `usrname` - The username, which an attacker would try first.
+///
+/// # Returns
+/// A `Response` with the score, from 0 to 4, and feedback explaining a low score.
+#[tauri::command]
+fn get_password_strength(passwd: String, usrname: String) -> Response {
+    let passwd = Zeroizing::new(passwd);
+    Response::ok().body(json!(estimate_strength(&passwd, &[&usrname])))
+}
+
+/// Command to retrieve the minimum strength score of the current user's new master passwords.
+///
+/// # Arguments
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` with the minimum score, or an error.
+#[tauri::command]
+fn get_min_password_score(state: tauri::State<Arc<Mutex<Option<Session>>>>) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.min_password_score() {
+            Ok(min_score) => Response::ok().body(json!(min_score)),
+            Err(e) => Response::err().body(json!(format!("Error getting minimum score: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to set the minimum strength score of the current user's new master passwords.
+///
+/// # Arguments
+/// * `min_score` - The minimum score, from 0 to 4.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_min_password_score(
+    min_score: u8,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref session) => match session.set_min_password_score(min_score) {
+            Ok(()) => Response::ok().body(json!(min_score)),
+            Err(e) => Response::err().body(json!(format!("Error setting minimum score: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+/// Command to generate a new random key file.
+///
+/// # Returns
+/// A `Response` with the base64 encoded contents of the key file, to be saved by the user.
+#[tauri::command]
+fn new_key_file() -> Response {
+    Response::ok().body(json!(Zeroizing::new(BASE64.encode(&*create_key_file()))))
+}
+
+/// Command to register a key file that is required together with the master password to
+/// log in, or to stop requiring one.
+///
+/// # Arguments
+/// * `passwd` - The user's password.
+/// * `key_file` - The base64 encoded contents of the new key file, or `None` to remove it.
+/// * `state` - A shared state containing the current session.
+///
+/// # Returns
+/// A `Response` indicating success or failure.
+#[tauri::command]
+fn set_key_file(
+    passwd: String,
+    key_file: Option<String>,
+    state: tauri::State<Arc<Mutex<Option<Session>>>>,
+) -> Response {
+    let key_file = match key_file.map(Zeroizing::new) {
+        Some(key_file) => match BASE64.decode(key_file.as_bytes()) {
+            Ok(key_file) => Some(key_file),
+            Err(e) => return Response::err().body(json!(format!("Error setting key file: {e:?}"))),
+        },
+        None => None,
+    };
+
+    let mut sess_guard = state.lock().expect("should get session");
+
+    match *sess_guard {
+        Some(ref mut session) => match session.set_key_file(passwd, key_file) {
+            Ok(()) => Response::ok().body(json!(session.has_key_file())),
+            Err(e) => Response::err().body(json!(format!("Error setting key file: {e:?}"))),
+        },
+        None => Response::err().body(json!("No running session")),
+    }
+}
+
+#[tauri::command]
+fn logout(
+    session: tauri::State<Arc<Mutex<Option<Session>>>>,
+    locked: tauri::State<Arc<Mutex<Option<LockedSession>>>>,
+) -> Response {
+    *locked.lock().unwrap() = None;
     let mut sess_guard = session.lock().unwrap();
     match *sess_guard {
         Some(ref mut sess) => {
@@ -324,6 +1619,8 @@ fn update_last_activity(session: tauri::State<Arc<Mutex<Option<Session>>>>) -> R
 
 fn main() {
     let session_state: Arc<Mutex<Option<Session>>> = Arc::new(Mutex::new(None));
+    // only held in memory, so quick unlock never survives a restart
+    let locked_state: Arc<Mutex<Option<LockedSession>>> = Arc::new(Mutex::new(None));
     let db_path: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
 
     let db_path_clone: Arc<Mutex<String>> = Arc::clone(&db_path);
@@ -345,17 +1642,65 @@ fn main() {
         })
         .manage(db_path)
         .manage(session_state)
+        .manage(locked_state)
         .invoke_handler(tauri::generate_handler![
             get_labels,
             new_secret,
             get_secret,
             edit_secret,
+            new_secret_bytes,
+            get_secret_bytes,
+            edit_secret_bytes,
             delete_secret,
+            get_trash,
+            restore_secret,
+            delete_secret_permanently,
+            get_trash_retention,
+            set_trash_retention,
+            get_folders,
+            create_folder,
+            rename_folder,
+            move_folder,
+            delete_folder,
+            move_secret,
+            get_tags,
+            tag_secret,
+            untag_secret,
+            get_secret_versions,
+            get_secret_version,
+            restore_secret_version,
+            get_history_limit,
+            set_history_limit,
             is_authenticated,
             login,
             logout,
             new_user,
-            update_last_activity
+            update_last_activity,
+            get_kdf_params,
+            get_vault_info,
+            strengthen_kdf,
+            get_cipher,
+            set_cipher,
+            get_padding,
+            set_padding,
+            change_master_password,
+            new_key_file,
+            set_key_file,
+            recover_account,
+            create_recovery_key,
+            remove_recovery_key,
+            get_lockout_threshold,
+            set_lockout_threshold,
+            enroll_totp,
+            confirm_totp,
+            disable_totp,
+            get_totp_enabled,
+            get_password_strength,
+            get_min_password_score,
+            set_min_password_score,
+            set_quick_unlock_pin,
+            is_locked,
+            quick_unlock
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+const RECOVERY_KEY_LEN: usize = 20; // 160 bits of entropy
+const GROUP_LEN: usize = 4; // characters per printed group
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567"; // RFC 4648 base32
+const RECOVERY_KEK_INFO: &[u8] = b"lockkey recovery key"; // HKDF context for the recovery KEK
+
+/// Generates a random recovery key formatted for printing, e.g. `ABCD-EFGH-...`.
+/// The key is base32 encoded so it can be typed back without ambiguity about case.
+pub fn generate_recovery_key() -> Zeroizing<String> {
+    let bytes = Zeroizing::new(rand::thread_rng().gen::<[u8; RECOVERY_KEY_LEN]>());
+    format_recovery_key(&*bytes)
+}
+
+/// Derives the key-encryption key that wraps the vault key from a printed recovery key.
+/// The recovery key is high-entropy, so HKDF is used instead of a password hash.
+///
+/// # Arguments
+/// * `recovery_key` - The recovery key as typed by the user.
+/// * `salt` - The per-user recovery salt.
+///
+/// # Returns
+/// * `Result<Zeroizing<[u8; 32]>>` - The key-encryption key, or an error if the recovery key is malformed.
+pub fn derive_recovery_kek(recovery_key: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let bytes = parse_recovery_key(recovery_key)?;
+    let mut kek = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(salt), &bytes)
+        .expand(RECOVERY_KEK_INFO, &mut *kek)
+        .map_err(|e| anyhow!("failed to expand recovery key: {e:?}"))?;
+    Ok(kek)
+}
+
+/// Encodes recovery key bytes as dash-separated groups of base32 characters.
+fn format_recovery_key(bytes: &[u8]) -> Zeroizing<String> {
+    let chars = encode_base32(bytes);
+
+    let mut formatted =
+        Zeroizing::new(String::with_capacity(chars.len() + chars.len() / GROUP_LEN));
+    for (i, group) in chars.as_bytes().chunks(GROUP_LEN).enumerate() {
+        if i > 0 {
+            formatted.push('-');
+        }
+        formatted.extend(group.iter().map(|&c| c as char));
+    }
+    formatted
+}
+
+/// Encodes bytes as unpadded RFC 4648 base32, as used by recovery keys and TOTP secrets.
+/// Like the other buffers holding key material, the string is allocated at its final size,
+/// since growing it would leave copies of the bytes behind that are never wiped.
+///
+/// # Arguments
+/// * `bytes` - The bytes to encode.
+///
+/// # Returns
+/// * `Zeroizing<String>` - The base32 characters, wiped from memory when dropped.
+pub fn encode_base32(bytes: &[u8]) -> Zeroizing<String> {
+    let mut chars = Zeroizing::new(String::with_capacity(bytes.len() * 8 / 5 + 1));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            chars.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        chars.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    chars
+}
+
+/// Decodes a recovery key typed by the user, ignoring case, dashes and whitespace.
+///
+/// # Arguments
+/// * `recovery_key` - The recovery key as typed by the user.
+///
+/// # Returns
+/// * `Result<Zeroizing<Vec<u8>>>` - The recovery key bytes, or an error if the key is malformed.
+fn parse_recovery_key(recovery_key: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let mut bytes = Zeroizing::new(Vec::with_capacity(RECOVERY_KEY_LEN));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in recovery_key.chars() {
+        if c == '-' || c.is_whitespace() {
+            continue;
+        }
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())
+            .ok_or_else(|| anyhow!("invalid character in recovery key: {c:?}"))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    if bytes.len() != RECOVERY_KEY_LEN {
+        return Err(anyhow!("invalid recovery key length"));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that a recovery key is printed in groups and parses back to the same bytes.
+    #[test]
+    fn can_format_and_parse_recovery_key() {
+        let bytes = [0xa5u8; RECOVERY_KEY_LEN];
+        let formatted = format_recovery_key(&bytes);
+
+        assert_eq!(formatted.len(), 32 + 7);
+        assert!(formatted.split('-').all(|group| group.len() == GROUP_LEN));
+        assert_eq!(*parse_recovery_key(&formatted).unwrap(), bytes);
+        assert_eq!(
+            *parse_recovery_key(&formatted.to_lowercase().replace('-', " ")).unwrap(),
+            bytes
+        );
+    }
+
+    /// Tests base32 encoding against the RFC 4648 test vectors, without padding.
+    #[test]
+    fn can_encode_base32() {
+        for (input, expected) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(*encode_base32(input.as_bytes()), expected);
+        }
+    }
+
+    /// Tests that malformed recovery keys are rejected.
+    #[test]
+    fn should_reject_malformed_recovery_key() {
+        let formatted = format_recovery_key(&[0u8; RECOVERY_KEY_LEN]);
+
+        assert!(parse_recovery_key(&formatted[..10]).is_err());
+        assert!(parse_recovery_key(&formatted.replacen('A', "1", 1)).is_err());
+        assert!(derive_recovery_kek("not a key", b"salt").is_err());
+    }
+}
@@ -0,0 +1,689 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use zeroize::Zeroizing;
+
+pub const MIN_PASSWORD_SCORE: u8 = 3; // default minimum score of a master password
+pub const MAX_PASSWORD_SCORE: u8 = 4; // score of passwords needing at least 10^10 guesses
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt"); // most common first
+const SCORE_THRESHOLDS: [f64; 4] = [1e3, 1e6, 1e8, 1e10]; // guesses needed for scores 1 to 4
+const BRUTEFORCE_CARDINALITY: f64 = 10.0; // guesses per character not matched by a pattern
+const MIN_SINGLE_CHAR_GUESSES: f64 = 10.0; // lowest guesses of a one-character pattern
+const MIN_MULTI_CHAR_GUESSES: f64 = 50.0; // lowest guesses of a longer pattern
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 10_000.0; // cost of every extra pattern
+const MAX_ANALYZED_LEN: usize = 100; // characters analyzed, longer passwords are cut
+const MAX_WORD_LEN: usize = 32; // longest substring looked up in the dictionaries
+const REFERENCE_YEAR: i32 = 2025; // years close to it are guessed first
+const MIN_YEAR_SPACE: i32 = 20; // years guessed even when the year is close to the reference
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+const KEYBOARD_STARTS: f64 = 40.0; // keys a keyboard row can be typed from
+
+/// Estimated strength of a password, modelled after zxcvbn: the password is split into the
+/// patterns an attacker would try first (common passwords, the username, repeats, sequences,
+/// keyboard rows and years) and the number of guesses needed to find it is turned into a
+/// score from 0 to 4. The feedback explains a low score to the user.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PasswordStrength {
+    pub score: u8,
+    pub guesses_log10: f64,
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+/// Error returned when a password is refused because its score is below the minimum. It is
+/// serialized as is for the frontend, which explains why with the feedback.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct WeakPasswordError {
+    pub min_score: u8,
+    pub strength: PasswordStrength,
+}
+
+impl fmt::Display for WeakPasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the password is too weak: its score is {} but at least {} is required",
+            self.strength.score, self.min_score
+        )
+    }
+}
+
+impl std::error::Error for WeakPasswordError {}
+
+/// A pattern found in the password and how it was matched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pattern {
+    Dictionary {
+        rank: usize,
+        user_input: bool,
+        reversed: bool,
+        l33t: bool,
+        capitalized: bool,
+        all_upper: bool,
+    },
+    Repeat {
+        block_len: usize,
+    },
+    Sequence,
+    Keyboard,
+    Year,
+    Bruteforce,
+}
+
+/// A pattern covering the characters `start..end` of the password, with the number of
+/// guesses needed to find it.
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    guesses: f64,
+    pattern: Pattern,
+}
+
+/// Estimates the strength of a password.
+///
+/// # Arguments
+/// * `passwd` - The password to estimate.
+/// * `user_inputs` - Words an attacker would try first for this user, such as the username.
+///
+/// # Returns
+/// * `PasswordStrength` - The score and feedback.
+pub fn estimate_strength(passwd: &str, user_inputs: &[&str]) -> PasswordStrength {
+    let chars = Zeroizing::new(passwd.chars().collect::<Vec<char>>());
+    let analyzed = &chars[..chars.len().min(MAX_ANALYZED_LEN)];
+    let (guesses, sequence) = most_guessable_sequence(analyzed, user_inputs);
+    let guesses_log10 = guesses.log10();
+
+    let score = SCORE_THRESHOLDS
+        .iter()
+        .take_while(|threshold| guesses_log10 >= threshold.log10())
+        .count() as u8;
+    let (warning, suggestions) = match score {
+        MAX_PASSWORD_SCORE => (None, Vec::new()),
+        _ => feedback(&sequence),
+    };
+
+    PasswordStrength {
+        score,
+        guesses_log10,
+        warning,
+        suggestions,
+    }
+}
+
+/// Estimates the strength of a password and refuses it if its score is below the minimum.
+///
+/// # Arguments
+/// * `passwd` - The password to check.
+/// * `user_inputs` - Words an attacker would try first for this user, such as the username.
+/// * `min_score` - The lowest score accepted.
+///
+/// # Returns
+/// * `Result<PasswordStrength, WeakPasswordError>` - The strength of an accepted password,
+///   otherwise an error with the feedback.
+pub fn check_strength(
+    passwd: &str,
+    user_inputs: &[&str],
+    min_score: u8,
+) -> Result<PasswordStrength, WeakPasswordError> {
+    let strength = estimate_strength(passwd, user_inputs);
+    match strength.score < min_score {
+        true => Err(WeakPasswordError {
+            min_score,
+            strength,
+        }),
+        false => Ok(strength),
+    }
+}
+
+/// Finds the sequence of non-overlapping patterns covering the password that needs the
+/// fewest guesses, as zxcvbn does: every extra pattern multiplies the guesses by the number
+/// of orders the patterns can come in, and adds a constant so that a password is not
+/// explained by many tiny patterns.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+/// * `user_inputs` - Words an attacker would try first for this user.
+///
+/// # Returns
+/// * `(f64, Vec<Match>)` - The number of guesses and the patterns, in order.
+fn most_guessable_sequence(chars: &[char], user_inputs: &[&str]) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    if n == 0 {
+        return (1.0, Vec::new());
+    }
+
+    let mut matches = find_matches(chars, user_inputs);
+    for start in 0..n {
+        for end in start + 1..=n {
+            matches.push(Match {
+                start,
+                end,
+                guesses: BRUTEFORCE_CARDINALITY.powi((end - start) as i32),
+                pattern: Pattern::Bruteforce,
+            });
+        }
+    }
+    for m in matches.iter_mut() {
+        let min_guesses = match m.end - m.start {
+            1 => MIN_SINGLE_CHAR_GUESSES,
+            _ => MIN_MULTI_CHAR_GUESSES,
+        };
+        m.guesses = m.guesses.max(min_guesses);
+    }
+
+    // best[end][count] is the lowest product of guesses of `count` patterns covering the
+    // first `end` characters, with the index of the last of them
+    let mut best: Vec<Vec<Option<(f64, usize)>>> = vec![vec![None; n + 1]; n + 1];
+    best[0][0] = Some((1.0, usize::MAX));
+    let mut by_end: Vec<Vec<usize>> = vec![Vec::new(); n + 1];
+    for (i, m) in matches.iter().enumerate() {
+        by_end[m.end].push(i);
+    }
+    for end in 1..=n {
+        for &i in &by_end[end] {
+            let m = &matches[i];
+            for count in 0..n {
+                let Some((product, _)) = best[m.start][count] else {
+                    continue;
+                };
+                let candidate = product * m.guesses;
+                if best[end][count + 1].is_none_or(|(current, _)| candidate < current) {
+                    best[end][count + 1] = Some((candidate, i));
+                }
+            }
+        }
+    }
+
+    let mut lowest = (f64::INFINITY, 0);
+    let mut factorial = 1.0;
+    for (count, entry) in best[n].iter().enumerate().skip(1) {
+        factorial *= count as f64;
+        if let Some((product, _)) = entry {
+            let guesses =
+                factorial * product + MIN_GUESSES_BEFORE_GROWING_SEQUENCE.powi(count as i32 - 1);
+            if guesses < lowest.0 {
+                lowest = (guesses, count);
+            }
+        }
+    }
+
+    let (guesses, mut count) = lowest;
+    let mut sequence = Vec::with_capacity(count);
+    let mut end = n;
+    while count > 0 {
+        let (_, i) = best[end][count].expect("the sequence was built from these entries");
+        sequence.push(matches[i]);
+        end = matches[i].start;
+        count -= 1;
+    }
+    sequence.reverse();
+
+    (guesses, sequence)
+}
+
+/// Finds every pattern in the password.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+/// * `user_inputs` - Words an attacker would try first for this user.
+///
+/// # Returns
+/// * `Vec<Match>` - The patterns found, possibly overlapping.
+fn find_matches(chars: &[char], user_inputs: &[&str]) -> Vec<Match> {
+    let mut matches = dictionary_matches(chars, user_inputs);
+    matches.extend(repeat_matches(chars, user_inputs));
+    matches.extend(sequence_matches(chars));
+    matches.extend(keyboard_matches(chars));
+    matches.extend(year_matches(chars));
+    matches
+}
+
+/// Returns the common passwords list, mapped to their rank.
+fn common_passwords() -> &'static HashMap<&'static str, usize> {
+    static RANKS: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+    RANKS.get_or_init(|| {
+        COMMON_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(i, word)| (word, i + 1))
+            .collect()
+    })
+}
+
+/// Finds the substrings of the password that are common passwords or user inputs, including
+/// reversed and with common l33t substitutions undone.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+/// * `user_inputs` - Words an attacker would try first for this user.
+///
+/// # Returns
+/// * `Vec<Match>` - The dictionary matches.
+fn dictionary_matches(chars: &[char], user_inputs: &[&str]) -> Vec<Match> {
+    let user_ranks: HashMap<String, usize> = user_inputs
+        .iter()
+        .filter(|input| !input.is_empty())
+        .enumerate()
+        .map(|(i, input)| (input.to_lowercase(), i + 1))
+        .collect();
+    let lookup = |word: &str| match user_ranks.get(word) {
+        Some(&rank) => Some((rank, true)),
+        None => common_passwords().get(word).map(|&rank| (rank, false)),
+    };
+
+    let lower = Zeroizing::new(chars.iter().map(|&c| to_lower(c)).collect::<Vec<char>>());
+    let unleeted = Zeroizing::new(lower.iter().map(|&c| unleet(c)).collect::<Vec<char>>());
+    // allocated once at their final size so that no copies of the password are left behind
+    let mut word = Zeroizing::new(String::with_capacity(MAX_WORD_LEN * 4));
+    let mut reversed_word = Zeroizing::new(String::with_capacity(MAX_WORD_LEN * 4));
+
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len().min(start + MAX_WORD_LEN) {
+            let token = &chars[start..end];
+            let (capitalized, all_upper) = capitalization(token);
+            let variants = [(&lower, false), (&unleeted, true)];
+            for (source, l33t) in variants {
+                if l33t && unleeted[start..end] == lower[start..end] {
+                    continue;
+                }
+                word.clear();
+                word.extend(&source[start..end]);
+                reversed_word.clear();
+                reversed_word.extend(source[start..end].iter().rev());
+
+                for (candidate, reversed) in [(&word, false), (&reversed_word, true)] {
+                    if reversed && *reversed_word == *word {
+                        continue;
+                    }
+                    if let Some((rank, user_input)) = lookup(candidate) {
+                        let guesses = rank as f64
+                            * uppercase_variations(token)
+                            * if l33t { 2.0 } else { 1.0 }
+                            * if reversed { 2.0 } else { 1.0 };
+                        matches.push(Match {
+                            start,
+                            end,
+                            guesses,
+                            pattern: Pattern::Dictionary {
+                                rank,
+                                user_input,
+                                reversed,
+                                l33t,
+                                capitalized,
+                                all_upper,
+                            },
+                        });
+                    }
+                }
+            }
+        }
+    }
+    matches
+}
+
+/// Finds runs of a repeated character or block of characters, e.g. `aaa` or `abcabc`, using
+/// the shortest block that repeats. The guesses of a run are those of its block times the
+/// number of repetitions.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+/// * `user_inputs` - Words an attacker would try first for this user.
+///
+/// # Returns
+/// * `Vec<Match>` - The repeat matches.
+fn repeat_matches(chars: &[char], user_inputs: &[&str]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let run = (1..=(chars.len() - start) / 2).find_map(|block_len| {
+            let block = &chars[start..start + block_len];
+            let mut repeats = 1;
+            while chars[start + repeats * block_len..]
+                .get(..block_len)
+                .is_some_and(|next| next == block)
+            {
+                repeats += 1;
+            }
+            (repeats >= 2 && repeats * block_len >= 3).then_some((block_len, repeats))
+        });
+        let Some((block_len, repeats)) = run else {
+            start += 1;
+            continue;
+        };
+
+        let block = &chars[start..start + block_len];
+        let (block_guesses, _) = most_guessable_sequence(block, user_inputs);
+        let end = start + repeats * block_len;
+        matches.push(Match {
+            start,
+            end,
+            guesses: block_guesses * repeats as f64,
+            pattern: Pattern::Repeat { block_len },
+        });
+        start = end;
+    }
+    matches
+}
+
+/// Finds runs of characters whose code points go up or down by one, e.g. `abc` or `6543`.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+///
+/// # Returns
+/// * `Vec<Match>` - The sequence matches.
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let class = char_class(chars[start]);
+        let mut end = start + 1;
+        while end < chars.len()
+            && chars[end] as i64 - chars[end - 1] as i64 == delta
+            && char_class(chars[end]) == class
+        {
+            end += 1;
+        }
+        if delta.abs() == 1 && class.is_some() && end - start >= 3 {
+            let base = match chars[start] {
+                'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9' => 4.0,
+                c if c.is_ascii_digit() => 10.0,
+                _ => 26.0,
+            };
+            let direction = if delta < 0 { 2.0 } else { 1.0 };
+            matches.push(Match {
+                start,
+                end,
+                guesses: base * direction * (end - start) as f64,
+                pattern: Pattern::Sequence,
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+/// Finds runs of adjacent keys on a row of a QWERTY keyboard, e.g. `qwer` or `lkjh`.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+///
+/// # Returns
+/// * `Vec<Match>` - The keyboard matches.
+fn keyboard_matches(chars: &[char]) -> Vec<Match> {
+    let position = |c: char| {
+        KEYBOARD_ROWS.iter().enumerate().find_map(|(row, keys)| {
+            keys.chars()
+                .position(|key| key == to_lower(c))
+                .map(|col| (row, col as i64))
+        })
+    };
+
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        let Some((row, col)) = position(chars[start]) else {
+            continue;
+        };
+        for delta in [-1, 1] {
+            let mut end = start + 1;
+            while end < chars.len()
+                && position(chars[end]) == Some((row, col + delta * (end - start) as i64))
+            {
+                end += 1;
+            }
+            if end - start >= 3 {
+                let direction = if delta < 0 { 2.0 } else { 1.0 };
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses: KEYBOARD_STARTS * direction * (end - start) as f64,
+                    pattern: Pattern::Keyboard,
+                });
+            }
+        }
+    }
+    matches
+}
+
+/// Finds four-digit years between 1900 and 2049; those close to today are guessed first.
+///
+/// # Arguments
+/// * `chars` - The characters of the password.
+///
+/// # Returns
+/// * `Vec<Match>` - The year matches.
+fn year_matches(chars: &[char]) -> Vec<Match> {
+    chars
+        .windows(4)
+        .enumerate()
+        .filter_map(|(start, window)| {
+            let year = window.iter().try_fold(0, |year, c| {
+                c.to_digit(10).map(|digit| year * 10 + digit as i32)
+            })?;
+            (1900..2050).contains(&year).then(|| Match {
+                start,
+                end: start + 4,
+                guesses: (year - REFERENCE_YEAR).abs().max(MIN_YEAR_SPACE) as f64,
+                pattern: Pattern::Year,
+            })
+        })
+        .collect()
+}
+
+/// Counts the ways the letters of a dictionary word could be capitalized to give the token:
+/// a capitalized or all-uppercase word is tried right after the lowercase one.
+///
+/// # Arguments
+/// * `token` - The characters of the word as typed.
+///
+/// # Returns
+/// * `f64` - The number of capitalization variations to try.
+fn uppercase_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    let (capitalized, all_upper) = capitalization(token);
+    if upper == 0 {
+        return 1.0;
+    }
+    if capitalized || all_upper || (upper == 1 && token.last().is_some_and(|c| c.is_uppercase())) {
+        return 2.0;
+    }
+    (1..=upper.min(lower))
+        .map(|k| binomial(upper + lower, k))
+        .sum()
+}
+
+/// Tells whether only the first letter of a token is uppercase, or all of them are.
+fn capitalization(token: &[char]) -> (bool, bool) {
+    let mut letters = token.iter().filter(|c| c.is_alphabetic());
+    let first_upper = letters.next().is_some_and(|c| c.is_uppercase());
+    let rest: Vec<bool> = letters.map(|c| c.is_uppercase()).collect();
+    let capitalized = first_upper && rest.iter().all(|&upper| !upper) && !rest.is_empty();
+    let all_upper = first_upper && rest.iter().all(|&upper| upper);
+    (capitalized, all_upper)
+}
+
+/// Computes the binomial coefficient `n` choose `k`.
+fn binomial(n: usize, k: usize) -> f64 {
+    (1..=k).fold(1.0, |acc, i| acc * (n + 1 - i) as f64 / i as f64)
+}
+
+/// Lowercases a character, keeping it as is if its lowercase form is several characters.
+fn to_lower(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
+    }
+}
+
+/// Undoes the most common l33t substitution of a character.
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '8' => 'b',
+        '(' => 'c',
+        '3' => 'e',
+        '6' | '9' => 'g',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '2' => 'z',
+        _ => c,
+    }
+}
+
+/// Classifies a character for sequences, which never mix classes.
+fn char_class(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' => Some(0),
+        'A'..='Z' => Some(1),
+        '0'..='9' => Some(2),
+        _ => None,
+    }
+}
+
+/// Explains a low score from the patterns the password was split into, starting with the
+/// longest one.
+///
+/// # Arguments
+/// * `sequence` - The patterns of the password.
+///
+/// # Returns
+/// * `(Option<String>, Vec<String>)` - A warning about the weakest pattern, and suggestions.
+fn feedback(sequence: &[Match]) -> (Option<String>, Vec<String>) {
+    let mut suggestions = vec!["Add another word or two. Uncommon words are better.".to_string()];
+    let longest = sequence
+        .iter()
+        .filter(|m| m.pattern != Pattern::Bruteforce)
+        .max_by_key(|m| m.end - m.start);
+    let Some(longest) = longest else {
+        if sequence.is_empty() {
+            return (Some("A password is required.".to_string()), suggestions);
+        }
+        return (None, suggestions);
+    };
+
+    let warning = match longest.pattern {
+        Pattern::Dictionary {
+            rank,
+            user_input,
+            reversed,
+            l33t,
+            capitalized,
+            all_upper,
+        } => {
+            if capitalized {
+                suggestions.push("Capitalization doesn't help very much.".to_string());
+            }
+            if all_upper {
+                suggestions
+                    .push("All-uppercase is almost as easy to guess as all-lowercase.".to_string());
+            }
+            if reversed {
+                suggestions.push("Reversed words aren't much harder to guess.".to_string());
+            }
+            if l33t {
+                suggestions.push(
+                    "Predictable substitutions like '@' instead of 'a' don't help very much."
+                        .to_string(),
+                );
+            }
+            match (user_input, rank) {
+                (true, _) => "Avoid using your username in the password.",
+                (false, ..=10) => "This is a top-10 common password.",
+                (false, ..=100) => "This is a top-100 common password.",
+                (false, _) => "This is similar to a commonly used password.",
+            }
+        }
+        Pattern::Repeat { block_len } => {
+            suggestions.push("Avoid repeated words and characters.".to_string());
+            match block_len {
+                1 => "Repeats like \"aaa\" are easy to guess.",
+                _ => "Repeats like \"abcabcabc\" are only slightly harder to guess than \"abc\".",
+            }
+        }
+        Pattern::Sequence => {
+            suggestions.push("Avoid sequences.".to_string());
+            "Sequences like \"abc\" or \"6543\" are easy to guess."
+        }
+        Pattern::Keyboard => {
+            suggestions.push("Use a longer keyboard pattern with more turns.".to_string());
+            "Straight rows of keys are easy to guess."
+        }
+        Pattern::Year => {
+            suggestions.push("Avoid recent years and years associated with you.".to_string());
+            "Recent years are easy to guess."
+        }
+        Pattern::Bruteforce => unreachable!("brute force matches were filtered out"),
+    };
+
+    (Some(warning.to_string()), suggestions)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that common passwords and simple patterns score low, with a warning explaining
+    /// why, while long uncommon passwords score high.
+    #[test]
+    fn should_score_weak_and_strong_passwords() {
+        for (passwd, max_score) in [
+            ("", 0),
+            ("password", 0),
+            ("P@ssw0rd", 1),
+            ("drowssap", 1),
+            ("aaaaaaaaaaaa", 1),
+            ("abcdefghijkl", 1),
+            ("qwertyuiop", 0),
+            ("1987", 0),
+            ("jdoe2024", 1),
+        ] {
+            let strength = estimate_strength(passwd, &["jdoe"]);
+            assert!(
+                strength.score <= max_score,
+                "{passwd:?} scored {}",
+                strength.score
+            );
+            assert!(strength.warning.is_some(), "{passwd:?} has no warning");
+        }
+
+        for passwd in [
+            "plum-orbit-cactus-19",
+            "Wq8#vL2!rT9z",
+            "fjord lantern mosaic",
+        ] {
+            let strength = estimate_strength(passwd, &[]);
+            assert_eq!(strength.score, MAX_PASSWORD_SCORE, "{passwd:?}");
+            assert_eq!(strength.warning, None);
+            assert!(strength.suggestions.is_empty());
+        }
+    }
+
+    /// Tests that the username is guessed first, and that the minimum score is enforced.
+    #[test]
+    fn should_refuse_passwords_below_min_score() {
+        let strength = estimate_strength("Margaret", &["margaret"]);
+        assert_eq!(
+            strength.warning.as_deref(),
+            Some("Avoid using your username in the password.")
+        );
+        assert!(strength.guesses_log10 < estimate_strength("Margaret", &[]).guesses_log10);
+
+        let err = check_strength("monkey1", &[], MIN_PASSWORD_SCORE).unwrap_err();
+        assert_eq!(err.min_score, MIN_PASSWORD_SCORE);
+        assert!(err.strength.score < MIN_PASSWORD_SCORE);
+        assert!(check_strength("monkey1", &[], 0).is_ok());
+        assert!(check_strength("plum-orbit-cactus-19", &[], MAX_PASSWORD_SCORE).is_ok());
+    }
+}
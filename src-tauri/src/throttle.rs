@@ -0,0 +1,155 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::data::LoginAttempts;
+
+const FREE_ATTEMPTS: u32 = 3; // failed logins allowed before attempts are delayed
+const BASE_DELAY_SECS: u64 = 1; // delay after the first delayed failure, doubled after each
+const MAX_DELAY_SECS: u64 = 15 * 60; // upper bound of the delay between attempts
+pub const MIN_LOCKOUT_THRESHOLD: u32 = 5; // lowest number of failures a lockout may be set to
+
+/// Error returned when a login is refused for a reason the frontend acts on: wrong
+/// credentials, earlier failed attempts, or a missing two-factor code. It is serialized as is
+/// for the frontend.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LoginError {
+    /// The username, password or key file is wrong. Which one is not told, and unknown
+    /// usernames cost as much work as wrong passwords, so that usernames cannot be probed.
+    InvalidCredentials,
+    /// Too many attempts failed recently; the next one is allowed at `retry_at`, in seconds
+    /// since the Unix epoch. Unknown usernames are throttled the same way as known ones.
+    Throttled { retry_at: u64 },
+    /// The lockout threshold of the user was reached; only the recovery key can unlock the
+    /// vault. It is told whatever the password, so that it does not confirm a right guess.
+    LockedOut,
+    /// The password is correct but the user enrolled in two-factor authentication and no code
+    /// was given.
+    TotpRequired,
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::InvalidCredentials => {
+                write!(f, "invalid username, password or key file")
+            }
+            LoginError::Throttled { retry_at } => write!(
+                f,
+                "too many failed login attempts, try again in {} seconds",
+                retry_at.saturating_sub(unix_now())
+            ),
+            LoginError::LockedOut => write!(
+                f,
+                "too many failed login attempts, use the recovery key to unlock the vault"
+            ),
+            LoginError::TotpRequired => write!(f, "a two-factor authentication code is required"),
+        }
+    }
+}
+
+impl std::error::Error for LoginError {}
+
+/// Checks whether a user may attempt to log in given their failed attempts. The first few
+/// failures are free, after which the delay before the next attempt doubles with every
+/// failure up to a maximum.
+///
+/// # Arguments
+/// * `attempts` - The failed login attempts of the user.
+/// * `now` - The current time, in seconds since the Unix epoch.
+///
+/// # Returns
+/// * `Result<(), LoginError>` - An empty result if an attempt is allowed, otherwise why not.
+pub fn check_login_allowed(attempts: &LoginAttempts, now: u64) -> Result<(), LoginError> {
+    let retry_at = attempts.last_failed_at + backoff_delay(attempts.failed);
+    match now < retry_at {
+        true => Err(LoginError::Throttled { retry_at }),
+        false => Ok(()),
+    }
+}
+
+/// Checks whether a user that set a lockout threshold has reached it.
+///
+/// # Arguments
+/// * `attempts` - The failed login attempts of the user.
+///
+/// # Returns
+/// * `Result<(), LoginError>` - An empty result if the user is not locked out, otherwise
+///   `LoginError::LockedOut`.
+pub fn check_not_locked_out(attempts: &LoginAttempts) -> Result<(), LoginError> {
+    match attempts
+        .lockout_after
+        .is_some_and(|lockout_after| attempts.failed >= lockout_after)
+    {
+        true => Err(LoginError::LockedOut),
+        false => Ok(()),
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Computes how long to wait after the last of the given number of failed attempts.
+///
+/// # Arguments
+/// * `failed` - The number of failed attempts.
+///
+/// # Returns
+/// * `u64` - The delay in seconds.
+fn backoff_delay(failed: u32) -> u64 {
+    match failed.checked_sub(FREE_ATTEMPTS) {
+        None => 0,
+        Some(doublings) => BASE_DELAY_SECS
+            .checked_shl(doublings)
+            .unwrap_or(MAX_DELAY_SECS)
+            .min(MAX_DELAY_SECS),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that the delay doubles after the free attempts and is capped.
+    #[test]
+    fn backoff_delay_doubles_up_to_max() {
+        assert_eq!(backoff_delay(0), 0);
+        assert_eq!(backoff_delay(FREE_ATTEMPTS - 1), 0);
+        assert_eq!(backoff_delay(FREE_ATTEMPTS), 1);
+        assert_eq!(backoff_delay(FREE_ATTEMPTS + 1), 2);
+        assert_eq!(backoff_delay(FREE_ATTEMPTS + 4), 16);
+        assert_eq!(backoff_delay(FREE_ATTEMPTS + 20), MAX_DELAY_SECS);
+        assert_eq!(backoff_delay(u32::MAX), MAX_DELAY_SECS);
+    }
+
+    /// Tests that attempts are refused until the delay has passed, and always once the
+    /// lockout threshold is reached.
+    #[test]
+    fn should_throttle_and_lock_out_failed_logins() {
+        let mut attempts = LoginAttempts {
+            failed: FREE_ATTEMPTS - 1,
+            last_failed_at: 1000,
+            lockout_after: None,
+        };
+        assert_eq!(check_login_allowed(&attempts, 1000), Ok(()));
+
+        attempts.failed = FREE_ATTEMPTS + 2;
+        assert_eq!(
+            check_login_allowed(&attempts, 1001),
+            Err(LoginError::Throttled { retry_at: 1004 })
+        );
+        assert_eq!(check_login_allowed(&attempts, 1004), Ok(()));
+        assert_eq!(check_not_locked_out(&attempts), Ok(()));
+
+        attempts.lockout_after = Some(FREE_ATTEMPTS + 3);
+        assert_eq!(check_not_locked_out(&attempts), Ok(()));
+        attempts.lockout_after = Some(FREE_ATTEMPTS + 2);
+        assert_eq!(check_not_locked_out(&attempts), Err(LoginError::LockedOut));
+        assert_eq!(check_login_allowed(&attempts, 1004), Ok(()));
+    }
+}
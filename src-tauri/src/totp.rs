@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use qrcode::{render::svg, QrCode};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::recovery::encode_base32;
+
+const SECRET_LEN: usize = 20; // 160-bit seed, as recommended by RFC 4226
+const DIGITS: u32 = 6; // digits of a code
+const PERIOD_SECS: u64 = 30; // lifetime of a code
+const SKEW_STEPS: u64 = 1; // steps accepted on either side of the current one, for clock drift
+const BACKUP_CODE_COUNT: usize = 10; // single-use backup codes generated on enrolment
+const BACKUP_CODE_LEN: usize = 5; // bytes of randomness, 8 base32 characters
+const ISSUER: &str = "Lockkey"; // issuer shown by authenticator apps
+
+/// The two-factor state of a user: the shared secret, the hashes of the backup codes that
+/// are left, and the last time step accepted so that a code cannot be replayed. It is
+/// stored encrypted with the vault key.
+#[derive(Serialize, Deserialize)]
+pub struct TotpState {
+    secret: Zeroizing<Vec<u8>>,
+    backup_codes: Vec<[u8; 32]>,
+    last_step: u64,
+}
+
+/// What the user is shown once when enrolling: the secret as an `otpauth://` URI and as a
+/// QR code to scan, and the backup codes to write down.
+#[derive(Serialize)]
+pub struct TotpEnrollment {
+    pub uri: Zeroizing<String>,
+    pub qr_code: Zeroizing<String>,
+    pub backup_codes: Zeroizing<Vec<String>>,
+}
+
+impl TotpState {
+    /// Generates a new random secret and backup codes for a user.
+    ///
+    /// # Arguments
+    /// * `username` - The username, shown by authenticator apps.
+    ///
+    /// # Returns
+    /// * `Result<(TotpState, TotpEnrollment)>` - The state to store once the user confirmed
+    ///   a code, and what to show them.
+    pub fn generate(username: &str) -> Result<(TotpState, TotpEnrollment)> {
+        let mut rng = rand::thread_rng();
+        let secret = Zeroizing::new(rng.gen::<[u8; SECRET_LEN]>().to_vec());
+
+        let mut backup_codes = Zeroizing::new(Vec::with_capacity(BACKUP_CODE_COUNT));
+        for _ in 0..BACKUP_CODE_COUNT {
+            let bytes = Zeroizing::new(rng.gen::<[u8; BACKUP_CODE_LEN]>());
+            let chars = encode_base32(&*bytes);
+            backup_codes.push(format!("{}-{}", &chars[..4], &chars[4..]));
+        }
+
+        let uri = otpauth_uri(username, &secret);
+        let qr_code = QrCode::new(uri.as_bytes())
+            .map_err(|e| anyhow!("failed to render the QR code: {e}"))?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+
+        let state = TotpState {
+            secret,
+            backup_codes: backup_codes
+                .iter()
+                .map(|code| hash_backup_code(code))
+                .collect(),
+            last_step: 0,
+        };
+        let enrollment = TotpEnrollment {
+            uri,
+            qr_code: Zeroizing::new(qr_code),
+            backup_codes,
+        };
+        Ok((state, enrollment))
+    }
+
+    /// Checks a code typed by the user, either from their authenticator app or one of the
+    /// backup codes. An accepted code is consumed: the state must be saved afterwards.
+    ///
+    /// # Arguments
+    /// * `code` - The code typed by the user.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the code was accepted.
+    pub fn verify(&mut self, code: &str, now: u64) -> bool {
+        let code: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect();
+
+        if code.len() == DIGITS as usize && code.bytes().all(|c| c.is_ascii_digit()) {
+            let current = now / PERIOD_SECS;
+            let first = current.saturating_sub(SKEW_STEPS).max(self.last_step + 1);
+            for step in first..=current + SKEW_STEPS {
+                if format_code(hotp(&self.secret, step, DIGITS)) == code {
+                    self.last_step = step;
+                    return true;
+                }
+            }
+            return false;
+        }
+
+        let hash = hash_backup_code(&code.to_ascii_uppercase());
+        match self.backup_codes.iter().position(|stored| *stored == hash) {
+            Some(index) => {
+                self.backup_codes.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Computes the code an authenticator app would show at the given time.
+    #[cfg(test)]
+    pub fn code_at(&self, now: u64) -> String {
+        format_code(hotp(&self.secret, now / PERIOD_SECS, DIGITS))
+    }
+}
+
+/// Builds the `otpauth://` URI understood by authenticator apps.
+///
+/// # Arguments
+/// * `username` - The username, used as the account name.
+/// * `secret` - The shared secret.
+///
+/// # Returns
+/// * `Zeroizing<String>` - The URI, wiped from memory when dropped.
+fn otpauth_uri(username: &str, secret: &[u8]) -> Zeroizing<String> {
+    let encoded = encode_base32(secret);
+    Zeroizing::new(format!(
+        "otpauth://totp/{ISSUER}:{}?secret={}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECS}",
+        percent_encode(username),
+        *encoded
+    ))
+}
+
+/// Percent-encodes everything but unreserved URI characters.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Computes an HOTP value as defined by RFC 4226, with HMAC-SHA1 and dynamic truncation.
+///
+/// # Arguments
+/// * `secret` - The shared secret.
+/// * `counter` - The counter, here the TOTP time step.
+/// * `digits` - The number of digits to keep.
+///
+/// # Returns
+/// * `u32` - The code.
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac =
+        <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(digits)
+}
+
+/// Formats a code with its leading zeros.
+fn format_code(code: u32) -> String {
+    format!("{code:0width$}", width = DIGITS as usize)
+}
+
+/// Hashes a normalized backup code; the codes are random so a plain hash is enough.
+fn hash_backup_code(code: &str) -> [u8; 32] {
+    let normalized: String = code.chars().filter(|&c| c != '-').collect();
+    Sha256::digest(normalized.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    /// Tests HOTP against the SHA-1 test vectors of RFC 6238.
+    #[test]
+    fn should_match_rfc_6238_vectors() {
+        for (time, expected) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ] {
+            assert_eq!(hotp(RFC_SECRET, time / PERIOD_SECS, 8), expected);
+        }
+        assert_eq!(
+            format_code(hotp(RFC_SECRET, 1111111109 / PERIOD_SECS, DIGITS)),
+            "081804"
+        );
+    }
+
+    /// Tests that codes are accepted within the allowed clock drift, only once, and that
+    /// backup codes are single-use.
+    #[test]
+    fn can_verify_codes_and_backup_codes() {
+        let (mut state, enrollment) = TotpState::generate("jane doe").unwrap();
+        assert!(enrollment
+            .uri
+            .starts_with("otpauth://totp/Lockkey:jane%20doe?secret="));
+        assert!(enrollment.qr_code.contains("<svg"));
+        assert_eq!(enrollment.backup_codes.len(), BACKUP_CODE_COUNT);
+
+        let now = 1_700_000_000;
+        let code = state.code_at(now);
+        assert!(!state.verify(&code, now + 3 * PERIOD_SECS));
+        assert!(state.verify(&code, now + PERIOD_SECS));
+        assert!(!state.verify(&code, now));
+        assert!(!state.verify(&state.code_at(now - PERIOD_SECS), now));
+        assert!(state.verify(&state.code_at(now + 2 * PERIOD_SECS), now + 2 * PERIOD_SECS));
+
+        let backup_code = enrollment.backup_codes[0].to_ascii_lowercase();
+        assert!(state.verify(&backup_code, now));
+        assert!(!state.verify(&backup_code, now));
+        assert_eq!(state.backup_codes.len(), BACKUP_CODE_COUNT - 1);
+        assert!(!state.verify("not a code", now));
+    }
+}
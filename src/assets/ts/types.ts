@@ -3,6 +3,11 @@ export interface Response<T> {
   body?: T;
 }
 
+export interface ErrorBody {
+  error: unknown;
+  message: string;
+}
+
 export interface Secret {
   label: string;
   kind: string;
@@ -1,6 +1,7 @@
 import { goto } from "$app/navigation";
 import { invoke } from "@tauri-apps/api/tauri";
 import { userPrefs } from "./userPrefs";
+import type { ErrorBody } from "./types";
 
 export function adjustTextAreaHeight(textArea: HTMLTextAreaElement) {
   if (textArea === null) return;
@@ -15,3 +16,7 @@ export async function logOut() {
     userPrefs.reset();
   }
 }
+
+export function errorMessage(body?: string | ErrorBody): string | undefined {
+  return typeof body === "object" ? body.message : body;
+}